@@ -2,6 +2,11 @@
 
 use std::ffi::{CString, c_char};
 
+/// An error returned by the library
+///
+/// `code` is the code of the matching `IdeviceError` variant. Error strings from the device that
+/// idevice doesn't recognize are reported as -70 (device error). Older versions reported them
+/// as -59 (unknown error type), so match both when supporting older versions.
 #[repr(C)]
 #[derive(Debug)]
 pub struct IdeviceFfiError {
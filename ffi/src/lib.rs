@@ -275,6 +275,39 @@ pub unsafe extern "C" fn idevice_start_session(
     }
 }
 
+/// Sets the read and write timeouts of a connection
+///
+/// # Arguments
+/// * [`idevice`] - The Idevice handle
+/// * [`read_timeout_ms`] - How long a read may wait for data, or 0 to wait forever
+/// * [`write_timeout_ms`] - How long a write may wait for the device, or 0 to wait forever
+///
+/// # Returns
+/// An IdeviceFfiError on error, null on success
+///
+/// # Safety
+/// `idevice` must be a valid, non-null pointer to an Idevice handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idevice_set_timeouts(
+    idevice: *mut IdeviceHandle,
+    read_timeout_ms: u64,
+    write_timeout_ms: u64,
+) -> *mut IdeviceFfiError {
+    if idevice.is_null() {
+        return ffi_err!(IdeviceError::FfiInvalidArg);
+    }
+
+    let dev = unsafe { &mut (*idevice).0 };
+    let to_duration = |ms| match ms {
+        0 => None,
+        ms => Some(std::time::Duration::from_millis(ms)),
+    };
+    dev.set_read_timeout(to_duration(read_timeout_ms));
+    dev.set_write_timeout(to_duration(write_timeout_ms));
+
+    null_mut()
+}
+
 /// Frees an Idevice handle
 ///
 /// # Arguments
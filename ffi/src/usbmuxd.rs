@@ -294,6 +294,86 @@ pub unsafe extern "C" fn idevice_usbmuxd_get_pair_record(
     }
 }
 
+/// Saves a pairing record to usbmuxd.
+///
+/// # Arguments
+/// * `usbmuxd_conn` - A valid connection to usbmuxd.
+/// * `device_id` - The usbmuxd device ID of the device.
+/// * `udid` - The UDID of the device.
+/// * `pair_record` - The pairing file to save. Ownership is not taken.
+///
+/// # Returns
+/// An `IdeviceFfiError` on error, `null` on success.
+///
+/// # Safety
+/// * `usbmuxd_conn` must be a valid pointer.
+/// * `udid` must be a valid, null-terminated C string.
+/// * `pair_record` must be a valid pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idevice_usbmuxd_save_pair_record(
+    usbmuxd_conn: *mut UsbmuxdConnectionHandle,
+    device_id: u32,
+    udid: *const c_char,
+    pair_record: *const IdevicePairingFile,
+) -> *mut IdeviceFfiError {
+    if usbmuxd_conn.is_null() || udid.is_null() || pair_record.is_null() {
+        return ffi_err!(IdeviceError::FfiInvalidArg);
+    }
+    let conn = unsafe { &mut (*usbmuxd_conn).0 };
+    let pf = unsafe { &(*pair_record).0 };
+
+    let udid_str = unsafe {
+        match CStr::from_ptr(udid).to_str() {
+            Ok(s) => s,
+            Err(_) => return ffi_err!(IdeviceError::FfiInvalidArg),
+        }
+    };
+
+    let res = RUNTIME.block_on(async { conn.save_pair_record(device_id, udid_str, pf).await });
+
+    match res {
+        Ok(_) => null_mut(),
+        Err(e) => ffi_err!(e),
+    }
+}
+
+/// Deletes a pairing record from usbmuxd.
+///
+/// # Arguments
+/// * `usbmuxd_conn` - A valid connection to usbmuxd.
+/// * `udid` - The UDID of the device.
+///
+/// # Returns
+/// An `IdeviceFfiError` on error, `null` on success.
+///
+/// # Safety
+/// * `usbmuxd_conn` must be a valid pointer.
+/// * `udid` must be a valid, null-terminated C string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idevice_usbmuxd_delete_pair_record(
+    usbmuxd_conn: *mut UsbmuxdConnectionHandle,
+    udid: *const c_char,
+) -> *mut IdeviceFfiError {
+    if usbmuxd_conn.is_null() || udid.is_null() {
+        return ffi_err!(IdeviceError::FfiInvalidArg);
+    }
+    let conn = unsafe { &mut (*usbmuxd_conn).0 };
+
+    let udid_str = unsafe {
+        match CStr::from_ptr(udid).to_str() {
+            Ok(s) => s,
+            Err(_) => return ffi_err!(IdeviceError::FfiInvalidArg),
+        }
+    };
+
+    let res = RUNTIME.block_on(async { conn.delete_pair_record(udid_str).await });
+
+    match res {
+        Ok(_) => null_mut(),
+        Err(e) => ffi_err!(e),
+    }
+}
+
 /// Reads the BUID (Boot-Unique ID) from usbmuxd.
 ///
 /// The returned string must be freed with `idevice_string_free`.
@@ -532,3 +612,94 @@ pub unsafe extern "C" fn idevice_usbmuxd_device_get_connection_type(
     };
     ct as u8
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use idevice::usbmuxd::server::UsbmuxdServer;
+
+    use super::*;
+    use crate::{
+        errors::idevice_error_free,
+        pairing_file::{idevice_pairing_file_free, idevice_pairing_file_from_bytes},
+    };
+
+    fn check(err: *mut IdeviceFfiError) -> Result<(), i32> {
+        if err.is_null() {
+            return Ok(());
+        }
+        let code = unsafe { (*err).code };
+        unsafe { idevice_error_free(err) };
+        Err(code)
+    }
+
+    #[test]
+    fn pair_records() {
+        let path = std::env::temp_dir().join(format!("idevice-ffi-usbmuxd-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = UsbmuxdAddr::UnixSocket(path.to_string_lossy().to_string());
+        let server = UsbmuxdServer::new("TEST-BUID");
+        let serving = RUNTIME.spawn(async move { server.serve(&addr).await });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !path.exists() {
+            assert!(!serving.is_finished(), "the server failed to start");
+            assert!(Instant::now() < deadline, "the server didn't start");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut record = plist::Dictionary::new();
+        for field in [
+            "DeviceCertificate",
+            "HostCertificate",
+            "HostPrivateKey",
+            "RootCertificate",
+            "RootPrivateKey",
+        ] {
+            record.insert(field.into(), plist::Value::Data(vec![1, 2, 3]));
+        }
+        for field in ["SystemBUID", "HostID", "WiFiMACAddress"] {
+            record.insert(field.into(), "00:11:22:33:44:55".into());
+        }
+        let mut bytes = Vec::new();
+        plist::to_writer_xml(&mut bytes, &record).unwrap();
+
+        let socket = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        let udid = CString::new("udid-usb").unwrap();
+        unsafe {
+            let mut pf = null_mut();
+            check(idevice_pairing_file_from_bytes(
+                bytes.as_ptr(),
+                bytes.len(),
+                &mut pf,
+            ))
+            .unwrap();
+            let mut conn = null_mut();
+            check(idevice_usbmuxd_new_unix_socket_connection(
+                socket.as_ptr(),
+                1,
+                &mut conn,
+            ))
+            .unwrap();
+
+            check(idevice_usbmuxd_save_pair_record(conn, 1, udid.as_ptr(), pf)).unwrap();
+            let mut saved = null_mut();
+            check(idevice_usbmuxd_get_pair_record(
+                conn,
+                udid.as_ptr(),
+                &mut saved,
+            ))
+            .unwrap();
+            assert_eq!((*saved).0.host_id, "00:11:22:33:44:55");
+            idevice_pairing_file_free(saved);
+
+            check(idevice_usbmuxd_delete_pair_record(conn, udid.as_ptr())).unwrap();
+            assert!(check(idevice_usbmuxd_delete_pair_record(conn, udid.as_ptr())).is_err());
+            assert!(check(idevice_usbmuxd_save_pair_record(conn, 1, null_mut(), pf)).is_err());
+
+            idevice_usbmuxd_connection_free(conn);
+            idevice_pairing_file_free(pf);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
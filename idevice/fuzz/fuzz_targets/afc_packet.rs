@@ -0,0 +1,11 @@
+#![no_main]
+
+use idevice::{afc::packet::AfcPacketDecoder, codec::Decoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = AfcPacketDecoder::new();
+    decoder.push(data);
+    while let Ok(Some(_)) = decoder.decode() {}
+    let _ = decoder.bytes_needed();
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::core_device_proxy::CDTunnelPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = CDTunnelPacket::parse(data);
+});
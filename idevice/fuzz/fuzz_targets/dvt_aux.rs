@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::dvt::message::Aux;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Aux::from_bytes(data.to_vec());
+});
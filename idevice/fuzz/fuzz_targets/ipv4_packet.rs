@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::tcp::packets::Ipv4Packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Ipv4Packet::parse(data);
+});
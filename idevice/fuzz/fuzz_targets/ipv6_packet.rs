@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::tcp::packets::Ipv6Packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Ipv6Packet::parse(data);
+});
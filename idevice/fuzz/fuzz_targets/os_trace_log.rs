@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::os_trace_relay::OsTraceLog;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = OsTraceLog::parse(data);
+});
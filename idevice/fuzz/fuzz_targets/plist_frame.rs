@@ -0,0 +1,11 @@
+#![no_main]
+
+use idevice::codec::{Decoder, PlistDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = PlistDecoder::new();
+    decoder.push(data);
+    while let Ok(Some(_)) = decoder.decode() {}
+    let _ = decoder.bytes_needed();
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use idevice::tcp::packets::TcpPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TcpPacket::parse(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use idevice::{codec::Decoder, xpc::XPCMessageDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = XPCMessageDecoder::new();
+    decoder.push(data);
+    while let Ok(Some(_)) = decoder.decode() {}
+    let _ = decoder.bytes_needed();
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use idevice::xpc::XPCObject;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Fuzz the body; the magic and version are fixed
+    let mut buf = 0x42133742_u32.to_le_bytes().to_vec();
+    buf.extend_from_slice(&5_u32.to_le_bytes());
+    buf.extend_from_slice(data);
+    if let Ok(o) = XPCObject::decode(&buf) {
+        let _ = o.encode();
+    }
+});
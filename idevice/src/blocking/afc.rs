@@ -0,0 +1,240 @@
+// Jackson Coxson
+// Blocking wrapper around the AFC client
+
+use std::{future::Future, io::SeekFrom};
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    afc::{
+        self,
+        opcode::{AfcFopenMode, AfcLockOp, LinkType},
+        DeviceInfo, FileInfo,
+    },
+    provider::IdeviceProvider,
+    IdeviceError,
+};
+
+/// Blocking client for the Apple File Conduit service
+///
+/// See [`crate::afc::AfcClient`] for the details of each request.
+pub struct AfcClient {
+    rt: Runtime,
+    inner: afc::AfcClient,
+}
+
+/// Blocking handle for an open file on the device
+///
+/// Call close before dropping
+pub struct FileDescriptor<'a> {
+    rt: &'a Runtime,
+    inner: afc::file::FileDescriptor<'a>,
+}
+
+impl AfcClient {
+    /// Establishes a connection to AFC
+    ///
+    /// # Arguments
+    /// * `provider` - Device connection provider
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the runtime can't be created or the connection fails
+    pub fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        let (rt, inner) = super::connect(provider)?;
+        Ok(Self { rt, inner })
+    }
+
+    /// Runs an operation on the async client that has no blocking counterpart
+    ///
+    /// # Arguments
+    /// * `f` - Builds the future to block on from the async client
+    pub fn block_on<'a, F: Future>(
+        &'a mut self,
+        f: impl FnOnce(&'a mut afc::AfcClient) -> F,
+    ) -> F::Output {
+        self.rt.block_on(f(&mut self.inner))
+    }
+
+    /// Lists the contents of a directory on the device
+    ///
+    /// # Arguments
+    /// * `path` - Path to the directory to list
+    pub fn list_dir(&mut self, path: impl Into<String>) -> Result<Vec<String>, IdeviceError> {
+        self.rt.block_on(self.inner.list_dir(path))
+    }
+
+    /// Creates a directory on the device
+    ///
+    /// # Arguments
+    /// * `path` - Path of the directory to create
+    pub fn mk_dir(&mut self, path: impl Into<String>) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.mk_dir(path))
+    }
+
+    /// Retrieves information about a file or directory
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file or directory
+    pub fn get_file_info(&mut self, path: impl Into<String>) -> Result<FileInfo, IdeviceError> {
+        self.rt.block_on(self.inner.get_file_info(path))
+    }
+
+    /// Retrieves information about the device's filesystem
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo, IdeviceError> {
+        self.rt.block_on(self.inner.get_device_info())
+    }
+
+    /// Removes a file or empty directory
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file or directory
+    pub fn remove(&mut self, path: impl Into<String>) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.remove(path))
+    }
+
+    /// Recursively removes a directory and its contents
+    ///
+    /// # Arguments
+    /// * `path` - Path to the directory
+    pub fn remove_all(&mut self, path: impl Into<String>) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.remove_all(path))
+    }
+
+    /// Opens a file on the device
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file to open
+    /// * `mode` - Opening mode (read, write, etc.)
+    pub fn open(
+        &mut self,
+        path: impl Into<String>,
+        mode: AfcFopenMode,
+    ) -> Result<FileDescriptor<'_>, IdeviceError> {
+        let inner = self.rt.block_on(self.inner.open(path, mode))?;
+        Ok(FileDescriptor {
+            rt: &self.rt,
+            inner,
+        })
+    }
+
+    /// Wraps a descriptor returned by [`FileDescriptor::into_raw`] in a handle
+    ///
+    /// # Arguments
+    /// * `fd` - The device's descriptor for the file
+    /// * `path` - Path the file was opened with
+    pub fn file_from_raw(&mut self, fd: u64, path: impl Into<String>) -> FileDescriptor<'_> {
+        FileDescriptor {
+            rt: &self.rt,
+            inner: self.inner.file_from_raw(fd, path),
+        }
+    }
+
+    /// Creates a hard or symbolic link
+    ///
+    /// # Arguments
+    /// * `target` - Target path of the link
+    /// * `source` - Path where the link should be created
+    /// * `kind` - Type of link to create (hard or symbolic)
+    pub fn link(
+        &mut self,
+        target: impl Into<String>,
+        source: impl Into<String>,
+        kind: LinkType,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.link(target, source, kind))
+    }
+
+    /// Renames a file or directory
+    ///
+    /// # Arguments
+    /// * `source` - Current path of the file/directory
+    /// * `target` - New path for the file/directory
+    pub fn rename(
+        &mut self,
+        source: impl Into<String>,
+        target: impl Into<String>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.rename(source, target))
+    }
+
+    /// Truncates or extends a file without opening it
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file
+    /// * `size` - New size of the file in bytes
+    pub fn truncate(&mut self, path: impl Into<String>, size: u64) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.truncate(path, size))
+    }
+
+    /// Sets the modification time of a file or directory
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file or directory
+    /// * `mtime` - New modification time, in UTC
+    pub fn set_mtime(
+        &mut self,
+        path: impl Into<String>,
+        mtime: chrono::NaiveDateTime,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.set_mtime(path, mtime))
+    }
+}
+
+impl FileDescriptor<'_> {
+    /// Closes the file descriptor
+    pub fn close(self) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.close())
+    }
+
+    /// Gives up the handle without closing the file, returning its descriptor
+    pub fn into_raw(self) -> Result<u64, IdeviceError> {
+        self.rt.block_on(self.inner.into_raw())
+    }
+
+    /// Reads the entire contents of the file
+    pub fn read(&mut self) -> Result<Vec<u8>, IdeviceError> {
+        self.rt.block_on(self.inner.read())
+    }
+
+    /// Writes data to the file
+    ///
+    /// # Arguments
+    /// * `bytes` - Data to write to the file
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.write(bytes))
+    }
+
+    /// Moves the position in the file, returning the new one
+    ///
+    /// # Arguments
+    /// * `position` - Where to move to
+    pub fn seek(&mut self, position: SeekFrom) -> Result<u64, IdeviceError> {
+        self.rt.block_on(self.inner.seek(position))
+    }
+
+    /// Returns the position in the file
+    pub fn tell(&mut self) -> Result<u64, IdeviceError> {
+        self.rt.block_on(self.inner.tell())
+    }
+
+    /// Truncates or extends the file
+    ///
+    /// # Arguments
+    /// * `size` - New size of the file in bytes
+    pub fn set_len(&mut self, size: u64) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.set_len(size))
+    }
+
+    /// Takes or releases an advisory lock on the file
+    ///
+    /// # Arguments
+    /// * `op` - The lock to take, or `AfcLockOp::Unlock`
+    pub fn lock(&mut self, op: AfcLockOp) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.lock(op))
+    }
+
+    /// Releases a lock taken with [`Self::lock`]
+    pub fn unlock(&mut self) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.unlock())
+    }
+}
@@ -0,0 +1,146 @@
+// Jackson Coxson
+// Blocking wrapper around the installation_proxy client
+
+use std::{collections::HashMap, future::Future};
+
+use tokio::runtime::Runtime;
+
+use crate::{installation_proxy, provider::IdeviceProvider, IdeviceError};
+
+/// Blocking client for the installation proxy
+///
+/// See [`crate::installation_proxy::InstallationProxyClient`] for the details of each
+/// request.
+pub struct InstallationProxyClient {
+    rt: Runtime,
+    inner: installation_proxy::InstallationProxyClient,
+}
+
+impl InstallationProxyClient {
+    /// Establishes a connection to the installation proxy
+    ///
+    /// # Arguments
+    /// * `provider` - Device connection provider
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the runtime can't be created or the connection fails
+    pub fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        let (rt, inner) = super::connect(provider)?;
+        Ok(Self { rt, inner })
+    }
+
+    /// Runs an operation on the async client that has no blocking counterpart
+    ///
+    /// # Arguments
+    /// * `f` - Builds the future to block on from the async client
+    pub fn block_on<'a, F: Future>(
+        &'a mut self,
+        f: impl FnOnce(&'a mut installation_proxy::InstallationProxyClient) -> F,
+    ) -> F::Output {
+        self.rt.block_on(f(&mut self.inner))
+    }
+
+    /// Retrieves the installed apps
+    ///
+    /// # Arguments
+    /// * `application_type` - `User`, `System` or `Any` when `None`
+    /// * `bundle_identifiers` - Only look up these apps
+    ///
+    /// # Returns
+    /// A map of bundle identifiers to the apps' info dictionaries
+    pub fn get_apps(
+        &mut self,
+        application_type: Option<&str>,
+        bundle_identifiers: Option<Vec<String>>,
+    ) -> Result<HashMap<String, plist::Value>, IdeviceError> {
+        self.rt
+            .block_on(self.inner.get_apps(application_type, bundle_identifiers))
+    }
+
+    /// Installs an application package on the device
+    ///
+    /// # Arguments
+    /// * `package_path` - Path to the .ipa package in the AFC jail
+    /// * `options` - Optional installation options as a plist dictionary
+    pub fn install(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Value>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.install(package_path, options))
+    }
+
+    /// Installs an application package on the device, reporting progress
+    ///
+    /// # Arguments
+    /// * `package_path` - Path to the .ipa package in the AFC jail
+    /// * `options` - Optional installation options as a plist dictionary
+    /// * `callback` - Receives the percentage completed
+    pub fn install_with_callback(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Value>,
+        callback: impl Fn(u64),
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.install_with_callback(
+            package_path,
+            options,
+            |(percent, _)| {
+                callback(percent);
+                async {}
+            },
+            (),
+        ))
+    }
+
+    /// Upgrades an installed application
+    ///
+    /// # Arguments
+    /// * `package_path` - Path to the .ipa package in the AFC jail
+    /// * `options` - Optional upgrade options as a plist dictionary
+    pub fn upgrade(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Value>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.upgrade(package_path, options))
+    }
+
+    /// Uninstalls an application
+    ///
+    /// # Arguments
+    /// * `bundle_id` - Bundle identifier of the app to uninstall
+    /// * `options` - Optional uninstall options as a plist dictionary
+    pub fn uninstall(
+        &mut self,
+        bundle_id: impl Into<String>,
+        options: Option<plist::Value>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.uninstall(bundle_id, options))
+    }
+
+    /// Checks whether the device supports a set of capabilities
+    ///
+    /// # Arguments
+    /// * `capabilities` - The capabilities to check
+    /// * `options` - Optional options as a plist dictionary
+    pub fn check_capabilities_match(
+        &mut self,
+        capabilities: Vec<plist::Value>,
+        options: Option<plist::Value>,
+    ) -> Result<bool, IdeviceError> {
+        self.rt
+            .block_on(self.inner.check_capabilities_match(capabilities, options))
+    }
+
+    /// Browses installed applications
+    ///
+    /// # Arguments
+    /// * `options` - Optional browse options as a plist dictionary
+    pub fn browse(
+        &mut self,
+        options: Option<plist::Value>,
+    ) -> Result<Vec<plist::Value>, IdeviceError> {
+        self.rt.block_on(self.inner.browse(options))
+    }
+}
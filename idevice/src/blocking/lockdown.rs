@@ -0,0 +1,189 @@
+// Jackson Coxson
+// Blocking wrapper around the lockdownd client
+
+use std::future::Future;
+
+use plist::Value;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+
+use crate::{
+    lockdown::{self, BatteryInfo, DeviceInfo, DiskUsage, IosVersion},
+    pairing_file::PairingFile,
+    provider::IdeviceProvider,
+    IdeviceError,
+};
+
+/// Blocking client for lockdownd
+///
+/// See [`crate::lockdown::LockdownClient`] for the details of each request.
+pub struct LockdownClient {
+    rt: Runtime,
+    inner: lockdown::LockdownClient,
+}
+
+impl LockdownClient {
+    /// Establishes a connection to lockdownd
+    ///
+    /// # Arguments
+    /// * `provider` - Device connection provider
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the runtime can't be created or the connection fails
+    pub fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        let (rt, inner) = super::connect(provider)?;
+        Ok(Self { rt, inner })
+    }
+
+    /// Runs an operation on the async client that has no blocking counterpart
+    ///
+    /// # Arguments
+    /// * `f` - Builds the future to block on from the async client
+    pub fn block_on<'a, F: Future>(
+        &'a mut self,
+        f: impl FnOnce(&'a mut lockdown::LockdownClient) -> F,
+    ) -> F::Output {
+        self.rt.block_on(f(&mut self.inner))
+    }
+
+    /// Returns the session ID of the active session, if any
+    pub fn session_id(&self) -> Option<&str> {
+        self.inner.session_id()
+    }
+
+    /// Queries the type of the service, `com.apple.mobile.lockdown` for lockdownd
+    pub fn query_type(&mut self) -> Result<String, IdeviceError> {
+        self.rt.block_on(self.inner.query_type())
+    }
+
+    /// Retrieves a value from the device
+    ///
+    /// # Arguments
+    /// * `key` - The value to get, or `None` for the whole domain
+    /// * `domain` - The domain to query, or `None` for the global domain
+    pub fn get_value(
+        &mut self,
+        key: Option<&str>,
+        domain: Option<&str>,
+    ) -> Result<Value, IdeviceError> {
+        self.rt.block_on(self.inner.get_value(key, domain))
+    }
+
+    /// Retrieves a whole domain and deserializes it
+    ///
+    /// # Arguments
+    /// * `domain` - The domain to query
+    pub fn get_domain<T: DeserializeOwned>(&mut self, domain: &str) -> Result<T, IdeviceError> {
+        self.rt.block_on(self.inner.get_domain(domain))
+    }
+
+    /// Retrieves every value of the global domain
+    pub fn get_all_values(&mut self) -> Result<plist::Dictionary, IdeviceError> {
+        self.rt.block_on(self.inner.get_all_values())
+    }
+
+    /// Retrieves the commonly used values of the global domain
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo, IdeviceError> {
+        self.rt.block_on(self.inner.get_device_info())
+    }
+
+    /// Retrieves and parses the iOS version of the device
+    pub fn ios_version(&mut self) -> Result<IosVersion, IdeviceError> {
+        self.rt.block_on(self.inner.ios_version())
+    }
+
+    /// Retrieves the disk usage of the device
+    pub fn get_disk_usage(&mut self) -> Result<DiskUsage, IdeviceError> {
+        self.rt.block_on(self.inner.get_disk_usage())
+    }
+
+    /// Retrieves the battery state of the device
+    pub fn get_battery(&mut self) -> Result<BatteryInfo, IdeviceError> {
+        self.rt.block_on(self.inner.get_battery())
+    }
+
+    /// Sets a value on the device
+    ///
+    /// # Arguments
+    /// * `key` - The value to set
+    /// * `value` - The new value
+    /// * `domain` - The domain of the value, or `None` for the global domain
+    pub fn set_value(
+        &mut self,
+        key: impl Into<String>,
+        value: Value,
+        domain: Option<&str>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.set_value(key, value, domain))
+    }
+
+    /// Removes a value from the device
+    ///
+    /// # Arguments
+    /// * `key` - The value to remove, or `None` for the whole domain
+    /// * `domain` - The domain of the value, or `None` for the global domain
+    pub fn remove_value(
+        &mut self,
+        key: Option<&str>,
+        domain: Option<&str>,
+    ) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.remove_value(key, domain))
+    }
+
+    /// Starts a TLS session with the device
+    ///
+    /// # Arguments
+    /// * `pairing_file` - Contains the device's identity and certificates
+    pub fn start_session(&mut self, pairing_file: &PairingFile) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.start_session(pairing_file))
+    }
+
+    /// Stops the active session, consuming the client
+    pub fn stop_session(self) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.stop_session())
+    }
+
+    /// Requests to start a service on the device
+    ///
+    /// # Arguments
+    /// * `identifier` - The identifier of the service to start
+    ///
+    /// # Returns
+    /// The port the service listens on and whether it uses SSL
+    pub fn start_service(
+        &mut self,
+        identifier: impl Into<String>,
+    ) -> Result<(u16, bool), IdeviceError> {
+        self.rt.block_on(self.inner.start_service(identifier))
+    }
+
+    /// Checks that a pairing record is still valid for the device
+    ///
+    /// # Arguments
+    /// * `pairing_file` - The pairing record to validate
+    pub fn validate_pair(&mut self, pairing_file: &PairingFile) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.validate_pair(pairing_file))
+    }
+
+    /// Pairs with the device, prompting the user to trust the host
+    ///
+    /// # Arguments
+    /// * `host_id` - The host ID, in the form of a UUID
+    /// * `system_buid` - UUID fetched from usbmuxd
+    ///
+    /// # Returns
+    /// The newly generated pairing record
+    #[cfg(feature = "pair")]
+    pub fn pair(
+        &mut self,
+        host_id: impl Into<String>,
+        system_buid: impl Into<String>,
+    ) -> Result<PairingFile, IdeviceError> {
+        self.rt.block_on(self.inner.pair(host_id, system_buid))
+    }
+
+    /// Tells lockdownd the host is done and closes the connection
+    pub fn goodbye(self) -> Result<(), IdeviceError> {
+        self.rt.block_on(self.inner.goodbye())
+    }
+}
@@ -0,0 +1,123 @@
+// Jackson Coxson
+//! Blocking wrappers around the async clients
+//!
+//! Each client owns a current-thread tokio runtime and drives the async client it
+//! wraps on it, the same way `reqwest::blocking` does. This lets plain threaded
+//! programs talk to a device without setting up a runtime of their own.
+//!
+//! The clients must not be used from within an async context, since blocking on a
+//! runtime from inside another one panics. Providers that keep connections alive
+//! between calls, such as `PooledProvider`, shouldn't be shared between blocking
+//! clients because every client runs its own runtime.
+
+#[cfg(feature = "afc")]
+pub mod afc;
+#[cfg(feature = "installation_proxy")]
+pub mod installation_proxy;
+pub mod lockdown;
+#[cfg(feature = "syslog_relay")]
+pub mod syslog_relay;
+#[cfg(feature = "usbmuxd")]
+pub mod usbmuxd;
+
+#[cfg(feature = "afc")]
+pub use afc::AfcClient;
+#[cfg(feature = "installation_proxy")]
+pub use installation_proxy::InstallationProxyClient;
+pub use lockdown::LockdownClient;
+#[cfg(feature = "syslog_relay")]
+pub use syslog_relay::SyslogRelayClient;
+#[cfg(feature = "usbmuxd")]
+pub use usbmuxd::UsbmuxdConnection;
+
+use tokio::runtime::Runtime;
+
+use crate::{provider::IdeviceProvider, IdeviceError, IdeviceService};
+
+/// Builds the runtime a blocking client runs on
+fn runtime() -> Result<Runtime, IdeviceError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(IdeviceError::Socket)
+}
+
+/// Connects to a service on a new runtime
+///
+/// # Arguments
+/// * `provider` - The device provider that can supply connections
+///
+/// # Returns
+/// The runtime along with the async client, which is bound to it
+fn connect<T: IdeviceService>(
+    provider: &dyn IdeviceProvider,
+) -> Result<(Runtime, T), IdeviceError> {
+    let rt = runtime()?;
+    let client = rt.block_on(T::connect(provider))?;
+    Ok((rt, client))
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use crate::{
+        afc::opcode::AfcFopenMode,
+        mock::{AfcStub, MockDevice, SyslogRelayStub},
+    };
+
+    use super::*;
+
+    #[test]
+    fn lockdown() {
+        let device = MockDevice::new();
+        let mut lockdown = LockdownClient::connect(&device).unwrap();
+        assert_eq!(lockdown.query_type().unwrap(), "com.apple.mobile.lockdown");
+        assert_eq!(lockdown.ios_version().unwrap().to_string(), "17.0");
+
+        let pairing_file = lockdown.block_on(|_| device.get_pairing_file()).unwrap();
+        lockdown.start_session(&pairing_file).unwrap();
+        assert!(lockdown.start_service("com.apple.afc").is_err());
+    }
+
+    #[test]
+    fn afc() {
+        let fs = AfcStub::new().with_dir("/Downloads");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).unwrap();
+
+        let mut file = client
+            .open("/Downloads/hello.txt", AfcFopenMode::WrOnly)
+            .unwrap();
+        file.write(b"hello").unwrap();
+        file.close().unwrap();
+
+        assert_eq!(fs.file("/Downloads/hello.txt").unwrap(), b"hello");
+        assert_eq!(
+            client.list_dir("/Downloads").unwrap(),
+            vec![".", "..", "hello.txt"]
+        );
+
+        let mut file = client
+            .open("/Downloads/hello.txt", AfcFopenMode::RdOnly)
+            .unwrap();
+        assert_eq!(file.read().unwrap(), b"hello");
+        file.close().unwrap();
+    }
+
+    #[cfg(feature = "syslog_relay")]
+    #[test]
+    fn syslog_relay_ends() {
+        let device = MockDevice::new().with_service(
+            "com.apple.syslog_relay",
+            true,
+            SyslogRelayStub::new(["first line", "second line"]).closing(),
+        );
+        let client = SyslogRelayClient::connect(&device).unwrap();
+
+        let lines: Vec<String> = client.filter_map(Result::ok).collect();
+        assert_eq!(lines, vec!["first line", "second line"]);
+
+        let mut client = SyslogRelayClient::connect(&device).unwrap();
+        assert_eq!(client.nth(2).map(|r| r.is_err()), Some(true));
+        assert!(client.next().is_none());
+    }
+}
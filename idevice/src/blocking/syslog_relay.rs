@@ -0,0 +1,52 @@
+// Jackson Coxson
+// Blocking wrapper around the syslog relay client
+
+use tokio::runtime::Runtime;
+
+use crate::{provider::IdeviceProvider, syslog_relay, IdeviceError};
+
+/// Blocking client for the syslog relay
+///
+/// Iterating yields the device's log lines as they arrive. Once the connection fails or the
+/// device closes it, the error is yielded and iteration ends.
+pub struct SyslogRelayClient {
+    rt: Runtime,
+    inner: syslog_relay::SyslogRelayClient,
+    /// Set once the connection is unusable
+    closed: bool,
+}
+
+impl SyslogRelayClient {
+    /// Establishes a connection to the syslog relay
+    ///
+    /// # Arguments
+    /// * `provider` - Device connection provider
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the runtime can't be created or the connection fails
+    pub fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        let (rt, inner) = super::connect(provider)?;
+        Ok(Self {
+            rt,
+            inner,
+            closed: false,
+        })
+    }
+}
+
+impl Iterator for SyslogRelayClient {
+    type Item = Result<String, IdeviceError>;
+
+    /// Waits for the next log line
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.closed {
+            return None;
+        }
+        let res = self.rt.block_on(self.inner.next());
+        // The relay sends nothing but lines, so any error means the connection is gone
+        if res.is_err() {
+            self.closed = true;
+        }
+        Some(res)
+    }
+}
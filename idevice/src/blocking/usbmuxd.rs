@@ -0,0 +1,58 @@
+// Jackson Coxson
+// Blocking wrapper around the usbmuxd connection
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    pairing_file::PairingFile,
+    usbmuxd::{self, UsbmuxdAddr, UsbmuxdDevice},
+    IdeviceError,
+};
+
+/// Blocking connection to usbmuxd
+///
+/// See [`crate::usbmuxd::UsbmuxdConnection`] for the details of each request. The
+/// providers created from the devices it lists can be passed to any blocking client.
+pub struct UsbmuxdConnection {
+    rt: Runtime,
+    inner: usbmuxd::UsbmuxdConnection,
+}
+
+impl UsbmuxdConnection {
+    /// Connects to usbmuxd
+    ///
+    /// # Arguments
+    /// * `addr` - The address usbmuxd listens on, usually `UsbmuxdAddr::default()`
+    /// * `tag` - Connection tag/identifier
+    pub fn connect(addr: &UsbmuxdAddr, tag: u32) -> Result<Self, IdeviceError> {
+        let rt = super::runtime()?;
+        let inner = rt.block_on(addr.connect(tag))?;
+        Ok(Self { rt, inner })
+    }
+
+    /// Lists the devices connected to usbmuxd
+    pub fn get_devices(&mut self) -> Result<Vec<UsbmuxdDevice>, IdeviceError> {
+        self.rt.block_on(self.inner.get_devices())
+    }
+
+    /// Gets a specific device by UDID
+    ///
+    /// # Arguments
+    /// * `udid` - The device UDID to find
+    pub fn get_device(&mut self, udid: &str) -> Result<UsbmuxdDevice, IdeviceError> {
+        self.rt.block_on(self.inner.get_device(udid))
+    }
+
+    /// Gets the pairing record for a device
+    ///
+    /// # Arguments
+    /// * `udid` - The device UDID
+    pub fn get_pair_record(&mut self, udid: &str) -> Result<PairingFile, IdeviceError> {
+        self.rt.block_on(self.inner.get_pair_record(udid))
+    }
+
+    /// Gets the system BUID
+    pub fn get_buid(&mut self) -> Result<String, IdeviceError> {
+        self.rt.block_on(self.inner.get_buid())
+    }
+}
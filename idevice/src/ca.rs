@@ -3,29 +3,44 @@
 
 use std::str::FromStr;
 
+use cms::{
+    builder::{SignedDataBuilder, SignerInfoBuilder},
+    cert::{CertificateChoices, IssuerAndSerialNumber},
+    signed_data::{EncapsulatedContentInfo, SignerIdentifier},
+};
 use rsa::{
-    pkcs1::DecodeRsaPublicKey,
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
     pkcs1v15::SigningKey,
-    pkcs8::{EncodePrivateKey, LineEnding, SubjectPublicKeyInfo},
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding, SubjectPublicKeyInfo},
+    rand_core::RngCore,
     RsaPrivateKey, RsaPublicKey,
 };
 use sha1::Sha1;
+use sha2::Sha256;
 use x509_cert::{
     builder::{Builder, CertificateBuilder, Profile},
-    der::EncodePem,
+    der::{asn1::ObjectIdentifier, Any, Decode, DecodePem, Encode, EncodePem, Tag},
     ext::pkix::{BasicConstraints, KeyUsage, KeyUsages},
     name::Name,
     serial_number::SerialNumber,
+    spki::AlgorithmIdentifierOwned,
     time::Validity,
     Certificate,
 };
 
+/// id-data from RFC 5652
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+/// id-sha256 from RFC 5754
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
 #[derive(Clone, Debug)]
 pub struct CaReturn {
     pub root_cert: Vec<u8>,
     pub host_cert: Vec<u8>,
     pub dev_cert: Vec<u8>,
-    pub private_key: Vec<u8>,
+    pub host_private_key: Vec<u8>,
+    /// The root's key, only returned when the root was generated for this record
+    pub root_private_key: Option<Vec<u8>>,
 }
 
 pub fn make_cert(
@@ -33,6 +48,7 @@ pub fn make_cert(
     public_key: &RsaPublicKey,
     common_name: Option<&str>,
     is_ca: bool,
+    profile: Profile,
 ) -> Result<Certificate, Box<dyn std::error::Error>> {
     // Create subject/issuer name
     let name = match common_name {
@@ -48,10 +64,16 @@ pub fn make_cert(
     let signing_key = SigningKey::<Sha1>::new(signing_key.clone());
     let public_key = SubjectPublicKeyInfo::from_key(public_key.clone())?;
 
+    // Issuer and serial number must be unique, and a reused root issues many certificates
+    let mut serial = [0u8; 16];
+    rsa::rand_core::OsRng.fill_bytes(&mut serial);
+    // Positive and without a leading zero byte
+    serial[0] = (serial[0] & 0x7f).max(1);
+
     // Build certificate matching libimobiledevice behavior
     let mut cert = CertificateBuilder::new(
-        Profile::Root,
-        SerialNumber::new(&[1])?,
+        profile,
+        SerialNumber::new(&serial)?,
         validity,
         name,
         public_key,
@@ -82,40 +104,302 @@ fn dump_cert(cert: &Certificate) -> Result<String, Box<dyn std::error::Error>> {
     Ok(b)
 }
 
+/// Generates the certificates of a pair record
+///
+/// Without a root, a new root is generated and its key doubles as the host key, like
+/// libimobiledevice does. With an existing root, such as an organisation-wide CA, a new host key
+/// is generated and the host and device certificates are issued by the root. The root's key is
+/// not returned, so it never ends up in the record.
 pub(crate) fn generate_certificates(
     device_public_key_pem: &[u8],
-    private_key: Option<RsaPrivateKey>,
+    root: Option<(&RsaPrivateKey, &Certificate)>,
 ) -> Result<CaReturn, Box<dyn std::error::Error>> {
     // Load device public key
     let device_public_key =
         RsaPublicKey::from_pkcs1_pem(std::str::from_utf8(device_public_key_pem)?)?;
+    let mut rng = rsa::rand_core::OsRng;
 
-    // Generate or use provided private key
-    let private_key = match private_key {
-        Some(p) => p,
+    let (root_key, root_cert) = match root {
+        Some(r) => r,
         None => {
-            let mut rng = rsa::rand_core::OsRng;
-            RsaPrivateKey::new(&mut rng, 2048)?
+            let private_key = RsaPrivateKey::new(&mut rng, 2048)?;
+            let public_key = RsaPublicKey::from(&private_key);
+
+            // Create CA cert (root certificate with CA=TRUE)
+            let ca_cert = make_cert(
+                &private_key,
+                &public_key,
+                Some("Root CA"),
+                true,
+                Profile::Root,
+            )?;
+
+            // Create host cert (signed by CA, CA=FALSE)
+            let host_cert = make_cert(
+                &private_key,
+                &public_key,
+                Some("Host"),
+                false,
+                Profile::Root,
+            )?;
+
+            // Create device cert (signed by CA, CA=FALSE)
+            let dev_cert = make_cert(
+                &private_key,
+                &device_public_key,
+                Some("Device"),
+                false,
+                Profile::Root,
+            )?;
+
+            let private_key = private_key
+                .to_pkcs8_pem(LineEnding::LF)?
+                .as_bytes()
+                .to_vec();
+            return Ok(CaReturn {
+                root_cert: dump_cert(&ca_cert)?.into_bytes(),
+                host_cert: dump_cert(&host_cert)?.into_bytes(),
+                dev_cert: dump_cert(&dev_cert)?.into_bytes(),
+                host_private_key: private_key.clone(),
+                root_private_key: Some(private_key),
+            });
         }
     };
 
-    // Create CA cert (root certificate with CA=TRUE)
-    let ca_public_key = RsaPublicKey::from(&private_key);
-    let ca_cert = make_cert(&private_key, &ca_public_key, Some("Root CA"), true)?;
-
-    // Create host cert (signed by CA, CA=FALSE)
-    let host_cert = make_cert(&private_key, &ca_public_key, Some("Host"), false)?;
+    if root_cert.tbs_certificate.subject_public_key_info
+        != SubjectPublicKeyInfo::from_key(RsaPublicKey::from(root_key))?
+    {
+        return Err("the root private key doesn't match the root certificate".into());
+    }
 
-    // Create device cert (signed by CA, CA=FALSE)
-    let dev_cert = make_cert(&private_key, &device_public_key, Some("Device"), false)?;
+    // Issue the host and device certs from the root so they chain to it
+    let issuer = Profile::Manual {
+        issuer: Some(root_cert.tbs_certificate.subject.clone()),
+    };
+    let host_key = RsaPrivateKey::new(&mut rng, 2048)?;
+    let host_cert = make_cert(
+        root_key,
+        &RsaPublicKey::from(&host_key),
+        Some("Host"),
+        false,
+        issuer.clone(),
+    )?;
+    let dev_cert = make_cert(root_key, &device_public_key, Some("Device"), false, issuer)?;
 
     Ok(CaReturn {
-        root_cert: dump_cert(&ca_cert)?.into_bytes(),
+        root_cert: dump_cert(root_cert)?.into_bytes(),
         host_cert: dump_cert(&host_cert)?.into_bytes(),
         dev_cert: dump_cert(&dev_cert)?.into_bytes(),
-        private_key: private_key
-            .to_pkcs8_pem(LineEnding::LF)?
-            .as_bytes()
-            .to_vec(),
+        host_private_key: host_key.to_pkcs8_pem(LineEnding::LF)?.as_bytes().to_vec(),
+        root_private_key: None,
     })
 }
+
+/// Parses a PEM or DER encoded certificate
+pub(crate) fn parse_cert(cert: &[u8]) -> Result<Certificate, Box<dyn std::error::Error>> {
+    match Certificate::from_pem(cert) {
+        Ok(c) => Ok(c),
+        Err(_) => Ok(Certificate::from_der(cert)?),
+    }
+}
+
+/// Parses a PEM encoded RSA private key in either PKCS#8 or PKCS#1 form
+pub(crate) fn parse_private_key(key: &[u8]) -> Result<RsaPrivateKey, Box<dyn std::error::Error>> {
+    let key = std::str::from_utf8(key)?;
+    match RsaPrivateKey::from_pkcs8_pem(key) {
+        Ok(k) => Ok(k),
+        Err(_) => Ok(RsaPrivateKey::from_pkcs1_pem(key)?),
+    }
+}
+
+/// Signs a supervised pairing challenge, producing a DER encoded PKCS#7 signed data blob
+pub(crate) fn sign_challenge(
+    challenge: &[u8],
+    cert: &Certificate,
+    private_key: &RsaPrivateKey,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let content = EncapsulatedContentInfo {
+        econtent_type: ID_DATA,
+        econtent: Some(Any::new(Tag::OctetString, challenge)?),
+    };
+    let digest_algorithm = AlgorithmIdentifierOwned {
+        oid: ID_SHA_256,
+        parameters: None,
+    };
+    let sid = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+        issuer: cert.tbs_certificate.issuer.clone(),
+        serial_number: cert.tbs_certificate.serial_number.clone(),
+    });
+
+    // cms' builder error doesn't implement std::error::Error
+    let signer = SigningKey::<Sha256>::new(private_key.clone());
+    let signer_info =
+        SignerInfoBuilder::new(&signer, sid, digest_algorithm.clone(), &content, None)
+            .map_err(|e| e.to_string())?;
+
+    let signed = SignedDataBuilder::new(&content)
+        .add_digest_algorithm(digest_algorithm)
+        .and_then(|b| b.add_certificate(CertificateChoices::Certificate(cert.clone())))
+        .and_then(|b| {
+            b.add_signer_info::<SigningKey<Sha256>, rsa::pkcs1v15::Signature>(signer_info)
+        })
+        .and_then(|b| b.build())
+        .map_err(|e| e.to_string())?;
+
+    Ok(signed.to_der()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::{
+        pkcs1::EncodeRsaPrivateKey,
+        pkcs1::EncodeRsaPublicKey,
+        pkcs1v15::{Signature, VerifyingKey},
+        signature::Verifier,
+    };
+
+    fn key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap()
+    }
+
+    /// Checks that `cert` was signed by `key`, using the SHA-1 signatures `make_cert` produces
+    fn assert_signed_by(cert: &Certificate, key: &RsaPublicKey) {
+        let tbs = cert.tbs_certificate.to_der().unwrap();
+        let signature = Signature::try_from(cert.signature.raw_bytes()).unwrap();
+        VerifyingKey::<Sha1>::new(key.clone())
+            .verify(&tbs, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn private_key_formats() {
+        let key = key();
+        let pkcs8 = key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let pkcs1 = key.to_pkcs1_pem(LineEnding::LF).unwrap();
+        assert_eq!(parse_private_key(pkcs8.as_bytes()).unwrap(), key);
+        assert_eq!(parse_private_key(pkcs1.as_bytes()).unwrap(), key);
+
+        assert!(parse_private_key(b"not a key").is_err());
+        assert!(parse_private_key(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn cert_formats() {
+        let key = key();
+        let cert = make_cert(
+            &key,
+            &RsaPublicKey::from(&key),
+            Some("Root CA"),
+            true,
+            Profile::Root,
+        )
+        .unwrap();
+        let pem = cert.to_pem(LineEnding::LF).unwrap();
+        let der = cert.to_der().unwrap();
+        assert_eq!(parse_cert(pem.as_bytes()).unwrap(), cert);
+        assert_eq!(parse_cert(&der).unwrap(), cert);
+
+        assert!(parse_cert(b"not a certificate").is_err());
+        assert!(parse_cert(&der[..der.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn challenge_signature() {
+        use cms::{content_info::ContentInfo, signed_data::SignedData};
+
+        let key = key();
+        let cert = make_cert(
+            &key,
+            &RsaPublicKey::from(&key),
+            Some("Supervisor"),
+            true,
+            Profile::Root,
+        )
+        .unwrap();
+        let challenge = b"pairing challenge";
+
+        let response = sign_challenge(challenge, &cert, &key).unwrap();
+        let signed: SignedData = ContentInfo::from_der(&response)
+            .unwrap()
+            .content
+            .decode_as()
+            .unwrap();
+
+        let content = signed.encap_content_info.econtent.as_ref().unwrap();
+        assert_eq!(content.value(), challenge);
+        assert!(matches!(
+            signed.certificates.as_ref().unwrap().0.get(0),
+            Some(CertificateChoices::Certificate(c)) if *c == cert
+        ));
+
+        let signer = signed.signer_infos.0.get(0).unwrap();
+        match &signer.sid {
+            SignerIdentifier::IssuerAndSerialNumber(i) => {
+                assert_eq!(i.issuer, cert.tbs_certificate.issuer);
+                assert_eq!(i.serial_number, cert.tbs_certificate.serial_number);
+            }
+            sid => panic!("unexpected signer identifier {sid:?}"),
+        }
+        let signed_attrs = signer.signed_attrs.as_ref().unwrap().to_der().unwrap();
+        let signature = Signature::try_from(signer.signature.as_bytes()).unwrap();
+        VerifyingKey::<Sha256>::new(RsaPublicKey::from(&key))
+            .verify(&signed_attrs, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn reused_root() {
+        let root_key = key();
+        let root_public_key = RsaPublicKey::from(&root_key);
+        let root_cert = make_cert(
+            &root_key,
+            &root_public_key,
+            Some("Fleet CA"),
+            true,
+            Profile::Root,
+        )
+        .unwrap();
+        let device_key = key();
+        let device_public_key = RsaPublicKey::from(&device_key)
+            .to_pkcs1_pem(LineEnding::LF)
+            .unwrap();
+
+        let ca = generate_certificates(device_public_key.as_bytes(), Some((&root_key, &root_cert)))
+            .unwrap();
+        assert!(ca.root_private_key.is_none());
+        assert_eq!(parse_cert(&ca.root_cert).unwrap(), root_cert);
+
+        // The host gets its own key
+        let host_key = parse_private_key(&ca.host_private_key).unwrap();
+        assert_ne!(host_key, root_key);
+
+        let host_cert = parse_cert(&ca.host_cert).unwrap();
+        let dev_cert = parse_cert(&ca.dev_cert).unwrap();
+        assert_ne!(
+            host_cert.tbs_certificate.serial_number,
+            dev_cert.tbs_certificate.serial_number
+        );
+        for cert in [&host_cert, &dev_cert] {
+            assert_eq!(
+                cert.tbs_certificate.issuer,
+                root_cert.tbs_certificate.subject
+            );
+            assert_signed_by(cert, &root_public_key);
+        }
+        assert_eq!(
+            host_cert.tbs_certificate.subject_public_key_info,
+            SubjectPublicKeyInfo::from_key(RsaPublicKey::from(&host_key)).unwrap()
+        );
+        assert_eq!(
+            dev_cert.tbs_certificate.subject_public_key_info,
+            SubjectPublicKeyInfo::from_key(RsaPublicKey::from(&device_key)).unwrap()
+        );
+
+        // A key that doesn't belong to the root is refused
+        assert!(
+            generate_certificates(device_public_key.as_bytes(), Some((&key(), &root_cert)))
+                .is_err()
+        );
+    }
+}
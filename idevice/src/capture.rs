@@ -0,0 +1,556 @@
+//! Traffic recording and replay
+//!
+//! [`RecordingProvider`] wraps another provider and records every byte sent and received on
+//! the connections it opens. Traffic is recorded after TLS is set up by `start_session`, so the
+//! capture contains the decrypted plists and service payloads.
+//!
+//! [`ReplayProvider`] serves a [`Capture`] back as a fake device, so a session can be reproduced
+//! without hardware.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{
+    codec::Decoder,
+    lockdown::LockdownClient,
+    pairing_file::PairingFile,
+    provider::{IdeviceProvider, StartServiceFuture},
+    Idevice, IdeviceError, ReadWrite,
+};
+
+/// Which way the bytes of an event travelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Sent by the host
+    HostToDevice,
+    /// Sent by the device
+    DeviceToHost,
+}
+
+/// A single read or write on a recorded connection
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CaptureEvent {
+    /// Which way the bytes travelled
+    pub direction: Direction,
+    /// Microseconds since the recording started
+    pub timestamp_micros: u64,
+    /// The bytes that were read or written
+    #[serde(with = "as_data")]
+    pub data: Vec<u8>,
+}
+
+/// A recorded connection to a single port on the device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CapturedConnection {
+    /// The service running on the port, or `unknown` if it wasn't started through the provider
+    pub service: String,
+    /// The port on the device
+    pub port: u16,
+    /// The traffic, in order
+    pub events: Vec<CaptureEvent>,
+}
+
+impl CapturedConnection {
+    /// Decodes the traffic sent in one direction with a sans-IO decoder
+    ///
+    /// # Arguments
+    /// * `direction` - Which side's traffic to decode
+    /// * `decoder` - The decoder for the connection's protocol
+    ///
+    /// # Errors
+    /// Returns the first `IdeviceError` the decoder hits
+    pub fn decode<D: Decoder>(
+        &self,
+        direction: Direction,
+        mut decoder: D,
+    ) -> Result<Vec<D::Item>, IdeviceError> {
+        let mut res = Vec::new();
+        for event in self.events.iter().filter(|e| e.direction == direction) {
+            decoder.push(&event.data);
+            while let Some(item) = decoder.decode()? {
+                res.push(item);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// A recorded session, made of every connection opened through a [`RecordingProvider`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Capture {
+    /// The connections, in the order they were opened
+    pub connections: Vec<CapturedConnection>,
+}
+
+impl Capture {
+    /// Reads a capture from disk
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the file can't be read or isn't a capture
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, IdeviceError> {
+        Ok(plist::from_file(path)?)
+    }
+
+    /// Writes the capture to disk as a binary plist
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the file can't be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), IdeviceError> {
+        Ok(plist::to_file_binary(path, self)?)
+    }
+}
+
+/// Serializes bytes as plist data instead of an array of integers
+mod as_data {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(v)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        Ok(plist::Data::deserialize(d)?.into())
+    }
+}
+
+/// Records the traffic of one connection into a shared capture
+///
+/// Every time a TLS session starts the recorder moves up a layer, so only the outermost,
+/// decrypted stream is recorded.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionRecorder {
+    capture: Arc<Mutex<Capture>>,
+    index: usize,
+    start: Instant,
+    layer: Arc<AtomicU64>,
+}
+
+impl ConnectionRecorder {
+    fn record(&self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let event = CaptureEvent {
+            direction,
+            timestamp_micros: self.start.elapsed().as_micros() as u64,
+            data: data.to_vec(),
+        };
+        if let Ok(mut capture) = self.capture.lock() {
+            capture.connections[self.index].events.push(event);
+        }
+    }
+
+    /// Stops recording until the next layer is wrapped
+    pub(crate) fn pause(&self) {
+        self.layer.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Wraps a socket, making it the only layer that is recorded
+    pub(crate) fn wrap(&self, socket: Box<dyn ReadWrite>) -> Box<dyn ReadWrite> {
+        let layer = self.layer.fetch_add(1, Ordering::Relaxed) + 1;
+        Box::new(RecordingStream {
+            inner: socket,
+            recorder: self.clone(),
+            layer,
+        })
+    }
+}
+
+struct RecordingStream {
+    inner: Box<dyn ReadWrite>,
+    recorder: ConnectionRecorder,
+    layer: u64,
+}
+
+impl RecordingStream {
+    fn active(&self) -> bool {
+        self.recorder.layer.load(Ordering::Relaxed) == self.layer
+    }
+}
+
+impl std::fmt::Debug for RecordingStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordingStream")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl AsyncRead for RecordingStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res
+            && this.active()
+        {
+            this.recorder
+                .record(Direction::DeviceToHost, &buf.filled()[before..]);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for RecordingStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res
+            && this.active()
+        {
+            this.recorder.record(Direction::HostToDevice, &buf[..n]);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Provider that records the traffic of every connection it opens
+pub struct RecordingProvider {
+    inner: Box<dyn IdeviceProvider>,
+    capture: Arc<Mutex<Capture>>,
+    services: Arc<Mutex<HashMap<u16, String>>>,
+    start: Instant,
+}
+
+impl RecordingProvider {
+    /// Starts recording connections made through another provider
+    ///
+    /// # Arguments
+    /// * `inner` - The provider that connects to the device
+    pub fn new(inner: Box<dyn IdeviceProvider>) -> Self {
+        let mut services = HashMap::new();
+        services.insert(
+            LockdownClient::LOCKDOWND_PORT,
+            "com.apple.mobile.lockdown".to_string(),
+        );
+        Self {
+            inner,
+            capture: Default::default(),
+            services: Arc::new(Mutex::new(services)),
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns a copy of everything recorded so far
+    pub fn capture(&self) -> Capture {
+        self.capture.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Debug for RecordingProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordingProvider")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl IdeviceProvider for RecordingProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let fut = self.inner.connect(port);
+        let capture = self.capture.clone();
+        let services = self.services.clone();
+        let start = self.start;
+
+        Box::pin(async move {
+            let mut idevice = fut.await?;
+
+            let service = services
+                .lock()
+                .ok()
+                .and_then(|s| s.get(&port).cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+            debug!("Recording connection to {service} on port {port}");
+
+            let index = {
+                let mut capture = capture.lock().map_err(|_| IdeviceError::UnexpectedResponse)?;
+                capture.connections.push(CapturedConnection {
+                    service,
+                    port,
+                    events: Vec::new(),
+                });
+                capture.connections.len() - 1
+            };
+
+            idevice.attach_recorder(ConnectionRecorder {
+                capture,
+                index,
+                start,
+                layer: Default::default(),
+            });
+            Ok(idevice)
+        })
+    }
+
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        self.inner.get_pairing_file()
+    }
+
+    /// Starts the service over a recorded lockdown connection, remembering its name for the port
+    fn start_service(&self, service_name: Cow<'static, str>) -> StartServiceFuture<'_> {
+        Box::pin(async move {
+            let mut lockdown =
+                LockdownClient::new(self.connect(LockdownClient::LOCKDOWND_PORT).await?);
+            lockdown
+                .start_session(&self.get_pairing_file().await?)
+                .await?;
+            let (port, ssl) = lockdown.start_service(service_name.clone()).await?;
+
+            if let Ok(mut services) = self.services.lock() {
+                services.insert(port, service_name.into_owned());
+            }
+            Ok((port, ssl))
+        })
+    }
+}
+
+/// Provider that acts as a fake device by replaying a [`Capture`]
+///
+/// Each connection to a port replays the next recorded connection to that port. Bytes sent by
+/// the host are compared to the recording and mismatches are logged, but replay continues.
+/// Connections skip TLS, since the capture is already decrypted.
+#[derive(Debug)]
+pub struct ReplayProvider {
+    connections: Arc<Mutex<Vec<Option<CapturedConnection>>>>,
+    label: String,
+}
+
+impl ReplayProvider {
+    /// Creates a fake device from a capture
+    ///
+    /// # Arguments
+    /// * `capture` - The session to replay
+    /// * `label` - Label identifying this provider
+    pub fn new(capture: Capture, label: impl Into<String>) -> Self {
+        Self {
+            connections: Arc::new(Mutex::new(
+                capture.connections.into_iter().map(Some).collect(),
+            )),
+            label: label.into(),
+        }
+    }
+}
+
+/// Plays the device side of a recorded connection
+async fn replay(connection: CapturedConnection, mut host: tokio::io::DuplexStream) {
+    for event in connection.events {
+        let res = match event.direction {
+            Direction::DeviceToHost => host.write_all(&event.data).await,
+            Direction::HostToDevice => {
+                let mut buf = vec![0; event.data.len()];
+                let res = host.read_exact(&mut buf).await.map(|_| ());
+                if res.is_ok() && buf != event.data {
+                    warn!(
+                        "Replay of {} diverged from the recording at {}us",
+                        connection.service, event.timestamp_micros
+                    );
+                }
+                res
+            }
+        };
+        if let Err(e) = res {
+            debug!("Replay of {} ended early: {e:?}", connection.service);
+            return;
+        }
+    }
+}
+
+impl IdeviceProvider for ReplayProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let connection = self.connections.lock().ok().and_then(|mut c| {
+            c.iter_mut()
+                .find(|x| x.as_ref().is_some_and(|x| x.port == port))
+                .and_then(|x| x.take())
+        });
+        let label = self.label.clone();
+
+        Box::pin(async move {
+            let connection = match connection {
+                Some(c) => c,
+                None => {
+                    warn!("No recorded connection left for port {port}");
+                    return Err(IdeviceError::ServiceNotFound);
+                }
+            };
+
+            let (host, device) = tokio::io::duplex(1024 * 64);
+            tokio::spawn(replay(connection, device));
+
+            let mut idevice = Idevice::new(Box::new(host), label);
+            idevice.replayed = true;
+            Ok(idevice)
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns a placeholder pairing file, since the capture holds no keys
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        Box::pin(async {
+            Ok(PairingFile {
+                device_certificate: Vec::new().into(),
+                host_private_key: Vec::new(),
+                host_certificate: Vec::new().into(),
+                root_private_key: Vec::new(),
+                root_certificate: Vec::new().into(),
+                system_buid: String::new(),
+                host_id: String::new(),
+                escrow_bag: Vec::new(),
+                wifi_mac_address: String::new(),
+                udid: None,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::PlistDecoder;
+
+    /// Answers QueryType requests like lockdownd
+    #[derive(Debug)]
+    struct FakeLockdown;
+
+    impl IdeviceProvider for FakeLockdown {
+        fn connect(
+            &self,
+            _port: u16,
+        ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+            Box::pin(async {
+                let (host, mut device) = tokio::io::duplex(1024);
+                tokio::spawn(async move {
+                    let mut len = [0u8; 4];
+                    device.read_exact(&mut len).await.unwrap();
+                    let mut req = vec![0; u32::from_be_bytes(len) as usize];
+                    device.read_exact(&mut req).await.unwrap();
+
+                    let mut res = plist::Dictionary::new();
+                    res.insert("Type".into(), "com.apple.mobile.lockdown".into());
+                    let mut buf = Vec::new();
+                    plist::to_writer_xml(&mut buf, &res).unwrap();
+                    device
+                        .write_all(&(buf.len() as u32).to_be_bytes())
+                        .await
+                        .unwrap();
+                    device.write_all(&buf).await.unwrap();
+                });
+                Ok(Idevice::new(Box::new(host), "test"))
+            })
+        }
+
+        fn label(&self) -> &str {
+            "test"
+        }
+
+        fn get_pairing_file(
+            &self,
+        ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+            Box::pin(async { Err(IdeviceError::NotFound) })
+        }
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let recorder = RecordingProvider::new(Box::new(FakeLockdown));
+        let mut idevice = recorder.connect(LockdownClient::LOCKDOWND_PORT).await.unwrap();
+        assert_eq!(
+            idevice.get_type().await.unwrap(),
+            "com.apple.mobile.lockdown"
+        );
+
+        let capture = recorder.capture();
+        assert_eq!(capture.connections.len(), 1);
+        let connection = &capture.connections[0];
+        assert_eq!(connection.service, "com.apple.mobile.lockdown");
+        assert_eq!(connection.events[0].direction, Direction::HostToDevice);
+        assert!(connection
+            .events
+            .iter()
+            .any(|e| e.direction == Direction::DeviceToHost));
+        let requests = connection
+            .decode(Direction::HostToDevice, PlistDecoder::new())
+            .unwrap();
+        assert_eq!(
+            requests[0].get("Request").and_then(|x| x.as_string()),
+            Some("QueryType")
+        );
+
+        let dir = std::env::temp_dir().join(format!("idevice-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.plist");
+        capture.save(&path).unwrap();
+        let loaded = Capture::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, capture);
+
+        let replayer = ReplayProvider::new(loaded, "replay");
+        let mut idevice = replayer
+            .connect(LockdownClient::LOCKDOWND_PORT)
+            .await
+            .unwrap();
+        assert_eq!(
+            idevice.get_type().await.unwrap(),
+            "com.apple.mobile.lockdown"
+        );
+
+        // Every recorded connection is only replayed once
+        assert!(replayer
+            .connect(LockdownClient::LOCKDOWND_PORT)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,214 @@
+// Jackson Coxson
+//! Sans-IO protocol codecs
+//!
+//! Decoders are state machines that bytes are pushed into and messages are pulled
+//! out of. They never touch a socket, so they can be fed from captured traffic, a
+//! fuzzer or any I/O stack. The async readers in this crate are thin adapters that
+//! read at most [`Decoder::bytes_needed`] bytes at a time and push them in, so they
+//! never consume bytes that belong to the next message.
+//!
+//! This module holds the length-prefixed plist framing used by lockdownd and most
+//! lockdown services. The other framings live next to their protocol:
+//! - `afc::packet::AfcPacketDecoder`
+//! - `dvt::message::MessageDecoder`
+//! - `xpc::FrameDecoder` for the HTTP/2 frames RemoteXPC is carried in
+
+use std::io::BufWriter;
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::IdeviceError;
+
+/// The most bytes [`read_from`] reads at once
+#[cfg(any(feature = "tokio", feature = "futures_io"))]
+const MAX_READ: usize = 1024 * 1024;
+
+/// A push-based decoder for one of the device protocols
+pub trait Decoder {
+    /// The message produced by the decoder
+    type Item;
+
+    /// Appends bytes received from the device
+    fn push(&mut self, bytes: &[u8]);
+
+    /// Decodes the next message if enough bytes have been pushed
+    ///
+    /// # Returns
+    /// `None` if more bytes are needed
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the buffered bytes aren't a valid message. The
+    /// decoder's state is unspecified afterwards.
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError>;
+
+    /// Returns how many more bytes are needed before `decode` can make progress
+    ///
+    /// Never returns more bytes than the rest of the current message.
+    fn bytes_needed(&self) -> usize;
+}
+
+/// Reads the next message from an async reader using a decoder
+///
+/// # Arguments
+/// * `decoder` - The decoder to push the read bytes into
+/// * `reader` - The reader to read from
+#[cfg(feature = "tokio")]
+pub async fn read_from<D: Decoder, R: AsyncRead + Unpin + ?Sized>(
+    decoder: &mut D,
+    reader: &mut R,
+) -> Result<D::Item, IdeviceError> {
+    loop {
+        if let Some(item) = decoder.decode()? {
+            return Ok(item);
+        }
+        // Lengths come from the device, so don't trust them for the allocation
+        let mut buf = vec![0; decoder.bytes_needed().min(MAX_READ)];
+        reader.read_exact(&mut buf).await?;
+        decoder.push(&buf);
+    }
+}
+
+/// Reads the next message from a `futures::io` reader using a decoder
+///
+/// The same as [`read_from`], for executors other than tokio.
+///
+/// # Arguments
+/// * `decoder` - The decoder to push the read bytes into
+/// * `reader` - The reader to read from
+#[cfg(feature = "futures_io")]
+pub async fn read_from_futures<D: Decoder, R: futures::io::AsyncRead + Unpin + ?Sized>(
+    decoder: &mut D,
+    reader: &mut R,
+) -> Result<D::Item, IdeviceError> {
+    use futures::io::AsyncReadExt;
+
+    loop {
+        if let Some(item) = decoder.decode()? {
+            return Ok(item);
+        }
+        let mut buf = vec![0; decoder.bytes_needed().min(MAX_READ)];
+        reader.read_exact(&mut buf).await?;
+        decoder.push(&buf);
+    }
+}
+
+/// Decoder for plists prefixed by their big endian `u32` length
+///
+/// Both XML and binary plists are accepted.
+#[derive(Debug, Default)]
+pub struct PlistDecoder {
+    buf: Vec<u8>,
+}
+
+impl PlistDecoder {
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the frame at the start of the buffer, once its prefix is in
+    fn frame_len(&self) -> Option<usize> {
+        self.buf
+            .get(..4)
+            .map(|b| 4 + u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+}
+
+impl Decoder for PlistDecoder {
+    type Item = plist::Dictionary;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError> {
+        let len = match self.frame_len() {
+            Some(l) if self.buf.len() >= l => l,
+            _ => return Ok(None),
+        };
+        let frame: Vec<u8> = self.buf.drain(..len).collect();
+        Ok(Some(plist::from_bytes(&frame[4..])?))
+    }
+
+    fn bytes_needed(&self) -> usize {
+        match self.frame_len() {
+            Some(l) => l.saturating_sub(self.buf.len()),
+            None => 4 - self.buf.len(),
+        }
+    }
+}
+
+/// Encodes a plist as XML with its length prefix
+///
+/// # Arguments
+/// * `message` - The plist to encode
+pub fn encode_plist(message: &plist::Value) -> Result<Vec<u8>, IdeviceError> {
+    let mut writer = BufWriter::new(Vec::new());
+    message.to_writer_xml(&mut writer)?;
+    Ok(frame(writer.into_inner().unwrap()))
+}
+
+/// Encodes a plist as a binary plist with its length prefix
+///
+/// # Arguments
+/// * `message` - The plist to encode
+pub fn encode_bplist(message: &plist::Value) -> Result<Vec<u8>, IdeviceError> {
+    let mut writer = BufWriter::new(Vec::new());
+    message.to_writer_binary(&mut writer)?;
+    Ok(frame(writer.into_inner().unwrap()))
+}
+
+fn frame(body: Vec<u8>) -> Vec<u8> {
+    let mut res = Vec::with_capacity(body.len() + 4);
+    res.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    res.extend(body);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plist_byte_by_byte() {
+        let mut message = plist::Dictionary::new();
+        message.insert("Request".into(), "QueryType".into());
+        let mut bytes = encode_plist(&plist::Value::Dictionary(message.clone())).unwrap();
+        bytes.extend(encode_bplist(&plist::Value::Dictionary(message.clone())).unwrap());
+
+        let mut decoder = PlistDecoder::new();
+        let mut decoded = Vec::new();
+        for b in bytes {
+            assert!(decoder.bytes_needed() > 0);
+            decoder.push(&[b]);
+            if let Some(m) = decoder.decode().unwrap() {
+                decoded.push(m);
+            }
+        }
+        assert_eq!(decoded, vec![message.clone(), message]);
+        assert_eq!(decoder.bytes_needed(), 4);
+    }
+
+    #[cfg(feature = "futures_io")]
+    #[test]
+    fn plist_from_futures_reader() {
+        let mut message = plist::Dictionary::new();
+        message.insert("Request".into(), "QueryType".into());
+        let bytes = encode_bplist(&plist::Value::Dictionary(message.clone())).unwrap();
+
+        let mut reader = &bytes[..];
+        let decoded =
+            futures::executor::block_on(read_from_futures(&mut PlistDecoder::new(), &mut reader))
+                .unwrap();
+        assert_eq!(decoded, message);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn plist_rejects_garbage() {
+        let mut decoder = PlistDecoder::new();
+        decoder.push(&[0, 0, 0, 3, b'a', b'b', b'c']);
+        assert!(decoder.decode().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+// Jackson Coxson
+//! Adapters between `futures::io` and tokio's I/O traits
+//!
+//! The protocol code in this crate is written against `tokio::io::AsyncRead` and
+//! `AsyncWrite`. Those traits don't need a tokio runtime, so wrapping a stream from
+//! smol, async-std or an embedded executor in [`FuturesIo`] is enough to use it as an
+//! [`Idevice`](crate::Idevice) socket, or with any of the codecs that read from a
+//! generic reader such as DVT's `Message::from_reader`. [`TokioIo`] goes the other way,
+//! exposing a socket from this crate to code written against `futures::io`.
+//!
+//! Only the features that open sockets or spawn tasks themselves (`tcp`, `usbmuxd`,
+//! `tunnel_tcp_stack`, ...) and read/write timeouts need a tokio runtime. To leave the
+//! tokio crate out entirely, turn off the `tokio` feature and read the codecs with
+//! [`codec::read_from_futures`](crate::codec::read_from_futures) instead.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::ReadBuf;
+
+/// Exposes a `futures::io` stream through tokio's I/O traits
+///
+/// # Example
+/// ```rust,no_run
+/// use futures::io::{AsyncRead, AsyncWrite};
+/// use idevice::{compat::FuturesIo, Idevice};
+///
+/// // e.g. a smol::net::TcpStream connected to lockdownd
+/// fn wrap<S>(stream: S) -> Idevice
+/// where
+///     S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+/// {
+///     Idevice::new(Box::new(FuturesIo::new(stream)), "my-app")
+/// }
+/// ```
+pub struct FuturesIo<T> {
+    inner: T,
+}
+
+/// Exposes a tokio stream through the `futures::io` traits
+pub struct TokioIo<T> {
+    inner: T,
+}
+
+impl<T> FuturesIo<T> {
+    /// Wraps a `futures::io` stream
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped stream
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped stream
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the stream
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> TokioIo<T> {
+    /// Wraps a tokio stream
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped stream
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped stream
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the stream
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+// Not every stream implements Debug, but ReadWrite requires it
+impl<T> std::fmt::Debug for FuturesIo<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuturesIo").finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Debug for TokioIo<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokioIo").finish_non_exhaustive()
+    }
+}
+
+impl<T: futures::io::AsyncRead + Unpin> tokio::io::AsyncRead for FuturesIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let unfilled = buf.initialize_unfilled();
+        match Pin::new(&mut self.inner).poll_read(cx, unfilled) {
+            Poll::Ready(Ok(n)) => {
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: futures::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for FuturesIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> futures::io::AsyncRead for TokioIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> futures::io::AsyncWrite for TokioIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Idevice;
+
+    #[test]
+    fn plist_without_runtime() {
+        // The futures executor stands in for any non-tokio executor; the duplex is
+        // only used as an in-memory pipe and doesn't need a runtime either.
+        let (host, device) = tokio::io::duplex(1024);
+        let host = FuturesIo::new(TokioIo::new(host));
+        let device = FuturesIo::new(TokioIo::new(device));
+        let mut host = Idevice::new(Box::new(host), "host");
+        let mut device = Idevice::new(Box::new(device), "device");
+
+        futures::executor::block_on(async {
+            let mut req = plist::Dictionary::new();
+            req.insert("Request".into(), "QueryType".into());
+            let (sent, received) = futures::join!(
+                host.send_plist(plist::Value::Dictionary(req)),
+                device.read_plist()
+            );
+            sent.unwrap();
+            assert_eq!(
+                received.unwrap().get("Request").and_then(|x| x.as_string()),
+                Some("QueryType")
+            );
+        });
+    }
+}
@@ -1,12 +1,30 @@
 #![doc = include_str!("../README.md")]
 // Jackson Coxson
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
 #[cfg(feature = "pair")]
 mod ca;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod codec;
+#[cfg(all(feature = "futures_io", feature = "tokio"))]
+pub mod compat;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod pairing_file;
+#[cfg(feature = "tokio")]
 mod plist_macro;
+#[cfg(feature = "tokio")]
 pub mod provider;
+#[cfg(feature = "tokio")]
+pub mod reconnect;
+#[cfg(feature = "tokio")]
 mod sni;
+#[cfg(feature = "tokio")]
+mod timeout;
 #[cfg(feature = "tunnel_tcp_stack")]
 pub mod tcp;
 #[cfg(feature = "tss")]
@@ -20,25 +38,29 @@ mod util;
 pub mod xpc;
 
 pub mod services;
+// Only the codecs are left without tokio, and not every feature has one
+#[cfg_attr(not(feature = "tokio"), allow(unused_imports))]
 pub use services::*;
 
-#[cfg(feature = "xpc")]
+#[cfg(all(feature = "xpc", feature = "tokio"))]
 pub use xpc::RemoteXpcClient;
 
+use std::io;
+use thiserror::Error;
+
+#[cfg(feature = "tokio")]
 use log::{debug, error, trace, warn};
+#[cfg(feature = "tokio")]
 use provider::{IdeviceProvider, RsdProvider};
+#[cfg(feature = "tokio")]
 use rustls::{crypto::CryptoProvider, pki_types::ServerName};
-use std::{
-    io::{self, BufWriter},
-    sync::Arc,
-};
-use thiserror::Error;
+#[cfg(feature = "tokio")]
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub use util::{pretty_print_dictionary, pretty_print_plist};
 
-use crate::services::lockdown::LockdownClient;
-
 /// A trait combining all required characteristics for a device communication socket
 ///
 /// This serves as a convenience trait for any type that can be used as an asynchronous
@@ -46,15 +68,18 @@ use crate::services::lockdown::LockdownClient;
 /// thread safety and debugging requirements.
 ///
 /// Tokio's TcpStream and UnixStream implement this trait.
+#[cfg(feature = "tokio")]
 pub trait ReadWrite: AsyncRead + AsyncWrite + Unpin + Send + Sync + std::fmt::Debug {}
 
 // Blanket implementation for any compatible type
+#[cfg(feature = "tokio")]
 impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + std::fmt::Debug> ReadWrite for T {}
 
 /// Interface for services that can be connected to on an iOS device
 ///
 /// Implement this trait to define new services that can be accessed through the
 /// device connection protocol.
+#[cfg(feature = "tokio")]
 pub trait IdeviceService: Sized {
     /// Returns the service name as advertised by the device
     fn service_name() -> std::borrow::Cow<'static, str>;
@@ -70,14 +95,10 @@ pub trait IdeviceService: Sized {
     // │ │ │    `#[warn(async_fn_in_trait)]` on by default rustc (async_fn_in_trait) [66, 5]
     #[allow(async_fn_in_trait)]
     async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
-        let mut lockdown = LockdownClient::connect(provider).await?;
-        lockdown
-            .start_session(&provider.get_pairing_file().await?)
-            .await?;
-
-        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+        let (port, ssl) = provider.start_service(Self::service_name()).await?;
 
         let mut idevice = provider.connect(port).await?;
+        idevice.set_service_name(Self::service_name());
         if ssl {
             idevice
                 .start_session(&provider.get_pairing_file().await?)
@@ -112,19 +133,34 @@ pub trait RsdService: Sized {
 ///
 /// Used to enable dynamic dispatch of different connection types while maintaining
 /// the required ReadWrite characteristics.
+#[cfg(feature = "tokio")]
 pub type IdeviceSocket = Box<dyn ReadWrite>;
 
 /// Main handle for communicating with an iOS device
 ///
 /// Manages the connection socket and provides methods for common device operations
 /// and message exchange.
+#[cfg(feature = "tokio")]
 pub struct Idevice {
     /// The underlying connection socket, boxed for dynamic dispatch
     socket: Option<Box<dyn ReadWrite>>,
     /// Unique label identifying this connection
     label: String,
+    /// Read and write timeouts enforced by the socket
+    timeouts: Arc<timeout::IoTimeouts>,
+    /// The service on the other end, reported in device errors
+    service: Option<String>,
+    /// The `Request` or `Command` of the last plist sent, reported in device errors
+    last_request: Option<String>,
+    /// Records traffic, moved above TLS when a session starts
+    #[cfg(feature = "capture")]
+    recorder: Option<capture::ConnectionRecorder>,
+    /// Replayed connections are already decrypted, so sessions skip TLS
+    #[cfg(feature = "capture")]
+    replayed: bool,
 }
 
+#[cfg(feature = "tokio")]
 impl Idevice {
     /// Creates a new device connection handle
     ///
@@ -132,12 +168,84 @@ impl Idevice {
     /// * `socket` - The established connection socket
     /// * `label` - Unique identifier for this connection
     pub fn new(socket: Box<dyn ReadWrite>, label: impl Into<String>) -> Self {
+        let timeouts = Arc::new(timeout::IoTimeouts::default());
         Self {
-            socket: Some(socket),
+            socket: Some(Box::new(timeout::TimeoutStream::new(
+                socket,
+                timeouts.clone(),
+            ))),
             label: label.into(),
+            timeouts,
+            service: None,
+            last_request: None,
+            #[cfg(feature = "capture")]
+            recorder: None,
+            #[cfg(feature = "capture")]
+            replayed: false,
         }
     }
 
+    /// Starts recording the traffic on this connection
+    #[cfg(feature = "capture")]
+    pub(crate) fn attach_recorder(&mut self, recorder: capture::ConnectionRecorder) {
+        if let Some(socket) = self.socket.take() {
+            self.socket = Some(recorder.wrap(socket));
+        }
+        self.recorder = Some(recorder);
+    }
+
+    /// Sets the name of the service on the other end of the connection
+    ///
+    /// Errors returned by the device are tagged with it. Set automatically by
+    /// [`IdeviceService::connect`].
+    pub fn set_service_name(&mut self, service: impl Into<String>) {
+        self.service = Some(service.into());
+    }
+
+    /// Returns the name of the service on the other end of the connection, if known
+    pub fn service_name(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    /// Remembers the request being sent so device errors can refer to it
+    fn track_request(&mut self, message: &plist::Value) {
+        if let Some(request) = message
+            .as_dictionary()
+            .and_then(|d| d.get("Request").or_else(|| d.get("Command")))
+            .and_then(|r| r.as_string())
+        {
+            self.last_request = Some(request.to_string());
+        }
+    }
+
+    /// Sets how long a read may wait for data before failing with `IdeviceError::Timeout`
+    ///
+    /// Applies to every read on this connection, including those made by services built on it.
+    ///
+    /// # Arguments
+    /// * `timeout` - The timeout, or `None` to wait forever
+    pub fn set_read_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.timeouts.set_read(timeout);
+    }
+
+    /// Sets how long a write may wait for the device before failing with `IdeviceError::Timeout`
+    ///
+    /// # Arguments
+    /// * `timeout` - The timeout, or `None` to wait forever
+    pub fn set_write_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.timeouts.set_write(timeout);
+    }
+
+    /// Returns the read timeout, if one is set
+    pub fn read_timeout(&self) -> Option<std::time::Duration> {
+        self.timeouts.read()
+    }
+
+    /// Returns the write timeout, if one is set
+    pub fn write_timeout(&self) -> Option<std::time::Duration> {
+        self.timeouts.write()
+    }
+
     pub fn get_socket(self) -> Option<Box<dyn ReadWrite>> {
         self.socket
     }
@@ -207,17 +315,11 @@ impl Idevice {
     /// # Errors
     /// Returns `IdeviceError` if serialization or transmission fails
     async fn send_plist(&mut self, message: plist::Value) -> Result<(), IdeviceError> {
+        self.track_request(&message);
         if let Some(socket) = &mut self.socket {
             debug!("Sending plist: {}", pretty_print_plist(&message));
 
-            let buf = Vec::new();
-            let mut writer = BufWriter::new(buf);
-            message.to_writer_xml(&mut writer)?;
-            let message = writer.into_inner().unwrap();
-            let message = String::from_utf8(message)?;
-            let len = message.len() as u32;
-            socket.write_all(&len.to_be_bytes()).await?;
-            socket.write_all(message.as_bytes()).await?;
+            socket.write_all(&codec::encode_plist(&message)?).await?;
             socket.flush().await?;
             Ok(())
         } else {
@@ -233,16 +335,11 @@ impl Idevice {
     /// # Errors
     /// Returns `IdeviceError` if serialization or transmission fails
     async fn send_bplist(&mut self, message: plist::Value) -> Result<(), IdeviceError> {
+        self.track_request(&message);
         if let Some(socket) = &mut self.socket {
             debug!("Sending plist: {}", pretty_print_plist(&message));
 
-            let buf = Vec::new();
-            let mut writer = BufWriter::new(buf);
-            message.to_writer_binary(&mut writer)?;
-            let message = writer.into_inner().unwrap();
-            let len = message.len() as u32;
-            socket.write_all(&len.to_be_bytes()).await?;
-            socket.write_all(&message).await?;
+            socket.write_all(&codec::encode_bplist(&message)?).await?;
             socket.flush().await?;
             Ok(())
         } else {
@@ -340,6 +437,26 @@ impl Idevice {
         }
     }
 
+    /// Reads the next message from the device using a sans-IO decoder
+    ///
+    /// Only the bytes the decoder asks for are read, so the connection can be used for
+    /// something else afterwards.
+    ///
+    /// # Arguments
+    /// * `decoder` - The decoder to push the received bytes into
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if reading fails or the bytes can't be decoded
+    pub async fn read_decoded<D: codec::Decoder>(
+        &mut self,
+        decoder: &mut D,
+    ) -> Result<D::Item, IdeviceError> {
+        match &mut self.socket {
+            Some(socket) => codec::read_from(decoder, socket).await,
+            None => Err(IdeviceError::NoEstablishedConnection),
+        }
+    }
+
     /// Reads a plist-formatted message from the device
     ///
     /// # Returns
@@ -348,28 +465,19 @@ impl Idevice {
     /// # Errors
     /// Returns `IdeviceError` if reading, parsing fails, or device reports an error
     async fn read_plist(&mut self) -> Result<plist::Dictionary, IdeviceError> {
-        if let Some(socket) = &mut self.socket {
-            debug!("Reading response size");
-            let mut buf = [0u8; 4];
-            socket.read_exact(&mut buf).await?;
-            let len = u32::from_be_bytes(buf);
-            let mut buf = vec![0; len as usize];
-            socket.read_exact(&mut buf).await?;
-            let res: plist::Dictionary = plist::from_bytes(&buf)?;
-            debug!("Received plist: {}", pretty_print_dictionary(&res));
-
-            if let Some(e) = res.get("Error") {
-                let e: String = plist::from_value(e)?;
-                if let Some(e) = IdeviceError::from_device_error_type(e.as_str(), &res) {
-                    return Err(e);
-                } else {
-                    return Err(IdeviceError::UnknownErrorType(e));
-                }
-            }
-            Ok(res)
-        } else {
-            Err(IdeviceError::NoEstablishedConnection)
+        let res = self.read_decoded(&mut codec::PlistDecoder::new()).await?;
+        debug!("Received plist: {}", pretty_print_dictionary(&res));
+
+        if let Some(e) = res.get("Error") {
+            let e: String = plist::from_value(e)?;
+            return Err(IdeviceError::from_device_error(DeviceError {
+                service: self.service.clone(),
+                request: self.last_request.clone(),
+                error: e,
+                response: res,
+            }));
         }
+        Ok(res)
     }
 
     #[cfg(feature = "syslog_relay")]
@@ -415,56 +523,117 @@ impl Idevice {
         &mut self,
         pairing_file: &pairing_file::PairingFile,
     ) -> Result<(), IdeviceError> {
-        if CryptoProvider::get_default().is_none() {
-            // rust-analyzer will choke on this block, don't worry about it
-            let crypto_provider: CryptoProvider = {
-                #[cfg(all(feature = "ring", not(feature = "aws-lc")))]
-                {
-                    debug!("Using ring crypto backend");
-                    rustls::crypto::ring::default_provider()
-                }
-
-                #[cfg(all(feature = "aws-lc", not(feature = "ring")))]
-                {
-                    debug!("Using aws-lc crypto backend");
-                    rustls::crypto::aws_lc_rs::default_provider()
-                }
-
-                #[cfg(not(any(feature = "ring", feature = "aws-lc")))]
-                {
-                    compile_error!(
-                        "No crypto backend was selected! Specify an idevice feature for a crypto backend"
-                    );
-                }
-
-                #[cfg(all(feature = "ring", feature = "aws-lc"))]
-                {
-                    // We can't throw a compile error because it breaks rust-analyzer.
-                    // My sanity while debugging the workspace crates are more important.
-
-                    debug!("Using ring crypto backend, because both were passed");
-                    warn!("Both ring && aws-lc are selected as idevice crypto backends!");
-                    rustls::crypto::ring::default_provider()
-                }
-            };
-
-            if let Err(e) = CryptoProvider::install_default(crypto_provider) {
-                // For whatever reason, getting the default provider will return None on iOS at
-                // random. Installing the default provider a second time will return an error, so
-                // we will log it but not propogate it. An issue should be opened with rustls.
-                log::error!("Failed to set crypto provider: {e:?}");
-            }
+        #[cfg(feature = "capture")]
+        if self.replayed {
+            return Ok(());
         }
+
+        install_crypto_provider();
         let config = sni::create_client_config(pairing_file)?;
         let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
 
         let socket = self.socket.take().unwrap();
+
+        // The handshake isn't recorded, only the decrypted traffic above it
+        #[cfg(feature = "capture")]
+        if let Some(recorder) = &self.recorder {
+            recorder.pause();
+        }
+
         let socket = connector
             .connect(ServerName::try_from("Device").unwrap(), socket)
             .await?;
 
         self.socket = Some(Box::new(socket));
 
+        #[cfg(feature = "capture")]
+        if let Some(recorder) = &self.recorder {
+            self.socket = Some(recorder.wrap(self.socket.take().unwrap()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Installs the selected rustls crypto backend as the process default, if none is installed yet
+#[cfg(feature = "tokio")]
+pub(crate) fn install_crypto_provider() {
+    if CryptoProvider::get_default().is_none() {
+        // rust-analyzer will choke on this block, don't worry about it
+        let crypto_provider: CryptoProvider = {
+            #[cfg(all(feature = "ring", not(feature = "aws-lc")))]
+            {
+                debug!("Using ring crypto backend");
+                rustls::crypto::ring::default_provider()
+            }
+
+            #[cfg(all(feature = "aws-lc", not(feature = "ring")))]
+            {
+                debug!("Using aws-lc crypto backend");
+                rustls::crypto::aws_lc_rs::default_provider()
+            }
+
+            #[cfg(not(any(feature = "ring", feature = "aws-lc")))]
+            {
+                compile_error!(
+                    "No crypto backend was selected! Specify an idevice feature for a crypto backend"
+                );
+            }
+
+            #[cfg(all(feature = "ring", feature = "aws-lc"))]
+            {
+                // We can't throw a compile error because it breaks rust-analyzer.
+                // My sanity while debugging the workspace crates are more important.
+
+                debug!("Using ring crypto backend, because both were passed");
+                warn!("Both ring && aws-lc are selected as idevice crypto backends!");
+                rustls::crypto::ring::default_provider()
+            }
+        };
+
+        if let Err(e) = CryptoProvider::install_default(crypto_provider) {
+            // For whatever reason, getting the default provider will return None on iOS at
+            // random. Installing the default provider a second time will return an error, so
+            // we will log it but not propogate it. An issue should be opened with rustls.
+            log::error!("Failed to set crypto provider: {e:?}");
+        }
+    }
+}
+
+/// An error reported by a service on the device, with the context it was reported in
+#[derive(Debug, Clone)]
+pub struct DeviceError {
+    /// The service that reported the error, if known
+    pub service: Option<String>,
+    /// The `Request` or `Command` the error was a response to, if known
+    pub request: Option<String>,
+    /// The `Error` string reported by the device
+    pub error: String,
+    /// The whole response containing the error
+    pub response: plist::Dictionary,
+}
+
+impl DeviceError {
+    /// Returns the human readable description sent along with the error, if any
+    pub fn description(&self) -> Option<&str> {
+        ["DetailedError", "ErrorDescription"]
+            .iter()
+            .find_map(|k| self.response.get(k).and_then(|x| x.as_string()))
+    }
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}`", self.error)?;
+        if let Some(service) = &self.service {
+            write!(f, " from {service}")?;
+        }
+        if let Some(request) = &self.request {
+            write!(f, " in response to {request}")?;
+        }
+        if let Some(description) = self.description() {
+            write!(f, ": {description}")?;
+        }
         Ok(())
     }
 }
@@ -475,7 +644,7 @@ impl Idevice {
 #[non_exhaustive]
 pub enum IdeviceError {
     #[error("device socket io failed")]
-    Socket(#[from] io::Error) = -1,
+    Socket(io::Error) = -1,
     #[error("PEM parse failed")]
     PemParseFailed(#[from] rustls::pki_types::pem::Error) = -2,
     #[error("TLS error")]
@@ -647,10 +816,13 @@ pub enum IdeviceError {
     #[error("failed to parse bytes as valid utf8")]
     Utf8Error = -56,
 
-    #[cfg(feature = "debug_proxy")]
+    #[cfg(any(feature = "afc", feature = "debug_proxy"))]
     #[error("invalid argument passed")]
     InvalidArgument = -57,
 
+    /// No longer returned for device error strings, which are reported as
+    /// [`IdeviceError::Device`]
+    #[deprecated(note = "device error strings are reported as `IdeviceError::Device`")]
     #[error("unknown error `{0}` returned from device")]
     UnknownErrorType(String) = -59,
 
@@ -664,48 +836,126 @@ pub enum IdeviceError {
     UnsupportedWatchKey = -63,
     #[error("malformed command")]
     MalformedCommand = -64,
+
+    #[cfg(feature = "mdns")]
+    #[error("mdns error: {0}")]
+    Mdns(#[from] mdns_sd::Error) = -65,
+
+    #[cfg(feature = "pair")]
+    #[error("supervised pairing challenge required")]
+    PairingChallengeRequired(Vec<u8>) = -66,
+    #[cfg(feature = "pair")]
+    #[error("certificate error: {0}")]
+    CertificateError(String) = -67,
+
+    #[error("pairing file is missing field `{0}`")]
+    PairingFileMissingField(String) = -68,
+
+    #[error("operation timed out")]
+    Timeout = -69,
+
+    /// An error string from the device that doesn't map to another variant. Before structured
+    /// device errors these were reported as [`IdeviceError::UnknownErrorType`], code -59.
+    #[error("device returned error {0}")]
+    Device(Box<DeviceError>) = -70,
+
+    #[cfg(feature = "installation_proxy")]
+    #[error("application verification failed: {0}")]
+    ApplicationVerificationFailed(Box<DeviceError>) = -71,
+    #[cfg(feature = "installation_proxy")]
+    #[error("installing apps is prohibited: {0}")]
+    InstallProhibited(Box<DeviceError>) = -72,
+    #[cfg(feature = "installation_proxy")]
+    #[error("uninstalling this app is prohibited: {0}")]
+    UninstallProhibited(Box<DeviceError>) = -73,
+    #[cfg(feature = "installation_proxy")]
+    #[error("the iOS version is too low for this app: {0}")]
+    DeviceOsVersionTooLow(Box<DeviceError>) = -74,
+    #[cfg(feature = "installation_proxy")]
+    #[error("the app doesn't support this device's architecture: {0}")]
+    IncorrectArchitecture(Box<DeviceError>) = -75,
+    #[cfg(feature = "installation_proxy")]
+    #[error("the package couldn't be inspected: {0}")]
+    PackageInspectionFailed(Box<DeviceError>) = -76,
+
+    #[cfg(feature = "mobile_image_mounter")]
+    #[error("image mount failed: {0}")]
+    ImageMountFailed(Box<DeviceError>) = -77,
+}
+
+impl From<io::Error> for IdeviceError {
+    /// Converts socket errors, surfacing timeouts as `IdeviceError::Timeout`
+    fn from(value: io::Error) -> Self {
+        if value.kind() == io::ErrorKind::TimedOut {
+            IdeviceError::Timeout
+        } else {
+            IdeviceError::Socket(value)
+        }
+    }
 }
 
 impl IdeviceError {
-    /// Converts a device-reported error string to a typed error
+    /// Converts an error reported by the device to a typed error
     ///
     /// # Arguments
-    /// * `e` - The error string from device
-    /// * `context` - Full plist context containing additional error details
+    /// * `e` - The error string from the device, with the context it was reported in
     ///
     /// # Returns
-    /// Some(IdeviceError) if the string maps to a known error type, None otherwise
-    fn from_device_error_type(e: &str, context: &plist::Dictionary) -> Option<Self> {
-        match e {
-            "GetProhibited" => Some(Self::GetProhibited),
-            "InvalidHostID" => Some(Self::InvalidHostID),
-            "SessionInactive" => Some(Self::SessionInactive),
-            "DeviceLocked" => Some(Self::DeviceLocked),
+    /// The matching variant, or [`IdeviceError::Device`] if there is none
+    #[cfg(feature = "tokio")]
+    fn from_device_error(e: DeviceError) -> Self {
+        match e.error.as_str() {
+            "GetProhibited" => Self::GetProhibited,
+            "InvalidHostID" => Self::InvalidHostID,
+            "SessionInactive" => Self::SessionInactive,
+            "DeviceLocked" => Self::DeviceLocked,
+            #[cfg(feature = "pair")]
+            "PairingDialogResponsePending" => Self::PairingDialogResponsePending,
             #[cfg(feature = "pair")]
-            "PairingDialogResponsePending" => Some(Self::PairingDialogResponsePending),
+            "UserDeniedPairing" => Self::UserDeniedPairing,
             #[cfg(feature = "pair")]
-            "UserDeniedPairing" => Some(Self::UserDeniedPairing),
+            "PasswordProtected" => Self::PasswordProtected,
             #[cfg(feature = "pair")]
-            "PasswordProtected" => Some(Self::PasswordProtected),
-            "UnsupportedWatchKey" => Some(Self::UnsupportedWatchKey),
-            "MalformedCommand" => Some(Self::MalformedCommand),
+            "MCChallengeRequired" => match e
+                .response
+                .get("ExtendedResponse")
+                .and_then(|x| x.as_dictionary())
+                .and_then(|x| x.get("PairingChallenge"))
+                .and_then(|x| x.as_data())
+            {
+                Some(challenge) => Self::PairingChallengeRequired(challenge.to_vec()),
+                None => Self::Device(Box::new(e)),
+            },
+            "UnsupportedWatchKey" => Self::UnsupportedWatchKey,
+            "MalformedCommand" => Self::MalformedCommand,
             "InternalError" => {
-                let detailed_error = context
-                    .get("DetailedError")
-                    .and_then(|d| d.as_string())
-                    .unwrap_or("No context")
-                    .to_string();
+                let detailed_error = e.description().unwrap_or("No context");
 
                 if detailed_error.contains("There is no matching entry in the device map for") {
-                    Some(Self::ImageNotMounted)
+                    Self::ImageNotMounted
                 } else {
-                    Some(Self::InternalError(detailed_error))
+                    Self::InternalError(detailed_error.to_string())
                 }
             }
-            _ => None,
+            #[cfg(feature = "installation_proxy")]
+            "ApplicationVerificationFailed" => Self::ApplicationVerificationFailed(Box::new(e)),
+            #[cfg(feature = "installation_proxy")]
+            "InstallProhibited" => Self::InstallProhibited(Box::new(e)),
+            #[cfg(feature = "installation_proxy")]
+            "UninstallProhibited" => Self::UninstallProhibited(Box::new(e)),
+            #[cfg(feature = "installation_proxy")]
+            "DeviceOSVersionTooLow" => Self::DeviceOsVersionTooLow(Box::new(e)),
+            #[cfg(feature = "installation_proxy")]
+            "IncorrectArchitecture" => Self::IncorrectArchitecture(Box::new(e)),
+            #[cfg(feature = "installation_proxy")]
+            "PackageInspectionFailed" => Self::PackageInspectionFailed(Box::new(e)),
+            #[cfg(feature = "mobile_image_mounter")]
+            "ImageMountFailed" => Self::ImageMountFailed(Box::new(e)),
+            _ => Self::Device(Box::new(e)),
         }
     }
 
+    #[allow(deprecated)]
     pub fn code(&self) -> i32 {
         match self {
             IdeviceError::Socket(_) => -1,
@@ -805,7 +1055,7 @@ impl IdeviceError {
             IdeviceError::NotEnoughBytes(_, _) => -55,
             IdeviceError::Utf8Error => -56,
 
-            #[cfg(feature = "debug_proxy")]
+            #[cfg(any(feature = "afc", feature = "debug_proxy"))]
             IdeviceError::InvalidArgument => -57,
 
             IdeviceError::UnknownErrorType(_) => -59,
@@ -814,6 +1064,35 @@ impl IdeviceError {
             IdeviceError::FfiBufferTooSmall(_, _) => -62,
             IdeviceError::UnsupportedWatchKey => -63,
             IdeviceError::MalformedCommand => -64,
+
+            #[cfg(feature = "mdns")]
+            IdeviceError::Mdns(_) => -65,
+
+            #[cfg(feature = "pair")]
+            IdeviceError::PairingChallengeRequired(_) => -66,
+            #[cfg(feature = "pair")]
+            IdeviceError::CertificateError(_) => -67,
+
+            IdeviceError::PairingFileMissingField(_) => -68,
+
+            IdeviceError::Timeout => -69,
+            IdeviceError::Device(_) => -70,
+
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::ApplicationVerificationFailed(_) => -71,
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::InstallProhibited(_) => -72,
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::UninstallProhibited(_) => -73,
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::DeviceOsVersionTooLow(_) => -74,
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::IncorrectArchitecture(_) => -75,
+            #[cfg(feature = "installation_proxy")]
+            IdeviceError::PackageInspectionFailed(_) => -76,
+
+            #[cfg(feature = "mobile_image_mounter")]
+            IdeviceError::ImageMountFailed(_) => -77,
         }
     }
 }
@@ -0,0 +1,236 @@
+//! Network device discovery over mDNS
+//!
+//! Devices with Wi-Fi connections enabled advertise themselves as `_apple-mobdev2._tcp`.
+//! The instance name starts with the device's Wi-Fi MAC address, which is matched against
+//! the `WiFiMACAddress` of known pairing files to build a [`TcpProvider`].
+
+use std::{collections::HashMap, net::IpAddr};
+
+use log::{debug, warn};
+use mdns_sd::{Receiver, ResolvedService, ServiceDaemon, ServiceEvent};
+
+use crate::{pairing_file::PairingFile, provider::TcpProvider, IdeviceError};
+
+/// The service type advertised by devices with Wi-Fi connections enabled
+pub const SERVICE_TYPE: &str = "_apple-mobdev2._tcp.local.";
+
+/// Browses the network for devices matching a set of pairing files
+pub struct MdnsBrowser {
+    daemon: ServiceDaemon,
+    receiver: Receiver<ServiceEvent>,
+    pairing_files: HashMap<String, PairingFile>,
+    label: String,
+}
+
+impl MdnsBrowser {
+    /// Starts browsing for devices
+    ///
+    /// # Arguments
+    /// * `pairing_files` - Pairing files of the devices to look for
+    /// * `label` - Label given to the created providers
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::Mdns` if the mDNS daemon couldn't be started
+    pub fn new(
+        pairing_files: impl IntoIterator<Item = PairingFile>,
+        label: impl Into<String>,
+    ) -> Result<Self, IdeviceError> {
+        let pairing_files = pairing_files
+            .into_iter()
+            .map(|p| (p.wifi_mac_address.to_lowercase(), p))
+            .collect();
+
+        let daemon = ServiceDaemon::new()?;
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+
+        Ok(Self {
+            daemon,
+            receiver,
+            pairing_files,
+            label: label.into(),
+        })
+    }
+
+    /// Waits for the next known device to be resolved on the network
+    ///
+    /// Devices without a matching pairing file are skipped.
+    ///
+    /// # Returns
+    /// A provider connecting to the device over TCP
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::Mdns` if the mDNS daemon stopped
+    pub async fn next(&mut self) -> Result<TcpProvider, IdeviceError> {
+        loop {
+            let event = self
+                .receiver
+                .recv_async()
+                .await
+                .map_err(|e| mdns_sd::Error::Msg(e.to_string()))?;
+
+            if let ServiceEvent::ServiceResolved(service) = event
+                && let Some(provider) = self.match_service(&service)
+            {
+                return Ok(provider);
+            }
+        }
+    }
+
+    fn match_service(&self, service: &ResolvedService) -> Option<TcpProvider> {
+        let mac = mac_from_fullname(service.get_fullname())?;
+        let pairing_file = match self.pairing_files.get(&mac) {
+            Some(p) => p,
+            None => {
+                debug!("No pairing file for {mac}, skipping");
+                return None;
+            }
+        };
+
+        let addr = match pick_address(service.get_addresses().iter().map(|a| a.to_ip_addr())) {
+            Some(a) => a,
+            None => {
+                warn!("{mac} has no routable address");
+                return None;
+            }
+        };
+
+        Some(TcpProvider {
+            addr,
+            pairing_file: pairing_file.clone(),
+            label: self.label.clone(),
+        })
+    }
+}
+
+impl Drop for MdnsBrowser {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.shutdown() {
+            debug!("Failed to shut down mDNS daemon: {e:?}");
+        }
+    }
+}
+
+/// Browses for a fixed amount of time and returns all known devices found
+///
+/// # Arguments
+/// * `pairing_files` - Pairing files of the devices to look for
+/// * `label` - Label given to the created providers
+/// * `duration` - How long to browse for
+///
+/// # Errors
+/// Returns `IdeviceError::Mdns` if the mDNS daemon couldn't be started
+pub async fn discover(
+    pairing_files: impl IntoIterator<Item = PairingFile>,
+    label: impl Into<String>,
+    duration: std::time::Duration,
+) -> Result<Vec<TcpProvider>, IdeviceError> {
+    let mut browser = MdnsBrowser::new(pairing_files, label)?;
+    let mut found: Vec<TcpProvider> = Vec::new();
+
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        let provider = match tokio::time::timeout_at(deadline, browser.next()).await {
+            Ok(p) => p?,
+            Err(_) => break,
+        };
+        if !found
+            .iter()
+            .any(|p| p.pairing_file.wifi_mac_address == provider.pairing_file.wifi_mac_address)
+        {
+            found.push(provider);
+        }
+    }
+
+    Ok(found)
+}
+
+/// Extracts the MAC address from an instance name like `aa:bb:cc:dd:ee:ff@fe80::1._apple-mobdev2._tcp.local.`
+fn mac_from_fullname(fullname: &str) -> Option<String> {
+    let (mac, _) = fullname.split_once('@')?;
+    let octets: Vec<&str> = mac.split(':').collect();
+    if octets.len() != 6
+        || !octets
+            .iter()
+            .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return None;
+    }
+    Some(mac.to_lowercase())
+}
+
+/// Prefers IPv4, falling back to IPv6 addresses that don't need a scope ID
+fn pick_address(addrs: impl Iterator<Item = IpAddr>) -> Option<IpAddr> {
+    let addrs: Vec<IpAddr> = addrs.collect();
+    addrs.iter().find(|a| a.is_ipv4()).copied().or_else(|| {
+        addrs
+            .iter()
+            .find(|a| match a {
+                IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) != 0xfe80,
+                IpAddr::V4(_) => false,
+            })
+            .copied()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_from_instance_name() {
+        for (fullname, expected) in [
+            (
+                "aa:bb:cc:dd:ee:ff@fe80::1._apple-mobdev2._tcp.local.",
+                Some("aa:bb:cc:dd:ee:ff"),
+            ),
+            (
+                "AA:BB:CC:DD:EE:FF@fe80::1._apple-mobdev2._tcp.local.",
+                Some("aa:bb:cc:dd:ee:ff"),
+            ),
+            ("aa:bb:cc:dd:ee:ff@", Some("aa:bb:cc:dd:ee:ff")),
+            ("aa:bb:cc:dd:ee:ff._apple-mobdev2._tcp.local.", None),
+            ("aa:bb:cc:dd:ee@fe80::1._apple-mobdev2._tcp.local.", None),
+            (
+                "aa:bb:cc:dd:ee:ff:00@fe80::1._apple-mobdev2._tcp.local.",
+                None,
+            ),
+            (":::::@fe80::1._apple-mobdev2._tcp.local.", None),
+            ("gg:bb:cc:dd:ee:ff@fe80::1._apple-mobdev2._tcp.local.", None),
+            ("a:bb:cc:dd:ee:fff@fe80::1._apple-mobdev2._tcp.local.", None),
+            ("@fe80::1._apple-mobdev2._tcp.local.", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                mac_from_fullname(fullname).as_deref(),
+                expected,
+                "{fullname}"
+            );
+        }
+    }
+
+    #[test]
+    fn address_preference() {
+        let v4: IpAddr = "192.168.1.20".parse().unwrap();
+        let global: IpAddr = "2001:db8::20".parse().unwrap();
+        let unique_local: IpAddr = "fd00::20".parse().unwrap();
+        let link_local: IpAddr = "fe80::20".parse().unwrap();
+        let link_local_upper: IpAddr = "febf::20".parse().unwrap();
+
+        for (addrs, expected) in [
+            (vec![link_local, global, v4], Some(v4)),
+            (vec![v4, global], Some(v4)),
+            (vec![link_local, global], Some(global)),
+            (vec![link_local, unique_local], Some(unique_local)),
+            (vec![global, unique_local], Some(global)),
+            (vec![link_local], None),
+            (vec![link_local, link_local_upper], None),
+            (vec![], None),
+        ] {
+            assert_eq!(
+                pick_address(addrs.clone().into_iter()),
+                expected,
+                "{addrs:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,538 @@
+// Jackson Coxson
+// Stub for com.apple.afc, backed by an in-memory filesystem
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    afc::{
+        errors::AfcError,
+        opcode::{AfcFopenMode, AfcOpcode, LinkType},
+        packet::{AfcPacket, AfcPacketHeader},
+        MAGIC,
+    },
+    Idevice, IdeviceError,
+};
+
+use super::{lock, MockService, MockServiceFuture};
+
+#[derive(Clone, Debug)]
+struct Node {
+    /// The file's contents, or `None` for a directory
+    data: Option<Vec<u8>>,
+    /// The target of a symbolic link
+    link: Option<String>,
+    created: i64,
+    modified: i64,
+}
+
+impl Node {
+    fn new(data: Option<Vec<u8>>) -> Self {
+        let now = now();
+        Self {
+            data,
+            link: None,
+            created: now,
+            modified: now,
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+/// Normalizes a path to start with a single `/` and have no trailing `/`
+fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|p| !p.is_empty() && *p != ".")
+        .collect();
+    format!("/{}", parts.join("/"))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+/// Returns whether `path` is `dir` or inside it
+fn is_within(path: &str, dir: &str) -> bool {
+    path == dir || dir == "/" || path.starts_with(&format!("{dir}/"))
+}
+
+/// Parses the null-separated strings of a header payload
+fn strings(payload: &[u8]) -> Vec<String> {
+    payload
+        .split(|b| *b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+fn u64_at(payload: &[u8], index: usize) -> Option<u64> {
+    payload
+        .get(index * 8..index * 8 + 8)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    path: String,
+    position: usize,
+    append: bool,
+    /// Whether the file is locked, and if so whether exclusively
+    lock: Option<bool>,
+}
+
+/// Serves AFC requests from an in-memory filesystem
+///
+/// Clones share the same filesystem, so a test can keep a handle to inspect what a client did.
+#[derive(Clone, Debug)]
+pub struct AfcStub {
+    nodes: Arc<Mutex<BTreeMap<String, Node>>>,
+}
+
+impl Default for AfcStub {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert("/".to_string(), Node::new(None));
+        Self {
+            nodes: Arc::new(Mutex::new(nodes)),
+        }
+    }
+}
+
+impl AfcStub {
+    /// Creates a stub with an empty filesystem
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory, creating its parents
+    pub fn with_dir(self, path: &str) -> Self {
+        make_dirs(&mut lock(&self.nodes), &normalize(path));
+        self
+    }
+
+    /// Adds a file, creating its parent directories
+    pub fn with_file(self, path: &str, data: impl Into<Vec<u8>>) -> Self {
+        let path = normalize(path);
+        let mut nodes = lock(&self.nodes);
+        make_dirs(&mut nodes, parent(&path));
+        nodes.insert(path, Node::new(Some(data.into())));
+        drop(nodes);
+        self
+    }
+
+    /// Returns the contents of a file
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        lock(&self.nodes)
+            .get(&normalize(path))
+            .and_then(|n| n.data.clone())
+    }
+
+    /// Returns whether a file or directory exists
+    pub fn contains(&self, path: &str) -> bool {
+        lock(&self.nodes).contains_key(&normalize(path))
+    }
+
+    /// Handles a request, returning the response's opcode, header payload and payload
+    fn handle(
+        &self,
+        req: &AfcPacket,
+        files: &mut HashMap<u64, OpenFile>,
+        next_fd: &mut u64,
+    ) -> Result<(AfcOpcode, Vec<u8>, Vec<u8>), AfcError> {
+        let mut nodes = lock(&self.nodes);
+        let args = strings(&req.header_payload);
+        let path = args.first().map(|p| normalize(p)).unwrap_or_default();
+        let success = Ok((AfcOpcode::Status, 0u64.to_le_bytes().to_vec(), Vec::new()));
+
+        match req.header.operation {
+            AfcOpcode::ReadDir => {
+                if nodes
+                    .get(&path)
+                    .ok_or(AfcError::ObjectNotFound)?
+                    .data
+                    .is_some()
+                {
+                    return Err(AfcError::InvalidArg);
+                }
+                let mut payload = b".\0..\0".to_vec();
+                for child in nodes.keys().filter(|k| *k != "/" && parent(k) == path) {
+                    payload.extend(child.rsplit('/').next().unwrap_or_default().as_bytes());
+                    payload.push(0);
+                }
+                Ok((AfcOpcode::Data, Vec::new(), payload))
+            }
+            AfcOpcode::MakeDir => {
+                if nodes.get(&path).is_some_and(|n| n.data.is_some()) {
+                    return Err(AfcError::ObjectExists);
+                }
+                make_dirs(&mut nodes, &path);
+                success
+            }
+            AfcOpcode::GetFileInfo => {
+                let node = nodes.get(&path).ok_or(AfcError::ObjectNotFound)?;
+                let children = nodes.keys().filter(|k| *k != "/" && parent(k) == path);
+                let (size, nlink, ifmt) = match (&node.data, &node.link) {
+                    (_, Some(target)) => (target.len(), 1, "S_IFLNK"),
+                    (Some(d), None) => (d.len(), 1, "S_IFREG"),
+                    (None, None) => (children.count() * 32 + 64, 2, "S_IFDIR"),
+                };
+                let mut info = vec![
+                    ("st_size", size.to_string()),
+                    ("st_blocks", size.div_ceil(512).to_string()),
+                    ("st_nlink", nlink.to_string()),
+                    ("st_ifmt", ifmt.to_string()),
+                    ("st_mtime", node.modified.to_string()),
+                    ("st_birthtime", node.created.to_string()),
+                ];
+                if let Some(target) = &node.link {
+                    info.push(("st_link_target", target.clone()));
+                }
+                let mut payload = Vec::new();
+                for (k, v) in info {
+                    payload.extend(k.as_bytes());
+                    payload.push(0);
+                    payload.extend(v.as_bytes());
+                    payload.push(0);
+                }
+                Ok((AfcOpcode::Data, Vec::new(), payload))
+            }
+            AfcOpcode::GetDevInfo => {
+                let used: usize = nodes
+                    .values()
+                    .filter_map(|n| n.data.as_ref())
+                    .map(Vec::len)
+                    .sum();
+                let total: usize = 64 * 1024 * 1024 * 1024;
+                let info = [
+                    ("Model", "iPhone14,2".to_string()),
+                    ("FSTotalBytes", total.to_string()),
+                    ("FSFreeBytes", (total - used).to_string()),
+                    ("FSBlockSize", "4096".to_string()),
+                ];
+                let mut payload = Vec::new();
+                for (k, v) in info {
+                    payload.extend(k.as_bytes());
+                    payload.push(0);
+                    payload.extend(v.as_bytes());
+                    payload.push(0);
+                }
+                Ok((AfcOpcode::Data, Vec::new(), payload))
+            }
+            AfcOpcode::RemovePath => {
+                if path == "/" {
+                    return Err(AfcError::PermDenied);
+                }
+                nodes.get(&path).ok_or(AfcError::ObjectNotFound)?;
+                if nodes.keys().any(|k| k != &path && is_within(k, &path)) {
+                    return Err(AfcError::DirNotEmpty);
+                }
+                nodes.remove(&path);
+                success
+            }
+            AfcOpcode::RemovePathAndContents => {
+                if path == "/" {
+                    return Err(AfcError::PermDenied);
+                }
+                nodes.get(&path).ok_or(AfcError::ObjectNotFound)?;
+                nodes.retain(|k, _| !is_within(k, &path));
+                success
+            }
+            AfcOpcode::RenamePath => {
+                let target = args
+                    .get(1)
+                    .map(|p| normalize(p))
+                    .ok_or(AfcError::InvalidArg)?;
+                nodes.get(&path).ok_or(AfcError::ObjectNotFound)?;
+                if !is_dir(&nodes, parent(&target)) {
+                    return Err(AfcError::ObjectNotFound);
+                }
+                let moved: Vec<String> = nodes
+                    .keys()
+                    .filter(|k| is_within(k, &path))
+                    .cloned()
+                    .collect();
+                for old in moved {
+                    if let Some(node) = nodes.remove(&old) {
+                        nodes.insert(format!("{target}{}", &old[path.len()..]), node);
+                    }
+                }
+                success
+            }
+            AfcOpcode::MakeLink => {
+                let kind = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let args = strings(&req.header_payload[8..]);
+                let [target, source] = args.as_slice() else {
+                    return Err(AfcError::InvalidArg);
+                };
+                let source = normalize(source);
+                if nodes.contains_key(&source) {
+                    return Err(AfcError::ObjectExists);
+                }
+                if !is_dir(&nodes, parent(&source)) {
+                    return Err(AfcError::ObjectNotFound);
+                }
+                let node = if kind == LinkType::Symlink as u64 {
+                    Node {
+                        link: Some(target.clone()),
+                        ..Node::new(Some(Vec::new()))
+                    }
+                } else {
+                    // Hard links get a copy, which is enough for tests that don't write
+                    // through both names
+                    let target = nodes.get(&normalize(target));
+                    match target.ok_or(AfcError::ObjectNotFound)? {
+                        Node { data: None, .. } => return Err(AfcError::ObjectIsDir),
+                        node => node.clone(),
+                    }
+                };
+                nodes.insert(source, node);
+                success
+            }
+            AfcOpcode::FileOpen => {
+                let mode = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let path =
+                    normalize(&String::from_utf8_lossy(&req.header_payload[8..]).replace('\0', ""));
+                let create = mode != AfcFopenMode::RdOnly as u64;
+                let truncate =
+                    mode == AfcFopenMode::WrOnly as u64 || mode == AfcFopenMode::Wr as u64;
+                let append =
+                    mode == AfcFopenMode::Append as u64 || mode == AfcFopenMode::RdAppend as u64;
+
+                match nodes.get_mut(&path) {
+                    Some(Node { data: None, .. }) => return Err(AfcError::ObjectIsDir),
+                    Some(node) => {
+                        if truncate {
+                            node.data = Some(Vec::new());
+                            node.modified = now();
+                        }
+                    }
+                    None if create => {
+                        if !is_dir(&nodes, parent(&path)) {
+                            return Err(AfcError::ObjectNotFound);
+                        }
+                        nodes.insert(path.clone(), Node::new(Some(Vec::new())));
+                    }
+                    None => return Err(AfcError::ObjectNotFound),
+                }
+
+                let fd = *next_fd;
+                *next_fd += 1;
+                files.insert(
+                    fd,
+                    OpenFile {
+                        path,
+                        position: 0,
+                        append,
+                        lock: None,
+                    },
+                );
+                Ok((
+                    AfcOpcode::FileOpenRes,
+                    fd.to_le_bytes().to_vec(),
+                    Vec::new(),
+                ))
+            }
+            AfcOpcode::Read => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let len = u64_at(&req.header_payload, 1).ok_or(AfcError::InvalidArg)? as usize;
+                let file = files.get_mut(&fd).ok_or(AfcError::InvalidArg)?;
+                let data = nodes
+                    .get(&file.path)
+                    .and_then(|n| n.data.as_ref())
+                    .ok_or(AfcError::ObjectNotFound)?;
+                let start = file.position.min(data.len());
+                let end = start.saturating_add(len).min(data.len());
+                file.position = end;
+                Ok((AfcOpcode::Data, Vec::new(), data[start..end].to_vec()))
+            }
+            AfcOpcode::Write => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let file = files.get_mut(&fd).ok_or(AfcError::InvalidArg)?;
+                let node = nodes.get_mut(&file.path).ok_or(AfcError::ObjectNotFound)?;
+                let data = node.data.as_mut().ok_or(AfcError::ObjectIsDir)?;
+                if file.append {
+                    file.position = data.len();
+                }
+                let end = file.position + req.payload.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[file.position..end].copy_from_slice(&req.payload);
+                file.position = end;
+                node.modified = now();
+                success
+            }
+            AfcOpcode::FileSeek => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let whence = u64_at(&req.header_payload, 1).ok_or(AfcError::InvalidArg)?;
+                let offset = u64_at(&req.header_payload, 2).ok_or(AfcError::InvalidArg)? as i64;
+                let file = files.get_mut(&fd).ok_or(AfcError::InvalidArg)?;
+                let len = nodes
+                    .get(&file.path)
+                    .and_then(|n| n.data.as_ref())
+                    .map(Vec::len)
+                    .ok_or(AfcError::ObjectNotFound)?;
+                let base = match whence {
+                    0 => 0,
+                    1 => file.position as i64,
+                    2 => len as i64,
+                    _ => return Err(AfcError::InvalidArg),
+                };
+                let position = base
+                    .checked_add(offset)
+                    .filter(|p| *p >= 0)
+                    .ok_or(AfcError::InvalidArg)?;
+                file.position = position as usize;
+                success
+            }
+            AfcOpcode::FileTell => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let file = files.get(&fd).ok_or(AfcError::InvalidArg)?;
+                Ok((
+                    AfcOpcode::FileTellRes,
+                    (file.position as u64).to_le_bytes().to_vec(),
+                    Vec::new(),
+                ))
+            }
+            AfcOpcode::FileSetSize => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let size = u64_at(&req.header_payload, 1).ok_or(AfcError::InvalidArg)?;
+                let file = files.get(&fd).ok_or(AfcError::InvalidArg)?;
+                let node = nodes.get_mut(&file.path).ok_or(AfcError::ObjectNotFound)?;
+                node.data
+                    .as_mut()
+                    .ok_or(AfcError::ObjectIsDir)?
+                    .resize(size as usize, 0);
+                node.modified = now();
+                success
+            }
+            AfcOpcode::Truncate => {
+                let size = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let path =
+                    normalize(&String::from_utf8_lossy(&req.header_payload[8..]).replace('\0', ""));
+                let node = nodes.get_mut(&path).ok_or(AfcError::ObjectNotFound)?;
+                node.data
+                    .as_mut()
+                    .ok_or(AfcError::ObjectIsDir)?
+                    .resize(size as usize, 0);
+                node.modified = now();
+                success
+            }
+            AfcOpcode::SetFileTime => {
+                let mtime = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let path =
+                    normalize(&String::from_utf8_lossy(&req.header_payload[8..]).replace('\0', ""));
+                nodes
+                    .get_mut(&path)
+                    .ok_or(AfcError::ObjectNotFound)?
+                    .modified = mtime as i64;
+                success
+            }
+            AfcOpcode::FileLock => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                let op = u64_at(&req.header_payload, 1).ok_or(AfcError::InvalidArg)?;
+                let path = &files.get(&fd).ok_or(AfcError::InvalidArg)?.path;
+                let lock = match op & !4 {
+                    1 => Some(false),
+                    2 => Some(true),
+                    8 => None,
+                    _ => return Err(AfcError::InvalidArg),
+                };
+                // Other handles on the same file conflict unless both locks are shared
+                let conflict = files
+                    .iter()
+                    .filter(|(f, o)| **f != fd && &o.path == path)
+                    .filter_map(|(_, o)| o.lock)
+                    .any(|held| lock.is_some_and(|exclusive| exclusive || held));
+                if conflict {
+                    return Err(AfcError::OpWouldBlock);
+                }
+                if let Some(file) = files.get_mut(&fd) {
+                    file.lock = lock;
+                }
+                success
+            }
+            AfcOpcode::SetFsBs | AfcOpcode::SetSocketBs => {
+                u64_at(&req.header_payload, 0)
+                    .filter(|s| *s > 0)
+                    .ok_or(AfcError::InvalidArg)?;
+                success
+            }
+            AfcOpcode::FileClose => {
+                let fd = u64_at(&req.header_payload, 0).ok_or(AfcError::InvalidArg)?;
+                files.remove(&fd).ok_or(AfcError::InvalidArg)?;
+                success
+            }
+            _ => Err(AfcError::UnknownPacketType),
+        }
+    }
+}
+
+fn is_dir(nodes: &BTreeMap<String, Node>, path: &str) -> bool {
+    nodes.get(path).is_some_and(|n| n.data.is_none())
+}
+
+/// Creates a directory and any missing parents
+fn make_dirs(nodes: &mut BTreeMap<String, Node>, path: &str) {
+    if path != "/" {
+        make_dirs(nodes, parent(path));
+    }
+    nodes
+        .entry(path.to_string())
+        .or_insert_with(|| Node::new(None));
+}
+
+impl MockService for AfcStub {
+    fn serve(&self, mut idevice: Idevice) -> MockServiceFuture {
+        let stub = self.clone();
+        Box::pin(async move {
+            let mut files = HashMap::new();
+            let mut next_fd = 1;
+            loop {
+                let req = match AfcPacket::read(&mut idevice).await {
+                    Ok(r) => r,
+                    Err(IdeviceError::Socket(_)) => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+
+                let (operation, header_payload, payload) =
+                    match stub.handle(&req, &mut files, &mut next_fd) {
+                        Ok(r) => r,
+                        Err(e) => (
+                            AfcOpcode::Status,
+                            (e as u64).to_le_bytes().to_vec(),
+                            Vec::new(),
+                        ),
+                    };
+
+                let header_payload_len = AfcPacketHeader::LEN + header_payload.len() as u64;
+                let res = AfcPacket {
+                    header: AfcPacketHeader {
+                        magic: MAGIC,
+                        entire_len: header_payload_len + payload.len() as u64,
+                        header_payload_len,
+                        packet_num: req.header.packet_num,
+                        operation,
+                    },
+                    header_payload,
+                    payload,
+                };
+                idevice.send_raw(&res.serialize()).await?;
+            }
+        })
+    }
+}
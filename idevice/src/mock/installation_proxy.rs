@@ -0,0 +1,141 @@
+// Jackson Coxson
+// Stub for com.apple.mobile.installation_proxy
+
+use std::sync::{Arc, Mutex};
+
+use crate::{Idevice, IdeviceError};
+
+use super::{lock, MockService, MockServiceFuture};
+
+/// Serves lookups and uninstalls from an in-memory list of installed apps
+///
+/// Clones share the same list of apps.
+#[derive(Clone, Debug, Default)]
+pub struct InstallationProxyStub {
+    apps: Arc<Mutex<plist::Dictionary>>,
+}
+
+impl InstallationProxyStub {
+    /// Creates a stub with no apps installed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an installed app
+    ///
+    /// # Arguments
+    /// * `bundle_id` - The app's bundle identifier
+    /// * `application_type` - `User` or `System`
+    /// * `info` - Extra keys of the app's info dictionary
+    pub fn with_app(
+        self,
+        bundle_id: impl Into<String>,
+        application_type: &str,
+        mut info: plist::Dictionary,
+    ) -> Self {
+        let bundle_id = bundle_id.into();
+        info.insert("CFBundleIdentifier".into(), bundle_id.clone().into());
+        info.insert("ApplicationType".into(), application_type.into());
+        lock(&self.apps).insert(bundle_id, plist::Value::Dictionary(info));
+        self
+    }
+
+    /// Returns the bundle identifiers of the installed apps
+    pub fn installed(&self) -> Vec<String> {
+        lock(&self.apps).keys().cloned().collect()
+    }
+
+    /// Returns the apps matching a command's client options
+    fn lookup(&self, options: Option<&plist::Dictionary>) -> plist::Dictionary {
+        let application_type = options
+            .and_then(|o| o.get("ApplicationType"))
+            .and_then(|x| x.as_string())
+            .unwrap_or("Any");
+        let bundle_ids: Option<Vec<&str>> = options
+            .and_then(|o| o.get("BundleIDs"))
+            .and_then(|x| x.as_array())
+            .map(|x| x.iter().filter_map(|x| x.as_string()).collect());
+
+        lock(&self.apps)
+            .iter()
+            .filter(|(id, info)| {
+                let kind = info
+                    .as_dictionary()
+                    .and_then(|i| i.get("ApplicationType"))
+                    .and_then(|x| x.as_string());
+                (application_type == "Any" || kind == Some(application_type))
+                    && bundle_ids
+                        .as_ref()
+                        .is_none_or(|ids| ids.contains(&id.as_str()))
+            })
+            .map(|(id, info)| (id.clone(), info.clone()))
+            .collect()
+    }
+}
+
+impl MockService for InstallationProxyStub {
+    fn serve(&self, mut idevice: Idevice) -> MockServiceFuture {
+        let stub = self.clone();
+        Box::pin(async move {
+            loop {
+                let req = match idevice.read_plist().await {
+                    Ok(r) => r,
+                    Err(IdeviceError::Socket(_)) => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+                let options = req.get("ClientOptions").and_then(|x| x.as_dictionary());
+
+                let mut res = plist::Dictionary::new();
+                match req.get("Command").and_then(|x| x.as_string()) {
+                    Some("Lookup") => {
+                        res.insert(
+                            "LookupResult".into(),
+                            plist::Value::Dictionary(stub.lookup(options)),
+                        );
+                        res.insert("Status".into(), "Complete".into());
+                    }
+                    Some("Browse") => {
+                        let apps = stub.lookup(options).into_iter().map(|(_, v)| v).collect();
+                        res.insert("CurrentList".into(), plist::Value::Array(apps));
+                        res.insert("Status".into(), "Complete".into());
+                    }
+                    Some("Uninstall") => {
+                        let id = req
+                            .get("ApplicationIdentifier")
+                            .and_then(|x| x.as_string())
+                            .unwrap_or_default();
+                        let mut apps = lock(&stub.apps);
+                        let kind = apps
+                            .get(id)
+                            .and_then(|x| x.as_dictionary())
+                            .and_then(|x| x.get("ApplicationType"))
+                            .and_then(|x| x.as_string());
+                        if kind == Some("System") {
+                            res.insert("Error".into(), "UninstallProhibited".into());
+                            res.insert(
+                                "ErrorDescription".into(),
+                                format!("Uninstall of {id} is prohibited").into(),
+                            );
+                        } else if apps.remove(id).is_some() {
+                            res.insert("Status".into(), "Complete".into());
+                        } else {
+                            res.insert("Error".into(), "APIInternalError".into());
+                            res.insert(
+                                "ErrorDescription".into(),
+                                format!("Could not find {id}").into(),
+                            );
+                        }
+                    }
+                    c => {
+                        res.insert("Error".into(), "UnknownCommand".into());
+                        res.insert(
+                            "ErrorDescription".into(),
+                            format!("Unknown command {c:?}").into(),
+                        );
+                    }
+                }
+                idevice.send_plist(plist::Value::Dictionary(res)).await?;
+            }
+        })
+    }
+}
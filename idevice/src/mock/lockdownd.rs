@@ -0,0 +1,216 @@
+// Jackson Coxson
+// The device side of lockdownd
+
+use std::sync::{atomic::Ordering, Arc};
+
+use log::debug;
+use tokio::io::AsyncWriteExt;
+
+use crate::{codec, IdeviceError, ReadWrite};
+
+use super::{accept_tls_stream, identity, lock, MockDevice, MockState};
+
+/// The connection to the host, which is only secured by TLS while a session is active
+#[derive(Debug)]
+enum Connection {
+    Plain(Box<dyn ReadWrite>),
+    Session(Box<tokio_rustls::server::TlsStream<Box<dyn ReadWrite>>>),
+}
+
+impl Connection {
+    fn socket(&mut self) -> &mut dyn ReadWrite {
+        match self {
+            Self::Plain(s) => s.as_mut(),
+            Self::Session(s) => s.as_mut(),
+        }
+    }
+
+    async fn read(&mut self) -> Result<plist::Dictionary, IdeviceError> {
+        codec::read_from(&mut codec::PlistDecoder::new(), self.socket()).await
+    }
+
+    async fn send(&mut self, message: plist::Dictionary) -> Result<(), IdeviceError> {
+        let socket = self.socket();
+        socket
+            .write_all(&codec::encode_plist(&plist::Value::Dictionary(message))?)
+            .await?;
+        socket.flush().await?;
+        Ok(())
+    }
+
+    /// Secures the connection for a session
+    async fn start_session(self) -> Result<Self, IdeviceError> {
+        match self {
+            Self::Plain(s) => Ok(Self::Session(Box::new(accept_tls_stream(s).await?))),
+            Self::Session(_) => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Drops back to plaintext once a session is stopped, like lockdownd does
+    fn stop_session(self) -> Self {
+        match self {
+            Self::Plain(s) => Self::Plain(s),
+            Self::Session(s) => Self::Plain(s.into_inner().0),
+        }
+    }
+}
+
+/// Returns the HostID of the pair record sent with a request
+fn pair_record_host_id(req: &plist::Dictionary) -> Option<&str> {
+    req.get("PairRecord")
+        .and_then(|x| x.as_dictionary())
+        .and_then(|x| x.get("HostID"))
+        .and_then(|x| x.as_string())
+}
+
+/// Serves lockdownd requests until the host disconnects or says goodbye
+pub(super) async fn serve(
+    state: Arc<MockState>,
+    socket: Box<dyn ReadWrite>,
+) -> Result<(), IdeviceError> {
+    lock(&state.values)
+        .entry(String::new())
+        .or_default()
+        .insert(
+            "DevicePublicKey".into(),
+            plist::Value::Data(identity()?.device_public_key.clone()),
+        );
+
+    let generation = state.generation.load(Ordering::SeqCst);
+    let mut connection = Connection::Plain(socket);
+    loop {
+        let req = match connection.read().await {
+            Ok(r) => r,
+            Err(IdeviceError::Socket(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if state.generation.load(Ordering::SeqCst) != generation {
+            debug!("Mock lockdownd dropping the connection");
+            return Ok(());
+        }
+        let request = req
+            .get("Request")
+            .and_then(|x| x.as_string())
+            .unwrap_or_default()
+            .to_string();
+        debug!("Mock lockdownd got {request}");
+
+        let mut res = plist::Dictionary::new();
+        res.insert("Request".into(), request.clone().into());
+
+        let domain = req
+            .get("Domain")
+            .and_then(|x| x.as_string())
+            .unwrap_or_default()
+            .to_string();
+        let key = req.get("Key").and_then(|x| x.as_string());
+        let session = matches!(connection, Connection::Session(_));
+        let trusted = |host_id: Option<&str>| {
+            host_id == Some(MockDevice::HOST_ID) && !state.unpaired.load(Ordering::SeqCst)
+        };
+
+        let error = match request.as_str() {
+            "QueryType" => {
+                res.insert("Type".into(), "com.apple.mobile.lockdown".into());
+                None
+            }
+            "GetValue" => {
+                let values = lock(&state.values);
+                let value = match (values.get(&domain), key) {
+                    (Some(d), Some(k)) => d.get(k).cloned(),
+                    (Some(d), None) => Some(plist::Value::Dictionary(d.clone())),
+                    (None, _) => None,
+                };
+                match value {
+                    Some(v) => {
+                        res.insert("Value".into(), v);
+                        None
+                    }
+                    None => Some("MissingValue"),
+                }
+            }
+            "SetValue" => match (key, req.get("Value")) {
+                (Some(k), Some(v)) => {
+                    lock(&state.values)
+                        .entry(domain)
+                        .or_default()
+                        .insert(k.to_string(), v.clone());
+                    None
+                }
+                _ => Some("MissingValue"),
+            },
+            "RemoveValue" => {
+                let mut values = lock(&state.values);
+                match key {
+                    Some(k) => {
+                        if let Some(d) = values.get_mut(&domain) {
+                            d.remove(k);
+                        }
+                    }
+                    None => {
+                        values.remove(&domain);
+                    }
+                }
+                None
+            }
+            "StartSession" if session => Some("SessionActive"),
+            "StartSession" if trusted(req.get("HostID").and_then(|x| x.as_string())) => {
+                res.insert("SessionID".into(), "MOCK-SESSION".into());
+                res.insert("EnableSessionSSL".into(), true.into());
+                state.sessions_started.fetch_add(1, Ordering::SeqCst);
+                connection.send(res).await?;
+                connection = connection.start_session().await?;
+                continue;
+            }
+            "StartSession" => Some("InvalidHostID"),
+            "StopSession" if !session => Some("SessionInactive"),
+            "StopSession" => {
+                res.insert("Result".into(), "Success".into());
+                connection.send(res).await?;
+                connection = connection.stop_session();
+                continue;
+            }
+            "StartService" if !session => Some("SessionInactive"),
+            "StartService" => {
+                let service = req
+                    .get("Service")
+                    .and_then(|x| x.as_string())
+                    .unwrap_or_default();
+                match state.start_service(service) {
+                    Some((port, ssl)) => {
+                        res.insert("Service".into(), service.into());
+                        res.insert("Port".into(), port.into());
+                        res.insert("EnableServiceSSL".into(), ssl.into());
+                        None
+                    }
+                    None => Some("InvalidService"),
+                }
+            }
+            "ValidatePair" if trusted(pair_record_host_id(&req)) => None,
+            "ValidatePair" => Some("InvalidHostID"),
+            "Unpair" if trusted(pair_record_host_id(&req)) => {
+                state.unpaired.store(true, Ordering::SeqCst);
+                None
+            }
+            "Unpair" => Some("InvalidHostID"),
+            "Pair" => match req.get("PairRecord").and_then(|x| x.as_dictionary()) {
+                Some(_) => {
+                    res.insert("EscrowBag".into(), plist::Value::Data(b"escrow".to_vec()));
+                    None
+                }
+                None => Some("InvalidPairRecord"),
+            },
+            "EnterRecovery" => None,
+            "Goodbye" => {
+                connection.send(res).await?;
+                return Ok(());
+            }
+            _ => Some("UnknownRequest"),
+        };
+
+        if let Some(e) = error {
+            res.insert("Error".into(), e.into());
+        }
+        connection.send(res).await?;
+    }
+}
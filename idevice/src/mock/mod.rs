@@ -0,0 +1,626 @@
+//! Simulated device for testing without hardware
+//!
+//! [`MockDevice`] is an [`IdeviceProvider`] backed by in-process connections instead of a real
+//! device. It runs lockdownd on port 62078, with sessions secured by TLS using a generated
+//! pairing record, and starts scriptable stub services on the ports it hands out from
+//! `StartService`.
+//!
+//! ```rust,no_run
+//! use idevice::{mock::{AfcStub, MockDevice}, afc::AfcClient, IdeviceService};
+//!
+//! # async fn example() -> Result<(), idevice::IdeviceError> {
+//! let afc = AfcStub::new().with_file("/DCIM/test.txt", b"hello");
+//! let device = MockDevice::new().with_service("com.apple.afc", false, afc.clone());
+//!
+//! let mut client = AfcClient::connect(&device).await?;
+//! client.mk_dir("/Downloads").await?;
+//! assert!(afc.contains("/Downloads"));
+//! # Ok(())
+//! # }
+//! ```
+
+mod afc;
+mod installation_proxy;
+mod lockdownd;
+mod syslog_relay;
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+};
+
+use log::debug;
+use rsa::{
+    pkcs1::EncodeRsaPublicKey,
+    pkcs8::{EncodePrivateKey, LineEnding},
+    RsaPrivateKey, RsaPublicKey,
+};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use x509_cert::{builder::Profile, der::EncodePem};
+
+use crate::{
+    lockdown::LockdownClient, pairing_file::PairingFile, provider::IdeviceProvider, Idevice,
+    IdeviceError, ReadWrite,
+};
+
+pub use afc::AfcStub;
+pub use installation_proxy::InstallationProxyStub;
+pub use syslog_relay::SyslogRelayStub;
+
+/// The future returned by [`MockService::serve`]
+pub type MockServiceFuture = Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>>;
+
+/// A service the mock device can start
+///
+/// Implement this to script the device side of a service that doesn't have a stub yet.
+pub trait MockService: std::fmt::Debug + Send + Sync {
+    /// Serves a single connection to the service
+    ///
+    /// # Arguments
+    /// * `idevice` - The device's end of the connection, with TLS already set up if the service
+    ///   was registered with it
+    fn serve(&self, idevice: Idevice) -> MockServiceFuture;
+}
+
+/// The first port handed out by `StartService`
+const FIRST_SERVICE_PORT: u16 = 49152;
+
+/// The mock device's identity and the pairing record trusted by it
+#[derive(Debug)]
+struct MockIdentity {
+    pairing_file: PairingFile,
+    device_public_key: Vec<u8>,
+    server_config: Arc<rustls::ServerConfig>,
+}
+
+/// Serves the device certificate to every client
+#[derive(Debug)]
+struct DeviceCertResolver(Arc<rustls::sign::CertifiedKey>);
+
+impl rustls::server::ResolvesServerCert for DeviceCertResolver {
+    fn resolve(
+        &self,
+        _client_hello: rustls::server::ClientHello<'_>,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Returns the identity shared by every mock device in the process
+///
+/// Generating RSA keys takes several seconds in debug builds, so it's only done once.
+fn identity() -> Result<&'static MockIdentity, IdeviceError> {
+    static IDENTITY: OnceLock<MockIdentity> = OnceLock::new();
+    if let Some(identity) = IDENTITY.get() {
+        return Ok(identity);
+    }
+    let identity = generate_identity()?;
+    Ok(IDENTITY.get_or_init(|| identity))
+}
+
+fn generate_identity() -> Result<MockIdentity, IdeviceError> {
+    debug!("Generating mock device identity");
+    let cert_error = |e: Box<dyn std::error::Error>| IdeviceError::CertificateError(e.to_string());
+    let mut rng = rsa::rand_core::OsRng;
+    let root_key = RsaPrivateKey::new(&mut rng, 2048).map_err(|e| cert_error(e.into()))?;
+    let device_key = RsaPrivateKey::new(&mut rng, 2048).map_err(|e| cert_error(e.into()))?;
+    let root_public_key = RsaPublicKey::from(&root_key);
+    let device_public_key = RsaPublicKey::from(&device_key);
+
+    // The mock's own rustls client rejects the duplicate extensions the root profile adds, so
+    // the mock identity is issued with the manual profile instead
+    let make_cert = |public_key, common_name, is_ca| {
+        crate::ca::make_cert(
+            &root_key,
+            public_key,
+            Some(common_name),
+            is_ca,
+            Profile::Manual { issuer: None },
+        )
+        .and_then(|c| Ok(c.to_pem(LineEnding::LF)?.into_bytes()))
+        .map_err(cert_error)
+    };
+    let root_cert = make_cert(&root_public_key, "Root CA", true)?;
+    let host_cert = make_cert(&root_public_key, "Host", false)?;
+    let device_cert = make_cert(&device_public_key, "Device", false)?;
+    let root_key = root_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| cert_error(e.into()))?
+        .as_bytes()
+        .to_vec();
+
+    let mut record = plist::Dictionary::new();
+    record.insert(
+        "DeviceCertificate".into(),
+        plist::Value::Data(device_cert.clone()),
+    );
+    record.insert("HostCertificate".into(), plist::Value::Data(host_cert));
+    record.insert(
+        "HostPrivateKey".into(),
+        plist::Value::Data(root_key.clone()),
+    );
+    record.insert("RootCertificate".into(), plist::Value::Data(root_cert));
+    record.insert("RootPrivateKey".into(), plist::Value::Data(root_key));
+    record.insert("SystemBUID".into(), MockDevice::SYSTEM_BUID.into());
+    record.insert("HostID".into(), MockDevice::HOST_ID.into());
+    record.insert("WiFiMACAddress".into(), MockDevice::WIFI_ADDRESS.into());
+    record.insert("UDID".into(), MockDevice::UDID.into());
+    let pairing_file = PairingFile::from_value(&plist::Value::Dictionary(record))?;
+
+    crate::install_crypto_provider();
+    let device_cert =
+        CertificateDer::from_pem_slice(&device_cert).map_err(|e| cert_error(e.into()))?;
+    let device_key = PrivateKeyDer::Pkcs8(
+        device_key
+            .to_pkcs8_der()
+            .map_err(|e| cert_error(e.into()))?
+            .as_bytes()
+            .to_vec()
+            .into(),
+    );
+    // Device certificates carry extensions webpki rejects, so the certificate is served as is
+    // instead of going through `with_single_cert`
+    let signing_key = rustls::crypto::CryptoProvider::get_default()
+        .ok_or(IdeviceError::CertificateError(
+            "no crypto provider installed".to_string(),
+        ))?
+        .key_provider
+        .load_private_key(device_key)
+        .map_err(|e| cert_error(e.into()))?;
+    let server_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(DeviceCertResolver(Arc::new(
+            rustls::sign::CertifiedKey::new(vec![device_cert], signing_key),
+        ))));
+
+    Ok(MockIdentity {
+        pairing_file,
+        device_public_key: device_public_key
+            .to_pkcs1_pem(LineEnding::LF)
+            .map_err(|e| cert_error(e.into()))?
+            .into_bytes(),
+        server_config: Arc::new(server_config),
+    })
+}
+
+/// Wraps the device's end of a connection in TLS, like lockdownd does once a session starts
+async fn accept_tls_stream(
+    socket: Box<dyn ReadWrite>,
+) -> Result<tokio_rustls::server::TlsStream<Box<dyn ReadWrite>>, IdeviceError> {
+    let acceptor = tokio_rustls::TlsAcceptor::from(identity()?.server_config.clone());
+    Ok(acceptor.accept(socket).await?)
+}
+
+/// Wraps the device's end of a service connection in TLS
+async fn accept_tls(idevice: Idevice) -> Result<Idevice, IdeviceError> {
+    let socket = idevice
+        .get_socket()
+        .ok_or(IdeviceError::NoEstablishedConnection)?;
+    let socket = accept_tls_stream(socket).await?;
+    Ok(Idevice::new(Box::new(socket), "mock"))
+}
+
+/// Locks a mutex, ignoring poisoning
+///
+/// A stub that panicked mid-request shouldn't take every other connection down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug)]
+struct RegisteredService {
+    ssl: bool,
+    service: Arc<dyn MockService>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// Lockdown values by domain, with the root domain stored under an empty string
+    values: Mutex<HashMap<String, plist::Dictionary>>,
+    services: Mutex<HashMap<String, RegisteredService>>,
+    /// Whether the host has revoked the mock pairing record with `Unpair`
+    unpaired: AtomicBool,
+    /// How many sessions lockdownd has started
+    sessions_started: AtomicUsize,
+    /// Bumped to close every lockdownd connection opened before
+    generation: AtomicUsize,
+    /// Ports handed out by `StartService` that haven't been connected to yet
+    pending: Mutex<HashMap<u16, String>>,
+    next_port: Mutex<u16>,
+}
+
+/// A simulated device, usable anywhere an [`IdeviceProvider`] is
+///
+/// Clones share the same device state.
+#[derive(Clone, Debug)]
+pub struct MockDevice {
+    state: Arc<MockState>,
+    label: String,
+}
+
+impl MockDevice {
+    /// The UDID reported by the device
+    pub const UDID: &'static str = "00008030-001A2D3E4F5A6B7C";
+    /// The Wi-Fi MAC address reported by the device
+    pub const WIFI_ADDRESS: &'static str = "aa:bb:cc:dd:ee:ff";
+    /// The HostID of the pairing record trusted by the device
+    pub const HOST_ID: &'static str = "5D8F6E1A-3B2C-4D7E-9F0A-1B2C3D4E5F60";
+    /// The SystemBUID of the pairing record trusted by the device
+    pub const SYSTEM_BUID: &'static str = "8E2D1C3B-4A5F-6E7D-8C9B-0A1B2C3D4E5F";
+
+    /// Creates a device with the values of an iPhone on iOS 17 and no services
+    pub fn new() -> Self {
+        let mut root = plist::Dictionary::new();
+        root.insert("DeviceName".into(), "Mock iPhone".into());
+        root.insert("DeviceClass".into(), "iPhone".into());
+        root.insert("ProductType".into(), "iPhone14,2".into());
+        root.insert("ProductVersion".into(), "17.0".into());
+        root.insert("BuildVersion".into(), "21A329".into());
+        root.insert("HardwareModel".into(), "D63AP".into());
+        root.insert("CPUArchitecture".into(), "arm64e".into());
+        root.insert("ActivationState".into(), "Activated".into());
+        root.insert("SerialNumber".into(), "F2LXK0AAAAAA".into());
+        root.insert("UniqueDeviceID".into(), Self::UDID.into());
+        root.insert("WiFiAddress".into(), Self::WIFI_ADDRESS.into());
+
+        let mut wireless = plist::Dictionary::new();
+        wireless.insert("EnableWifiConnections".into(), false.into());
+
+        let mut values = HashMap::new();
+        values.insert(String::new(), root);
+        values.insert("com.apple.mobile.wireless_lockdown".to_string(), wireless);
+
+        Self {
+            state: Arc::new(MockState {
+                values: Mutex::new(values),
+                next_port: Mutex::new(FIRST_SERVICE_PORT),
+                ..Default::default()
+            }),
+            label: "mock".to_string(),
+        }
+    }
+
+    /// Sets a value returned by `GetValue`
+    ///
+    /// # Arguments
+    /// * `domain` - The domain of the value, or `None` for the root domain
+    /// * `key` - The key of the value
+    /// * `value` - The value
+    pub fn with_value(
+        self,
+        domain: Option<&str>,
+        key: impl Into<String>,
+        value: impl Into<plist::Value>,
+    ) -> Self {
+        lock(&self.state.values)
+            .entry(domain.unwrap_or_default().to_string())
+            .or_default()
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Registers a service that can be started through lockdownd
+    ///
+    /// # Arguments
+    /// * `name` - The service name, such as `com.apple.afc`
+    /// * `ssl` - Whether connections to the service are secured by TLS
+    /// * `service` - Serves connections to the service
+    pub fn with_service(
+        self,
+        name: impl Into<String>,
+        ssl: bool,
+        service: impl MockService + 'static,
+    ) -> Self {
+        lock(&self.state.services).insert(
+            name.into(),
+            RegisteredService {
+                ssl,
+                service: Arc::new(service),
+            },
+        );
+        self
+    }
+
+    /// Returns a value as currently stored on the device, including changes made by `SetValue`
+    ///
+    /// # Arguments
+    /// * `domain` - The domain of the value, or `None` for the root domain
+    /// * `key` - The key of the value
+    pub fn value(&self, domain: Option<&str>, key: &str) -> Option<plist::Value> {
+        lock(&self.state.values)
+            .get(domain.unwrap_or_default())
+            .and_then(|d| d.get(key))
+            .cloned()
+    }
+
+    /// Returns whether the device still trusts the mock pairing record
+    ///
+    /// This is `false` once the host has sent `Unpair`.
+    pub fn is_paired(&self) -> bool {
+        !self.state.unpaired.load(Ordering::SeqCst)
+    }
+
+    /// Returns how many sessions lockdownd has started, across every connection
+    pub fn sessions_started(&self) -> usize {
+        self.state.sessions_started.load(Ordering::SeqCst)
+    }
+
+    /// Closes every open lockdownd connection, like a device that dropped off the network
+    ///
+    /// Each connection is closed when the host sends its next request on it.
+    pub fn drop_connections(&self) {
+        self.state.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for MockDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockState {
+    /// Reserves a port for a registered service
+    fn start_service(&self, name: &str) -> Option<(u16, bool)> {
+        let ssl = lock(&self.services).get(name)?.ssl;
+        let mut next_port = lock(&self.next_port);
+        let port = *next_port;
+        *next_port = next_port.checked_add(1).unwrap_or(FIRST_SERVICE_PORT);
+        lock(&self.pending).insert(port, name.to_string());
+        Some((port, ssl))
+    }
+}
+
+impl IdeviceProvider for MockDevice {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let state = self.state.clone();
+        let label = self.label.clone();
+        Box::pin(async move {
+            let (host, device) = tokio::io::duplex(1024 * 64);
+            if port == LockdownClient::LOCKDOWND_PORT {
+                tokio::spawn(async move {
+                    if let Err(e) = lockdownd::serve(state, Box::new(device)).await {
+                        debug!("Mock lockdownd stopped: {e:?}");
+                    }
+                });
+                return Ok(Idevice::new(Box::new(host), label));
+            }
+
+            let device = Idevice::new(Box::new(device), "mock");
+
+            // Like on a real device, each started service accepts a single connection
+            let name = lock(&state.pending).remove(&port);
+            let registered = name.and_then(|n| {
+                lock(&state.services)
+                    .get(&n)
+                    .map(|r| (r.ssl, r.service.clone()))
+            });
+            let (ssl, service) = match registered {
+                Some(r) => r,
+                None => {
+                    return Err(IdeviceError::Socket(
+                        std::io::ErrorKind::ConnectionRefused.into(),
+                    ));
+                }
+            };
+            tokio::spawn(async move {
+                let res = match ssl {
+                    true => match accept_tls(device).await {
+                        Ok(device) => service.serve(device).await,
+                        Err(e) => Err(e),
+                    },
+                    false => service.serve(device).await,
+                };
+                if let Err(e) = res {
+                    debug!("Mock service on port {port} stopped: {e:?}");
+                }
+            });
+            Ok(Idevice::new(Box::new(host), label))
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        Box::pin(async { Ok(identity()?.pairing_file.clone()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IdeviceService;
+
+    #[tokio::test]
+    async fn lockdown_session() {
+        let device = MockDevice::new();
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        assert_eq!(
+            lockdown.query_type().await.unwrap(),
+            "com.apple.mobile.lockdown"
+        );
+        assert_eq!(
+            lockdown
+                .get_value(Some("ProductVersion"), None)
+                .await
+                .unwrap()
+                .as_string(),
+            Some("17.0")
+        );
+
+        // Services can't be started before a session
+        assert!(lockdown.start_service("com.apple.afc").await.is_err());
+
+        lockdown
+            .start_session(&device.get_pairing_file().await.unwrap())
+            .await
+            .unwrap();
+        lockdown.set_wifi_connections(true).await.unwrap();
+        assert!(lockdown.wifi_connections_enabled().await.unwrap());
+        assert_eq!(
+            device.value(
+                Some("com.apple.mobile.wireless_lockdown"),
+                "EnableWifiConnections"
+            ),
+            Some(true.into())
+        );
+
+        assert!(matches!(
+            lockdown.start_service("com.apple.afc").await,
+            Err(IdeviceError::Device(e)) if e.error == "InvalidService"
+                && e.service.as_deref() == Some("com.apple.mobile.lockdown")
+                && e.request.as_deref() == Some("StartService")
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_host_id() {
+        let device = MockDevice::new();
+        let mut pairing_file = device.get_pairing_file().await.unwrap();
+        pairing_file.host_id = "00000000-0000-0000-0000-000000000000".to_string();
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        assert!(matches!(
+            lockdown.start_session(&pairing_file).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unpair() {
+        let device = MockDevice::new();
+        let pairing_file = device.get_pairing_file().await.unwrap();
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        lockdown.unpair(&pairing_file).await.unwrap();
+        assert!(!device.is_paired());
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        assert!(matches!(
+            lockdown.start_session(&pairing_file).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+    }
+
+    #[tokio::test]
+    async fn syslog_relay() {
+        let device = MockDevice::new().with_service(
+            "com.apple.syslog_relay",
+            true,
+            SyslogRelayStub::new(["first line", "second line"]),
+        );
+        let mut syslog = crate::syslog_relay::SyslogRelayClient::connect(&device)
+            .await
+            .unwrap();
+        assert_eq!(syslog.next().await.unwrap(), "first line");
+        assert_eq!(syslog.next().await.unwrap(), "second line");
+    }
+
+    #[tokio::test]
+    async fn installation_proxy() {
+        let apps = InstallationProxyStub::new()
+            .with_app("com.example.app", "User", plist::Dictionary::new())
+            .with_app("com.apple.Preferences", "System", plist::Dictionary::new());
+        let device = MockDevice::new().with_service(
+            "com.apple.mobile.installation_proxy",
+            false,
+            apps.clone(),
+        );
+        let mut client = crate::installation_proxy::InstallationProxyClient::connect(&device)
+            .await
+            .unwrap();
+
+        let user = client.get_apps(Some("User"), None).await.unwrap();
+        assert_eq!(user.len(), 1);
+        assert!(user.contains_key("com.example.app"));
+        assert_eq!(client.browse(None).await.unwrap().len(), 2);
+
+        client.uninstall("com.example.app", None).await.unwrap();
+        assert_eq!(apps.installed(), vec!["com.apple.Preferences"]);
+
+        match client.uninstall("com.apple.Preferences", None).await {
+            Err(IdeviceError::UninstallProhibited(e)) => {
+                assert_eq!(
+                    e.service.as_deref(),
+                    Some("com.apple.mobile.installation_proxy")
+                );
+                assert_eq!(e.request.as_deref(), Some("Uninstall"));
+                assert_eq!(e.response["Error"], "UninstallProhibited".into());
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+        match client.uninstall("com.example.app", None).await {
+            Err(IdeviceError::Device(e)) => {
+                assert_eq!(e.error, "APIInternalError");
+                assert_eq!(
+                    e.service.as_deref(),
+                    Some("com.apple.mobile.installation_proxy")
+                );
+                assert_eq!(e.request.as_deref(), Some("Uninstall"));
+                assert_eq!(e.description(), Some("Could not find com.example.app"));
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn afc() {
+        let fs = AfcStub::new().with_file("/DCIM/100APPLE/IMG_0001.JPG", b"jpeg");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = crate::afc::AfcClient::connect(&device).await.unwrap();
+
+        assert_eq!(
+            client.list_dir("/DCIM").await.unwrap(),
+            vec![".", "..", "100APPLE"]
+        );
+        assert_eq!(
+            client
+                .get_file_info("/DCIM/100APPLE/IMG_0001.JPG")
+                .await
+                .unwrap()
+                .size,
+            4
+        );
+
+        client.mk_dir("/Downloads").await.unwrap();
+        let mut file = client
+            .open(
+                "/Downloads/test.txt",
+                crate::afc::opcode::AfcFopenMode::WrOnly,
+            )
+            .await
+            .unwrap();
+        file.write(b"hello world").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(fs.file("/Downloads/test.txt").unwrap(), b"hello world");
+
+        let mut file = client
+            .open(
+                "/Downloads/test.txt",
+                crate::afc::opcode::AfcFopenMode::RdOnly,
+            )
+            .await
+            .unwrap();
+        assert_eq!(file.read().await.unwrap(), b"hello world");
+        file.close().await.unwrap();
+
+        assert!(matches!(
+            client.remove("/Downloads").await,
+            Err(IdeviceError::Afc(crate::afc::errors::AfcError::DirNotEmpty))
+        ));
+        client.remove_all("/Downloads").await.unwrap();
+        assert!(!fs.contains("/Downloads/test.txt"));
+    }
+}
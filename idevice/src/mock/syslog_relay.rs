@@ -0,0 +1,60 @@
+// Jackson Coxson
+// Stub for com.apple.syslog_relay
+
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+
+use crate::{Idevice, IdeviceError};
+
+use super::{MockService, MockServiceFuture};
+
+/// Sends a fixed set of log lines to every connection, then waits for the host to disconnect
+#[derive(Clone, Debug, Default)]
+pub struct SyslogRelayStub {
+    lines: Arc<Vec<String>>,
+    close: bool,
+}
+
+impl SyslogRelayStub {
+    /// Creates a stub that sends the given lines, in order
+    ///
+    /// # Arguments
+    /// * `lines` - The log lines, without terminators
+    pub fn new(lines: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            lines: Arc::new(lines.into_iter().map(Into::into).collect()),
+            close: false,
+        }
+    }
+
+    /// Closes the connection once the lines are sent, like a device that was unplugged
+    pub fn closing(mut self) -> Self {
+        self.close = true;
+        self
+    }
+}
+
+impl MockService for SyslogRelayStub {
+    fn serve(&self, mut idevice: Idevice) -> MockServiceFuture {
+        let lines = self.lines.clone();
+        let close = self.close;
+        Box::pin(async move {
+            for line in lines.iter() {
+                let mut buf = line.as_bytes().to_vec();
+                buf.extend_from_slice(b"\n\x00");
+                idevice.send_raw(&buf).await?;
+            }
+            if close {
+                return Ok(());
+            }
+
+            let mut socket = idevice
+                .get_socket()
+                .ok_or(IdeviceError::NoEstablishedConnection)?;
+            let mut buf = [0u8; 64];
+            while socket.read(&mut buf).await? > 0 {}
+            Ok(())
+        })
+    }
+}
@@ -22,7 +22,8 @@ pub struct PairingFile {
     pub host_private_key: Vec<u8>,
     /// Host's certificate in DER format
     pub host_certificate: CertificateDer<'static>,
-    /// Root CA's private key in DER format
+    /// Root CA's private key in DER format. Empty when the record was issued from a root CA
+    /// whose key isn't stored in it, such as an organisation-wide root.
     pub root_private_key: Vec<u8>,
     /// Root CA's certificate in DER format
     pub root_certificate: CertificateDer<'static>,
@@ -38,6 +39,49 @@ pub struct PairingFile {
     pub udid: Option<String>,
 }
 
+/// The result of checking a pairing file against a device
+#[cfg(feature = "pair")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PairingFileStatus {
+    /// Whether the device recognizes the record's HostID
+    pub host_id_known: bool,
+    /// Whether a TLS session could be established with the record's certificates
+    pub trusted: bool,
+    /// Names of the certificates in the record that are expired, not yet valid or unparsable
+    pub invalid_certificates: Vec<String>,
+}
+
+#[cfg(feature = "pair")]
+impl PairingFileStatus {
+    /// Returns whether the pairing file can be used as is
+    pub fn is_healthy(&self) -> bool {
+        self.host_id_known && self.trusted && self.invalid_certificates.is_empty()
+    }
+}
+
+/// The on-disk layouts of pairing files used by other tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PairingFileFormat {
+    /// The record as stored by usbmuxd, libimobiledevice (`/var/lib/lockdown/<UDID>.plist`) and
+    /// pymobiledevice3 (`~/.pymobiledevice3/<UDID>.plist`). The UDID is the file name and isn't
+    /// stored in the record.
+    #[default]
+    Lockdown,
+    /// SideStore and Jitterbug `.mobiledevicepairing` files, which store the UDID in the record
+    MobileDevicePairing,
+}
+
+/// Fields every pairing record needs, in the order they are checked
+const REQUIRED_FIELDS: [&str; 7] = [
+    "DeviceCertificate",
+    "HostCertificate",
+    "HostPrivateKey",
+    "RootCertificate",
+    "SystemBUID",
+    "HostID",
+    "WiFiMACAddress",
+];
+
 /// Internal representation of a pairing file for serialization/deserialization
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
@@ -45,13 +89,17 @@ struct RawPairingFile {
     device_certificate: Data,
     host_private_key: Data,
     host_certificate: Data,
-    root_private_key: Data,
+    // Records issued from a reused root CA don't store its key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root_private_key: Option<Data>,
     root_certificate: Data,
     #[serde(rename = "SystemBUID")]
     system_buid: String,
     #[serde(rename = "HostID")]
     host_id: String,
-    escrow_bag: Data,
+    // Records created before the device was first unlocked don't have an escrow bag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    escrow_bag: Option<Data>,
     #[serde(rename = "WiFiMACAddress")]
     wifi_mac_address: String,
     #[serde(rename = "UDID")]
@@ -88,24 +136,17 @@ impl PairingFile {
     /// # Errors
     /// Returns `IdeviceError` if:
     /// - The data cannot be parsed as PLIST
-    /// - Required fields are missing
+    /// - Required fields are missing, as `IdeviceError::PairingFileMissingField`
     /// - Cryptographic materials are invalid
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::IdeviceError> {
-        let r = match ::plist::from_bytes::<RawPairingFile>(bytes) {
-            Ok(r) => r,
+        let v: plist::Value = match plist::from_bytes(bytes) {
+            Ok(v) => v,
             Err(e) => {
-                warn!("Unable to convert bytes to raw pairing file: {e:?}");
-                return Err(crate::IdeviceError::UnexpectedResponse);
+                warn!("Unable to parse pairing file bytes as a plist: {e:?}");
+                return Err(e.into());
             }
         };
-
-        match r.try_into() {
-            Ok(r) => Ok(r),
-            Err(e) => {
-                warn!("Unable to convert raw pairing file into pairing file: {e:?}");
-                Err(crate::IdeviceError::UnexpectedResponse)
-            }
-        }
+        Self::from_value(&v)
     }
 
     /// Creates a pairing file from a plist value
@@ -118,14 +159,155 @@ impl PairingFile {
     ///
     /// # Errors
     /// Returns `IdeviceError` if:
-    /// - Required fields are missing
+    /// - Required fields are missing, as `IdeviceError::PairingFileMissingField`
     /// - Cryptographic materials are invalid
     pub fn from_value(v: &plist::Value) -> Result<Self, crate::IdeviceError> {
+        let dict = match v.as_dictionary() {
+            Some(d) => d,
+            None => {
+                warn!("Pairing file is not a dictionary");
+                return Err(crate::IdeviceError::UnexpectedResponse);
+            }
+        };
+        if let Some(missing) = REQUIRED_FIELDS.iter().find(|f| !dict.contains_key(f)) {
+            warn!("Pairing file is missing {missing}");
+            return Err(crate::IdeviceError::PairingFileMissingField(
+                missing.to_string(),
+            ));
+        }
+
         let raw: RawPairingFile = plist::from_value(v)?;
+        if raw.escrow_bag.is_none() {
+            warn!("Pairing file has no EscrowBag, services requiring an unlocked device may fail");
+        }
         let p = raw.try_into()?;
         Ok(p)
     }
 
+    /// Imports a pairing file written by another tool
+    ///
+    /// # Arguments
+    /// * `bytes` - Raw bytes of the pairing file, XML or binary PLIST
+    /// * `format` - The layout the file was written in
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::PairingFileMissingField` if a field the format requires is
+    /// missing, or another `IdeviceError` if the file is malformed
+    pub fn from_bytes_as(
+        bytes: &[u8],
+        format: PairingFileFormat,
+    ) -> Result<Self, crate::IdeviceError> {
+        let p = Self::from_bytes(bytes)?;
+        if format == PairingFileFormat::MobileDevicePairing && p.udid.is_none() {
+            return Err(crate::IdeviceError::PairingFileMissingField(
+                "UDID".to_string(),
+            ));
+        }
+        Ok(p)
+    }
+
+    /// Imports a pairing file written by another tool from disk
+    ///
+    /// For [`PairingFileFormat::Lockdown`] records, the UDID is taken from the file name.
+    ///
+    /// # Arguments
+    /// * `path` - Path to the pairing file
+    /// * `format` - The layout the file was written in
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::PairingFileMissingField` if a field the format requires is
+    /// missing, or another `IdeviceError` if the file can't be read or is malformed
+    pub fn read_from_file_as(
+        path: impl AsRef<Path>,
+        format: PairingFileFormat,
+    ) -> Result<Self, crate::IdeviceError> {
+        let path = path.as_ref();
+        let mut p = Self::from_bytes_as(&std::fs::read(path)?, format)?;
+        if format == PairingFileFormat::Lockdown && p.udid.is_none() {
+            p.udid = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .filter(|x| is_udid(x))
+                .map(|x| x.to_string());
+        }
+        Ok(p)
+    }
+
+    /// Builds the pair record sent to lockdownd for `ValidatePair` and `Unpair`
+    ///
+    /// Only contains the public parts of the record, the private keys never leave the host.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn public_pair_record(&self) -> plist::Dictionary {
+        let mut record = plist::Dictionary::new();
+        record.insert(
+            "DeviceCertificate".into(),
+            plist::Value::Data(ensure_pem_headers(&self.device_certificate, "CERTIFICATE")),
+        );
+        record.insert(
+            "HostCertificate".into(),
+            plist::Value::Data(ensure_pem_headers(&self.host_certificate, "CERTIFICATE")),
+        );
+        record.insert("HostID".into(), self.host_id.clone().into());
+        record.insert(
+            "RootCertificate".into(),
+            plist::Value::Data(ensure_pem_headers(&self.root_certificate, "CERTIFICATE")),
+        );
+        record.insert("SystemBUID".into(), self.system_buid.clone().into());
+        record
+    }
+
+    /// Checks whether this pairing file is still usable with a device
+    ///
+    /// Starts a session on the lockdown client, so on a healthy record the client is left with
+    /// an active TLS session. On an unhealthy record the client should be discarded.
+    ///
+    /// # Arguments
+    /// * `lockdown` - A lockdown client without an active session
+    ///
+    /// # Returns
+    /// A report of what is wrong with the record, if anything
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the device couldn't be reached at all
+    #[cfg(feature = "pair")]
+    pub async fn verify_against(
+        &self,
+        lockdown: &mut crate::lockdown::LockdownClient,
+    ) -> Result<PairingFileStatus, crate::IdeviceError> {
+        let mut invalid_certificates = Vec::new();
+        for (name, cert) in [
+            ("DeviceCertificate", &self.device_certificate),
+            ("HostCertificate", &self.host_certificate),
+            ("RootCertificate", &self.root_certificate),
+        ] {
+            if !certificate_is_current(cert) {
+                warn!("{name} is expired or unparsable");
+                invalid_certificates.push(name.to_string());
+            }
+        }
+
+        let (host_id_known, trusted) = match lockdown.start_session(self).await {
+            Ok(_) => (true, true),
+            Err(crate::IdeviceError::InvalidHostID) => (false, false),
+            Err(crate::IdeviceError::Rustls(e)) => {
+                warn!("TLS handshake with pairing file failed: {e:?}");
+                (true, false)
+            }
+            Err(crate::IdeviceError::Socket(e)) if e.kind() == std::io::ErrorKind::InvalidData => {
+                // tokio-rustls reports handshake failures as IO errors
+                warn!("TLS handshake with pairing file failed: {e:?}");
+                (true, false)
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(PairingFileStatus {
+            host_id_known,
+            trusted,
+            invalid_certificates,
+        })
+    }
+
     /// Serializes the pairing file to a PLIST-formatted byte vector
     ///
     /// # Returns
@@ -140,6 +322,31 @@ impl PairingFile {
         plist::to_writer_xml(&mut buf, &raw)?;
         Ok(buf)
     }
+
+    /// Exports the pairing file in the layout another tool expects
+    ///
+    /// # Arguments
+    /// * `format` - The layout to write
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::PairingFileMissingField` if the format needs the UDID and it is
+    /// unknown, or another `IdeviceError` if serialization fails
+    pub fn serialize_as(
+        mut self,
+        format: PairingFileFormat,
+    ) -> Result<Vec<u8>, crate::IdeviceError> {
+        match format {
+            PairingFileFormat::Lockdown => self.udid = None,
+            PairingFileFormat::MobileDevicePairing => {
+                if self.udid.is_none() {
+                    return Err(crate::IdeviceError::PairingFileMissingField(
+                        "UDID".to_string(),
+                    ));
+                }
+            }
+        }
+        self.serialize()
+    }
 }
 
 impl TryFrom<RawPairingFile> for PairingFile {
@@ -153,7 +360,7 @@ impl TryFrom<RawPairingFile> for PairingFile {
         let device_cert_data = Into::<Vec<u8>>::into(value.device_certificate);
         let host_private_key_data = Into::<Vec<u8>>::into(value.host_private_key);
         let host_cert_data = Into::<Vec<u8>>::into(value.host_certificate);
-        let root_private_key_data = Into::<Vec<u8>>::into(value.root_private_key);
+        let root_private_key_data = value.root_private_key.map(Into::into).unwrap_or_default();
         let root_cert_data = Into::<Vec<u8>>::into(value.root_certificate);
 
         // Ensure device certificate has proper PEM headers
@@ -173,7 +380,7 @@ impl TryFrom<RawPairingFile> for PairingFile {
             root_certificate: CertificateDer::from_pem_slice(&root_certificate_pem)?,
             system_buid: value.system_buid,
             host_id: value.host_id,
-            escrow_bag: value.escrow_bag.into(),
+            escrow_bag: value.escrow_bag.map(Into::into).unwrap_or_default(),
             wifi_mac_address: value.wifi_mac_address,
             udid: value.udid,
         })
@@ -190,23 +397,45 @@ impl From<PairingFile> for RawPairingFile {
 
         // Ensure private keys include proper PEM format
         let host_private_key_data = ensure_pem_headers(&value.host_private_key, "PRIVATE KEY");
-        let root_private_key_data = ensure_pem_headers(&value.root_private_key, "PRIVATE KEY");
+        let root_private_key_data = (!value.root_private_key.is_empty())
+            .then(|| ensure_pem_headers(&value.root_private_key, "PRIVATE KEY"));
 
         Self {
             device_certificate: Data::new(device_cert_data),
             host_private_key: Data::new(host_private_key_data),
             host_certificate: Data::new(host_cert_data),
-            root_private_key: Data::new(root_private_key_data),
+            root_private_key: root_private_key_data.map(Data::new),
             root_certificate: Data::new(root_cert_data),
             system_buid: value.system_buid,
             host_id: value.host_id.clone(),
-            escrow_bag: Data::new(value.escrow_bag),
+            escrow_bag: (!value.escrow_bag.is_empty()).then(|| Data::new(value.escrow_bag)),
             wifi_mac_address: value.wifi_mac_address,
             udid: value.udid,
         }
     }
 }
 
+/// Checks that the current time falls in a certificate's validity period
+#[cfg(feature = "pair")]
+fn certificate_is_current(cert: &CertificateDer) -> bool {
+    use x509_cert::der::Decode;
+
+    let cert = match x509_cert::Certificate::from_der(cert) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Unable to parse certificate: {e:?}");
+            return false;
+        }
+    };
+    let now = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+
+    let validity = cert.tbs_certificate.validity;
+    validity.not_before.to_unix_duration() <= now && now <= validity.not_after.to_unix_duration()
+}
+
 /// Helper function to ensure data has proper PEM headers
 /// If the data already has headers, it returns it as is
 /// If not, it adds the appropriate BEGIN and END headers
@@ -250,6 +479,15 @@ fn ensure_pem_headers(data: &[u8], pem_type: &str) -> Vec<u8> {
     result
 }
 
+/// Checks if a string looks like a UDID, either 40 hex characters or `XXXXXXXX-XXXXXXXXXXXXXXXX`
+fn is_udid(s: &str) -> bool {
+    let hex = |x: &str| x.chars().all(|c| c.is_ascii_hexdigit());
+    match s.split_once('-') {
+        Some((a, b)) => a.len() == 8 && b.len() == 16 && hex(a) && hex(b),
+        None => s.len() == 40 && hex(s),
+    }
+}
+
 /// Check if data is already in PEM format
 fn is_pem_formatted(data: &[u8]) -> bool {
     if let Ok(data_str) = std::str::from_utf8(data) {
@@ -283,3 +521,148 @@ fn test_pairing_file_roundtrip() {
 
     assert_eq!(f[..output.len()], output);
 }
+
+#[cfg(test)]
+pub(crate) fn test_record() -> plist::Dictionary {
+    let mut record = plist::Dictionary::new();
+    for field in REQUIRED_FIELDS {
+        record.insert(field.into(), plist::Value::Data(vec![1, 2, 3]));
+    }
+    for field in ["SystemBUID", "HostID", "WiFiMACAddress"] {
+        record.insert(field.into(), "00:11:22:33:44:55".into());
+    }
+    record
+}
+
+#[test]
+fn test_pairing_file_missing_fields() {
+    let mut record = test_record();
+    record.remove("HostPrivateKey");
+    match PairingFile::from_value(&plist::Value::Dictionary(record)) {
+        Err(crate::IdeviceError::PairingFileMissingField(f)) => assert_eq!(f, "HostPrivateKey"),
+        other => panic!("expected a missing field error, got {other:?}"),
+    }
+
+    // Records without an escrow bag or UDID are still usable
+    let p = PairingFile::from_value(&plist::Value::Dictionary(test_record())).unwrap();
+    assert!(p.escrow_bag.is_empty());
+    assert!(p.udid.is_none());
+
+    let bytes = p.clone().serialize().unwrap();
+    let record: plist::Dictionary = plist::from_bytes(&bytes).unwrap();
+    assert!(!record.contains_key("EscrowBag"));
+    match PairingFile::from_bytes_as(&bytes, PairingFileFormat::MobileDevicePairing) {
+        Err(crate::IdeviceError::PairingFileMissingField(f)) => assert_eq!(f, "UDID"),
+        other => panic!("expected a missing field error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_pairing_file_formats() {
+    let mut p = PairingFile::from_value(&plist::Value::Dictionary(test_record())).unwrap();
+    p.udid = Some("00008030-001A2B3C4D5E6F70".to_string());
+
+    let exported = p
+        .clone()
+        .serialize_as(PairingFileFormat::MobileDevicePairing)
+        .unwrap();
+    let imported =
+        PairingFile::from_bytes_as(&exported, PairingFileFormat::MobileDevicePairing).unwrap();
+    assert_eq!(imported.udid, p.udid);
+
+    // Lockdown records keep the UDID in the file name
+    let exported = p.clone().serialize_as(PairingFileFormat::Lockdown).unwrap();
+    assert!(PairingFile::from_bytes(&exported).unwrap().udid.is_none());
+
+    // The file name carries the UDID, so the test gets its own directory
+    let dir = std::env::temp_dir().join(format!(
+        "idevice-pairing-file-{}-formats",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("00008030-001A2B3C4D5E6F70.plist");
+    std::fs::write(&path, &exported).unwrap();
+    let imported = PairingFile::read_from_file_as(&path, PairingFileFormat::Lockdown);
+    std::fs::remove_dir_all(&dir).unwrap();
+    let imported = imported.unwrap();
+    assert_eq!(imported.udid, p.udid);
+    assert_eq!(imported.host_certificate, p.host_certificate);
+}
+
+#[cfg(feature = "pair")]
+#[test]
+fn test_certificate_is_current() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use x509_cert::{
+        builder::{Builder, CertificateBuilder, Profile},
+        der::{asn1::UtcTime, Encode},
+        name::Name,
+        serial_number::SerialNumber,
+        spki::SubjectPublicKeyInfoOwned,
+        time::{Time, Validity},
+    };
+
+    let mut rng = rsa::rand_core::OsRng;
+    let key = rsa::RsaPrivateKey::new(&mut rng, 1024).unwrap();
+    let signer = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(key.clone());
+    let public_key = SubjectPublicKeyInfoOwned::from_key(rsa::RsaPublicKey::from(&key)).unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let day = Duration::from_secs(24 * 60 * 60);
+
+    let cert = |not_before: Duration, not_after: Duration| {
+        let validity = Validity {
+            not_before: Time::UtcTime(UtcTime::from_unix_duration(not_before).unwrap()),
+            not_after: Time::UtcTime(UtcTime::from_unix_duration(not_after).unwrap()),
+        };
+        let cert = CertificateBuilder::new(
+            Profile::Manual { issuer: None },
+            SerialNumber::new(&[1]).unwrap(),
+            validity,
+            Name::default(),
+            public_key.clone(),
+            &signer,
+        )
+        .unwrap()
+        .build::<rsa::pkcs1v15::Signature>()
+        .unwrap();
+        CertificateDer::from(cert.to_der().unwrap())
+    };
+
+    assert!(certificate_is_current(&cert(now - day, now + day)));
+    // Expired
+    assert!(!certificate_is_current(&cert(now - day * 2, now - day)));
+    // Not yet valid
+    assert!(!certificate_is_current(&cert(now + day, now + day * 2)));
+    // Unparsable
+    let garbage = CertificateDer::from(vec![1, 2, 3]);
+    assert!(!certificate_is_current(&garbage));
+}
+
+#[cfg(all(test, feature = "mock"))]
+#[tokio::test]
+async fn test_verify_against() {
+    use crate::{provider::IdeviceProvider, IdeviceService};
+
+    let device = crate::mock::MockDevice::new();
+    let pairing_file = device.get_pairing_file().await.unwrap();
+
+    let mut lockdown = crate::lockdown::LockdownClient::connect(&device)
+        .await
+        .unwrap();
+    let status = pairing_file.verify_against(&mut lockdown).await.unwrap();
+    assert!(status.is_healthy());
+
+    let mut unknown = pairing_file.clone();
+    unknown.host_id = "00000000-0000-0000-0000-000000000000".to_string();
+    let mut lockdown = crate::lockdown::LockdownClient::connect(&device)
+        .await
+        .unwrap();
+    assert_eq!(
+        unknown.verify_against(&mut lockdown).await.unwrap(),
+        PairingFileStatus {
+            host_id_known: false,
+            trusted: false,
+            invalid_certificates: Vec::new(),
+        }
+    );
+}
@@ -3,16 +3,25 @@
 //! Provides abstractions for establishing connections to iOS devices through different
 //! transport mechanisms (TCP, USB, etc.).
 
-use std::{future::Future, pin::Pin};
+use std::{borrow::Cow, future::Future, pin::Pin};
+
+#[cfg(feature = "pool")]
+use log::debug;
 
 #[cfg(feature = "tcp")]
 use tokio::net::TcpStream;
 
-use crate::{pairing_file::PairingFile, Idevice, IdeviceError, ReadWrite};
+use crate::{
+    lockdown::LockdownClient, pairing_file::PairingFile, Idevice, IdeviceError, ReadWrite,
+};
 
 #[cfg(feature = "usbmuxd")]
 use crate::usbmuxd::UsbmuxdAddr;
 
+/// The port and TLS requirement of a started service, see [`IdeviceProvider::start_service`]
+pub type StartServiceFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<(u16, bool), IdeviceError>> + Send + 'a>>;
+
 /// Trait for providers that can establish connections to iOS devices
 ///
 /// This is an async trait that abstracts over different connection methods
@@ -30,6 +39,27 @@ pub trait IdeviceProvider: Unpin + Send + Sync + std::fmt::Debug {
         port: u16,
     ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>>;
 
+    /// Establishes a connection, giving up after `timeout`
+    ///
+    /// # Arguments
+    /// * `port` - The port number to connect to
+    /// * `timeout` - How long to wait for the connection
+    ///
+    /// # Returns
+    /// A future that resolves to an `Idevice` connection handle, or `IdeviceError::Timeout`
+    fn connect_timeout(
+        &self,
+        port: u16,
+        timeout: std::time::Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let fut = self.connect(port);
+        Box::pin(async move {
+            tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| IdeviceError::Timeout)?
+        })
+    }
+
     /// Returns a label identifying this provider/connection
     fn label(&self) -> &str;
 
@@ -40,6 +70,26 @@ pub trait IdeviceProvider: Unpin + Send + Sync + std::fmt::Debug {
     fn get_pairing_file(
         &self,
     ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>>;
+
+    /// Asks lockdownd to start a service
+    ///
+    /// The default implementation opens a new lockdown connection and TLS session every time.
+    ///
+    /// # Arguments
+    /// * `service_name` - The name of the service to start
+    ///
+    /// # Returns
+    /// A future that resolves to the service's port and whether it requires TLS
+    fn start_service(&self, service_name: Cow<'static, str>) -> StartServiceFuture<'_> {
+        Box::pin(async move {
+            let mut lockdown =
+                LockdownClient::new(self.connect(LockdownClient::LOCKDOWND_PORT).await?);
+            lockdown
+                .start_session(&self.get_pairing_file().await?)
+                .await?;
+            lockdown.start_service(service_name).await
+        })
+    }
 }
 
 pub trait RsdProvider: Unpin + Send + Sync + std::fmt::Debug {
@@ -157,6 +207,113 @@ impl IdeviceProvider for UsbmuxdProvider {
     }
 }
 
+/// Provider that keeps one authenticated lockdown connection alive and reuses it
+///
+/// Wraps another provider so starting many services only costs one lockdown TLS handshake.
+/// The pairing file is fetched once and cached. If the cached lockdown connection dies, a new
+/// one is started transparently.
+#[cfg(feature = "pool")]
+#[derive(Clone)]
+pub struct PooledProvider {
+    inner: std::sync::Arc<dyn IdeviceProvider>,
+    pairing_file: std::sync::Arc<tokio::sync::Mutex<Option<PairingFile>>>,
+    lockdown: std::sync::Arc<tokio::sync::Mutex<Option<LockdownClient>>>,
+}
+
+#[cfg(feature = "pool")]
+impl PooledProvider {
+    /// Creates a pooled provider wrapping another provider
+    ///
+    /// # Arguments
+    /// * `inner` - The provider used to open connections
+    pub fn new(inner: Box<dyn IdeviceProvider>) -> Self {
+        Self {
+            inner: inner.into(),
+            pairing_file: Default::default(),
+            lockdown: Default::default(),
+        }
+    }
+
+    /// Drops the cached lockdown connection and pairing file
+    ///
+    /// The next call will connect and read the pairing file again.
+    pub async fn reset(&self) {
+        *self.lockdown.lock().await = None;
+        *self.pairing_file.lock().await = None;
+    }
+}
+
+#[cfg(feature = "pool")]
+impl std::fmt::Debug for PooledProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledProvider")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "pool")]
+impl IdeviceProvider for PooledProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        self.inner.connect(port)
+    }
+
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    /// Returns the cached pairing file, fetching it from the inner provider the first time
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let inner = self.inner.clone();
+        let pairing_file = self.pairing_file.clone();
+
+        Box::pin(async move {
+            let mut pairing_file = pairing_file.lock().await;
+            if let Some(p) = pairing_file.as_ref() {
+                return Ok(p.clone());
+            }
+            let p = inner.get_pairing_file().await?;
+            *pairing_file = Some(p.clone());
+            Ok(p)
+        })
+    }
+
+    /// Starts a service on the cached lockdown connection, reconnecting if it died
+    fn start_service(&self, service_name: Cow<'static, str>) -> StartServiceFuture<'_> {
+        Box::pin(async move {
+            let mut lockdown = self.lockdown.lock().await;
+            if let Some(l) = lockdown.as_mut() {
+                match l.start_service(service_name.clone()).await {
+                    Ok(r) => return Ok(r),
+                    Err(
+                        e @ (IdeviceError::Socket(_)
+                        | IdeviceError::Rustls(_)
+                        | IdeviceError::SessionInactive
+                        | IdeviceError::NoEstablishedConnection
+                        | IdeviceError::Timeout),
+                    ) => {
+                        debug!("Cached lockdown connection died, reconnecting: {e:?}");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            *lockdown = None;
+            let mut l =
+                LockdownClient::new(self.inner.connect(LockdownClient::LOCKDOWND_PORT).await?);
+            l.start_session(&self.get_pairing_file().await?).await?;
+            let r = l.start_service(service_name).await?;
+            *lockdown = Some(l);
+            Ok(r)
+        })
+    }
+}
+
 #[cfg(feature = "tcp")]
 impl RsdProvider for std::net::IpAddr {
     async fn connect_to_service_port(
@@ -168,3 +325,69 @@ impl RsdProvider for std::net::IpAddr {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "mock")]
+    use super::*;
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn default_start_service() {
+        let device = crate::mock::MockDevice::new().with_service(
+            "com.apple.afc",
+            false,
+            crate::mock::AfcStub::new(),
+        );
+
+        let (_, ssl) = device.start_service("com.apple.afc".into()).await.unwrap();
+        assert!(!ssl);
+        device.start_service("com.apple.afc".into()).await.unwrap();
+        // Every call starts its own session
+        assert_eq!(device.sessions_started(), 2);
+
+        assert!(device
+            .start_service("com.apple.missing".into())
+            .await
+            .is_err());
+    }
+
+    #[cfg(all(feature = "mock", feature = "pool"))]
+    #[tokio::test]
+    async fn pooled_provider() {
+        use crate::IdeviceService;
+
+        let device = crate::mock::MockDevice::new().with_service(
+            "com.apple.afc",
+            false,
+            crate::mock::AfcStub::new().with_file("/test.txt", b"hello"),
+        );
+        let pool = PooledProvider::new(Box::new(device.clone()));
+
+        // One session serves every service
+        for _ in 0..3 {
+            let mut afc = crate::afc::AfcClient::connect(&pool).await.unwrap();
+            assert_eq!(afc.get_file_info("/test.txt").await.unwrap().size, 5);
+        }
+        assert_eq!(device.sessions_started(), 1);
+
+        // Errors from the service itself keep the session
+        assert!(pool
+            .start_service("com.apple.missing".into())
+            .await
+            .is_err());
+        pool.start_service("com.apple.afc".into()).await.unwrap();
+        assert_eq!(device.sessions_started(), 1);
+
+        // A dead session is replaced
+        device.drop_connections();
+        pool.start_service("com.apple.afc".into()).await.unwrap();
+        assert_eq!(device.sessions_started(), 2);
+        pool.start_service("com.apple.afc".into()).await.unwrap();
+        assert_eq!(device.sessions_started(), 2);
+
+        pool.reset().await;
+        pool.start_service("com.apple.afc".into()).await.unwrap();
+        assert_eq!(device.sessions_started(), 3);
+    }
+}
@@ -0,0 +1,291 @@
+//! Automatic reconnection for service clients
+//!
+//! [`Reconnecting`] holds a service client along with the provider it was created from. When a
+//! request fails with a retryable error, the client is reconnected through the provider and the
+//! request is replayed after a backoff.
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use log::{debug, warn};
+
+use crate::{provider::IdeviceProvider, IdeviceError, IdeviceService};
+
+/// A boxed future borrowing the client it was created from
+pub type ClientFuture<'a, R> = Pin<Box<dyn Future<Output = Result<R, IdeviceError>> + Send + 'a>>;
+
+/// Controls how failed requests are retried
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// How many times a request is retried before giving up
+    pub max_retries: u32,
+    /// How long to wait before the first retry. Doubles with every following retry.
+    pub initial_backoff: Duration,
+    /// The longest to wait between retries
+    pub max_backoff: Duration,
+    /// Decides whether an error should trigger a reconnect and retry
+    pub is_retryable: fn(&IdeviceError) -> bool,
+}
+
+impl RetryPolicy {
+    /// The errors retried by the default policy, which indicate a dead connection
+    pub fn default_is_retryable(e: &IdeviceError) -> bool {
+        matches!(
+            e,
+            IdeviceError::Socket(_)
+                | IdeviceError::Timeout
+                | IdeviceError::NoEstablishedConnection
+                | IdeviceError::Rustls(_)
+                | IdeviceError::HeartbeatTimeout
+        )
+    }
+
+    /// Returns how long to wait before the given retry, starting from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            is_retryable: Self::default_is_retryable,
+        }
+    }
+}
+
+/// A service client that reconnects when its connection dies
+///
+/// Only pass idempotent requests to [`Reconnecting::call`], since a request that failed midway
+/// may have already been processed by the device.
+pub struct Reconnecting<T: IdeviceService> {
+    provider: Arc<dyn IdeviceProvider>,
+    client: Option<T>,
+    policy: RetryPolicy,
+}
+
+impl<T: IdeviceService> Reconnecting<T> {
+    /// Creates a wrapper that connects lazily on the first request
+    ///
+    /// # Arguments
+    /// * `provider` - The provider used to connect and reconnect the client
+    /// * `policy` - How failed requests are retried
+    pub fn new(provider: Box<dyn IdeviceProvider>, policy: RetryPolicy) -> Self {
+        Self {
+            provider: provider.into(),
+            client: None,
+            policy,
+        }
+    }
+
+    /// Creates a wrapper and connects the client right away
+    ///
+    /// # Arguments
+    /// * `provider` - The provider used to connect and reconnect the client
+    /// * `policy` - How failed requests are retried
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the first connection fails
+    pub async fn connect(
+        provider: Box<dyn IdeviceProvider>,
+        policy: RetryPolicy,
+    ) -> Result<Self, IdeviceError> {
+        let mut s = Self::new(provider, policy);
+        s.client().await?;
+        Ok(s)
+    }
+
+    /// Returns the retry policy
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Returns the connected client, connecting first if needed
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if connecting fails
+    pub async fn client(&mut self) -> Result<&mut T, IdeviceError> {
+        if self.client.is_none() {
+            self.client = Some(T::connect(&*self.provider).await?);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    /// Drops the current connection, so the next request reconnects
+    pub fn disconnect(&mut self) {
+        self.client = None;
+    }
+
+    /// Runs a request, reconnecting and replaying it after retryable errors
+    ///
+    /// # Arguments
+    /// * `f` - Sends the request on the client. Called once per attempt.
+    ///
+    /// # Errors
+    /// Returns the last error if the request failed with a non-retryable error, or failed more
+    /// times than the policy allows
+    ///
+    /// # Example
+    /// ```rust
+    /// # #[cfg(feature = "mock")]
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), idevice::IdeviceError> {
+    /// use idevice::{
+    ///     lockdown::LockdownClient,
+    ///     mock::MockDevice,
+    ///     reconnect::{Reconnecting, RetryPolicy},
+    /// };
+    ///
+    /// let device = MockDevice::new();
+    /// let mut lockdown =
+    ///     Reconnecting::<LockdownClient>::new(Box::new(device.clone()), RetryPolicy::default());
+    /// let name = lockdown
+    ///     .call(|c| Box::pin(c.get_value(Some("DeviceName"), None)))
+    ///     .await?;
+    /// assert_eq!(name.as_string(), Some("Mock iPhone"));
+    ///
+    /// // The request is replayed on a new connection
+    /// device.drop_connections();
+    /// let name = lockdown
+    ///     .call(|c| Box::pin(c.get_value(Some("DeviceName"), None)))
+    ///     .await?;
+    /// assert_eq!(name.as_string(), Some("Mock iPhone"));
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(feature = "mock"))]
+    /// # fn main() {}
+    /// ```
+    pub async fn call<R, F>(&mut self, mut f: F) -> Result<R, IdeviceError>
+    where
+        F: for<'a> FnMut(&'a mut T) -> ClientFuture<'a, R>,
+    {
+        let mut retry = 0;
+        loop {
+            let res = match self.client().await {
+                Ok(client) => f(client).await,
+                Err(e) => Err(e),
+            };
+
+            match res {
+                Ok(r) => return Ok(r),
+                Err(e) if (self.policy.is_retryable)(&e) && retry < self.policy.max_retries => {
+                    let backoff = self.policy.backoff(retry);
+                    warn!("Request failed with {e:?}, reconnecting in {backoff:?}");
+                    self.client = None;
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                Err(e) => {
+                    debug!("Giving up after {retry} retries");
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::{pairing_file::PairingFile, Idevice};
+
+    #[derive(Debug, Default)]
+    struct CountingProvider {
+        connects: Arc<AtomicU32>,
+    }
+
+    impl IdeviceProvider for CountingProvider {
+        fn connect(
+            &self,
+            _port: u16,
+        ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            let (client, _) = tokio::io::duplex(16);
+            Box::pin(async move { Ok(Idevice::new(Box::new(client), "test")) })
+        }
+
+        fn label(&self) -> &str {
+            "test"
+        }
+
+        fn get_pairing_file(
+            &self,
+        ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+            Box::pin(async { Err(IdeviceError::NotFound) })
+        }
+    }
+
+    struct TestClient;
+
+    impl IdeviceService for TestClient {
+        fn service_name() -> std::borrow::Cow<'static, str> {
+            "test".into()
+        }
+
+        async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+            Self::from_stream(provider.connect(0).await?).await
+        }
+
+        async fn from_stream(_idevice: Idevice) -> Result<Self, IdeviceError> {
+            Ok(Self)
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_socket_errors() {
+        let provider = CountingProvider::default();
+        let connects = provider.connects.clone();
+        let mut r = Reconnecting::<TestClient>::new(Box::new(provider), policy());
+
+        let attempts = AtomicU32::new(0);
+        let res = r
+            .call(|_| {
+                let n = attempts.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move {
+                    if n < 2 {
+                        Err(IdeviceError::Socket(std::io::ErrorKind::BrokenPipe.into()))
+                    } else {
+                        Ok(n)
+                    }
+                })
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res, 2);
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+
+        // Errors that don't indicate a dead connection are returned right away
+        let res = r
+            .call(|_| Box::pin(async { Err::<(), _>(IdeviceError::UnexpectedResponse) }))
+            .await;
+        assert!(matches!(res, Err(IdeviceError::UnexpectedResponse)));
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let provider = CountingProvider::default();
+        let connects = provider.connects.clone();
+        let mut r = Reconnecting::<TestClient>::new(Box::new(provider), policy());
+
+        let res = r
+            .call(|_| Box::pin(async { Err::<(), _>(IdeviceError::Timeout) }))
+            .await;
+        assert!(matches!(res, Err(IdeviceError::Timeout)));
+        assert_eq!(connects.load(Ordering::SeqCst), 4);
+    }
+}
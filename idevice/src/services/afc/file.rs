@@ -1,83 +1,122 @@
 // Jackson Coxson
 
+use std::{
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use log::warn;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt, ReadBuf};
+
 use crate::IdeviceError;
 
 use super::{
-    opcode::AfcOpcode,
+    opcode::{AfcLockOp, AfcOpcode},
     packet::{AfcPacket, AfcPacketHeader},
+    AfcClient,
 };
 
-/// Maximum transfer size for file operations (64KB)
-const MAX_TRANSFER: u64 = 64 * 1024; // this is what go-ios uses
+/// Default transfer size for file operations (64KB)
+pub(super) const MAX_TRANSFER: u64 = 64 * 1024; // this is what go-ios uses
+
+/// `whence` values for `FileSeek`, same as lseek
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+/// A request made by the I/O trait impls
+enum Op {
+    Read,
+    Write { rewind: i64, data: Vec<u8> },
+    Seek { whence: u64, offset: i64 },
+}
+
+/// The result of an [`Op`]
+enum Done {
+    Read(Vec<u8>),
+    Write,
+    Seek(u64),
+}
+
+/// A request in flight, which holds the client until it finishes
+type Pending<'a> =
+    Pin<Box<dyn Future<Output = (&'a mut AfcClient, Result<Done, IdeviceError>)> + Send + 'a>>;
 
 /// Handle for an open file on the device.
 /// Call close before dropping
+///
+/// The handle implements tokio's `AsyncRead`, `AsyncWrite` and `AsyncSeek`, moving at
+/// most one block per request, so files of any size can be streamed with `tokio::io::copy`.
+/// Like `tokio::fs::File`, a write finishes in the background and its error is returned
+/// by the next operation, so flush or close the file before relying on it.
+///
+/// `read`, `write`, `copy_to` and `copy_from` keep the client's window of requests in
+/// flight (see [`AfcClient::set_window`]), which the I/O traits can't, so prefer them for
+/// large transfers.
 pub struct FileDescriptor<'a> {
-    pub(crate) client: &'a mut super::AfcClient,
+    /// `None` while a request from the I/O traits holds the client
+    client: Option<&'a mut AfcClient>,
+    pending: Option<Pending<'a>>,
+    /// Data read from the device but not yet returned by `poll_read`
+    read_buf: Vec<u8>,
+    read_pos: usize,
     pub(crate) fd: u64,
     pub(crate) path: String,
 }
 
-impl FileDescriptor<'_> {
-    /// Closes the file descriptor
-    pub async fn close(self) -> Result<(), IdeviceError> {
-        let header_payload = self.fd.to_le_bytes().to_vec();
-        let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
-
-        let header = AfcPacketHeader {
-            magic: super::MAGIC,
-            entire_len: header_len,
-            header_payload_len: header_len,
-            packet_num: self.client.package_number,
-            operation: AfcOpcode::FileClose,
-        };
-        self.client.package_number += 1;
-
-        let packet = AfcPacket {
-            header,
-            header_payload,
-            payload: Vec::new(),
-        };
+impl<'a> FileDescriptor<'a> {
+    pub(crate) fn new(client: &'a mut AfcClient, fd: u64, path: String) -> Self {
+        Self {
+            client: Some(client),
+            pending: None,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            fd,
+            path,
+        }
+    }
 
-        self.client.send(packet).await?;
-        self.client.read().await?;
+    /// Closes the file descriptor
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        request(
+            client,
+            AfcOpcode::FileClose,
+            fd.to_le_bytes().to_vec(),
+            Vec::new(),
+        )
+        .await?;
         Ok(())
     }
 
+    /// Gives up the handle without closing the file
+    ///
+    /// The returned descriptor stays open on the client's connection and can be turned
+    /// back into a handle with [`AfcClient::file_from_raw`], so a file can be kept open
+    /// while the client is used for other requests.
+    ///
+    /// # Returns
+    /// The device's descriptor for the file
+    pub async fn into_raw(mut self) -> Result<u64, IdeviceError> {
+        self.client().await?;
+        Ok(self.fd)
+    }
+
     /// Reads the entire contents of the file
     ///
     /// # Returns
     /// A vector containing the file's data
     pub async fn read(&mut self) -> Result<Vec<u8>, IdeviceError> {
-        // Get the file size first
-        let mut bytes_left = self.client.get_file_info(&self.path).await?.size;
-        let mut collected_bytes = Vec::with_capacity(bytes_left);
-
-        while bytes_left > 0 {
-            let mut header_payload = self.fd.to_le_bytes().to_vec();
-            header_payload.extend_from_slice(&MAX_TRANSFER.to_le_bytes());
-            let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
-
-            let header = AfcPacketHeader {
-                magic: super::MAGIC,
-                entire_len: header_len,
-                header_payload_len: header_len,
-                packet_num: self.client.package_number,
-                operation: AfcOpcode::Read,
-            };
-            self.client.package_number += 1;
-
-            let packet = AfcPacket {
-                header,
-                header_payload,
-                payload: Vec::new(),
-            };
+        let (fd, path) = (self.fd, self.path.clone());
+        let client = self.client().await?;
 
-            self.client.send(packet).await?;
-            let res = self.client.read().await?;
-            bytes_left -= res.payload.len();
-            collected_bytes.extend(res.payload);
-        }
+        // Get the file size first
+        let size = client.get_file_info(&path).await?.size;
+        let mut collected_bytes = Vec::with_capacity(size);
+        pipelined_read(client, fd, &mut collected_bytes).await?;
 
         Ok(collected_bytes)
     }
@@ -87,30 +126,658 @@ impl FileDescriptor<'_> {
     /// # Arguments
     /// * `bytes` - Data to write to the file
     pub async fn write(&mut self, bytes: &[u8]) -> Result<(), IdeviceError> {
-        let chunks = bytes.chunks(MAX_TRANSFER as usize);
-
-        for chunk in chunks {
-            let header_payload = self.fd.to_le_bytes().to_vec();
-            let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
-
-            let header = AfcPacketHeader {
-                magic: super::MAGIC,
-                entire_len: header_len + chunk.len() as u64,
-                header_payload_len: header_len,
-                packet_num: self.client.package_number,
-                operation: AfcOpcode::Write,
-            };
-            self.client.package_number += 1;
+        let fd = self.fd;
+        let client = self.client().await?;
+        pipelined_write(client, fd, &mut &bytes[..]).await?;
+        Ok(())
+    }
 
-            let packet = AfcPacket {
-                header,
-                header_payload,
-                payload: chunk.to_vec(),
-            };
+    /// Streams the rest of the file into a writer
+    ///
+    /// # Arguments
+    /// * `writer` - Where to write the file's data
+    ///
+    /// # Returns
+    /// How many bytes were copied
+    pub async fn copy_to<W: AsyncWrite + Unpin + ?Sized>(
+        &mut self,
+        writer: &mut W,
+    ) -> Result<u64, IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        let copied = pipelined_read(client, fd, writer).await?;
+        writer.flush().await?;
+        Ok(copied)
+    }
+
+    /// Streams a reader into the file at its position
+    ///
+    /// # Arguments
+    /// * `reader` - Where to read the data from
+    ///
+    /// # Returns
+    /// How many bytes were copied
+    pub async fn copy_from<R: AsyncRead + Unpin + ?Sized>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<u64, IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        pipelined_write(client, fd, reader).await
+    }
 
-            self.client.send(packet).await?;
-            self.client.read().await?;
+    /// Moves the position in the file
+    ///
+    /// # Arguments
+    /// * `position` - Where to move to
+    ///
+    /// # Returns
+    /// The new position from the start of the file
+    pub async fn seek(&mut self, position: SeekFrom) -> Result<u64, IdeviceError> {
+        let fd = self.fd;
+        let (whence, offset) = seek_args(position).ok_or(IdeviceError::InvalidArgument)?;
+        let client = self.client().await?;
+        seek(client, fd, whence, offset).await
+    }
+
+    /// Returns the position in the file
+    pub async fn tell(&mut self) -> Result<u64, IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        tell(client, fd).await
+    }
+
+    /// Truncates or extends the file
+    ///
+    /// # Arguments
+    /// * `size` - New size of the file in bytes
+    pub async fn set_len(&mut self, size: u64) -> Result<(), IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        let mut header_payload = fd.to_le_bytes().to_vec();
+        header_payload.extend_from_slice(&size.to_le_bytes());
+        request(client, AfcOpcode::FileSetSize, header_payload, Vec::new()).await?;
+        Ok(())
+    }
+
+    /// Takes or releases an advisory lock on the file
+    ///
+    /// Locks never block; taking a lock that's held elsewhere fails instead.
+    ///
+    /// # Arguments
+    /// * `op` - The lock to take, or `AfcLockOp::Unlock`
+    pub async fn lock(&mut self, op: AfcLockOp) -> Result<(), IdeviceError> {
+        let fd = self.fd;
+        let client = self.client().await?;
+        let mut header_payload = fd.to_le_bytes().to_vec();
+        header_payload.extend_from_slice(&(op as u64).to_le_bytes());
+        request(client, AfcOpcode::FileLock, header_payload, Vec::new()).await?;
+        Ok(())
+    }
+
+    /// Releases a lock taken with [`Self::lock`]
+    pub async fn unlock(&mut self) -> Result<(), IdeviceError> {
+        self.lock(AfcLockOp::Unlock).await
+    }
+
+    /// Finishes any request started by the I/O traits and returns the client
+    ///
+    /// Data read ahead by `poll_read` is given back, so the device's position matches
+    /// what the caller has seen.
+    async fn client(&mut self) -> Result<&mut AfcClient, IdeviceError> {
+        // Awaited in place, so if this call is cancelled the next one finishes the request
+        if let Some(pending) = self.pending.as_mut() {
+            let (client, res) = pending.await;
+            self.pending = None;
+            self.client = Some(client);
+            if let Done::Read(data) = res? {
+                self.read_buf = data;
+                self.read_pos = 0;
+            }
         }
+        let rewind = self.discard_read_buf();
+        let fd = self.fd;
+        let client = self.client.as_deref_mut().ok_or_else(no_client)?;
+        if rewind != 0 {
+            seek(client, fd, SEEK_CUR, -rewind).await?;
+        }
+        Ok(client)
+    }
+
+    /// Drops the read-ahead buffer, returning how many bytes in it were unread
+    fn discard_read_buf(&mut self) -> i64 {
+        let unread = self.read_buf.len() - self.read_pos;
+        self.read_buf.clear();
+        self.read_pos = 0;
+        unread as i64
+    }
+
+    /// Hands the client to a new request
+    fn start(&mut self, op: Op) -> io::Result<()> {
+        let client = self.client.take().ok_or_else(|| io_error(no_client()))?;
+        let fd = self.fd;
+        self.pending = Some(Box::pin(async move {
+            let res = match op {
+                Op::Read => {
+                    let size = client.transfer_size;
+                    read_chunk(client, fd, size).await.map(Done::Read)
+                }
+                Op::Write { rewind, data } => async {
+                    if rewind != 0 {
+                        seek(client, fd, SEEK_CUR, -rewind).await?;
+                    }
+                    write_chunk(client, fd, data).await
+                }
+                .await
+                .map(|_| Done::Write),
+                Op::Seek { whence, offset } => {
+                    seek(client, fd, whence, offset).await.map(Done::Seek)
+                }
+            };
+            (client, res)
+        }));
         Ok(())
     }
+
+    /// Drives the request in flight, if any, to completion
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Done>>> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(Ok(None));
+        };
+        let (client, res) = ready!(pending.as_mut().poll(cx));
+        self.pending = None;
+        self.client = Some(client);
+        Poll::Ready(res.map(Some).map_err(io_error))
+    }
+
+    fn start_seek_inner(&mut self, position: SeekFrom) -> io::Result<()> {
+        if self.pending.is_some() {
+            return Err(io::Error::other(
+                "other file operation is pending, call poll_complete before start_seek",
+            ));
+        }
+        let (whence, mut offset) =
+            seek_args(position).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        let unread = self.discard_read_buf();
+        if whence == SEEK_CUR {
+            offset -= unread;
+        }
+        self.start(Op::Seek { whence, offset })
+    }
+}
+
+impl AsyncRead for FileDescriptor<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.read_pos < this.read_buf.len() {
+                let n = buf.remaining().min(this.read_buf.len() - this.read_pos);
+                buf.put_slice(&this.read_buf[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(this.poll_pending(cx))? {
+                // An empty read is the end of the file
+                Some(Done::Read(data)) if data.is_empty() => return Poll::Ready(Ok(())),
+                Some(Done::Read(data)) => {
+                    this.read_buf = data;
+                    this.read_pos = 0;
+                }
+                _ => this.start(Op::Read)?,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for FileDescriptor<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        let size = this
+            .client
+            .as_ref()
+            .map_or(MAX_TRANSFER, |c| c.transfer_size);
+        let n = buf.len().min(size as usize);
+        let rewind = this.discard_read_buf();
+        this.start(Op::Write {
+            rewind,
+            data: buf[..n].to_vec(),
+        })?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.get_mut().poll_pending(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for FileDescriptor<'_> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.get_mut().start_seek_inner(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        loop {
+            match ready!(this.poll_pending(cx))? {
+                Some(Done::Seek(position)) => return Poll::Ready(Ok(position)),
+                // Nothing to complete, so report the current position
+                _ => this.start_seek_inner(SeekFrom::Current(0))?,
+            }
+        }
+    }
+}
+
+/// Converts a position to the `whence` and offset sent to the device
+fn seek_args(position: SeekFrom) -> Option<(u64, i64)> {
+    Some(match position {
+        SeekFrom::Start(p) => (SEEK_SET, i64::try_from(p).ok()?),
+        SeekFrom::Current(p) => (SEEK_CUR, p),
+        SeekFrom::End(p) => (SEEK_END, p),
+    })
+}
+
+/// The client is only taken while a request is pending, so this shouldn't happen
+fn no_client() -> IdeviceError {
+    IdeviceError::InternalError("file handle has no client".into())
+}
+
+fn io_error(e: IdeviceError) -> io::Error {
+    match e {
+        IdeviceError::Socket(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+/// Sends a request for the file without waiting for the response
+async fn send_request(
+    client: &mut AfcClient,
+    operation: AfcOpcode,
+    header_payload: Vec<u8>,
+    payload: Vec<u8>,
+) -> Result<(), IdeviceError> {
+    let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
+
+    let header = AfcPacketHeader {
+        magic: super::MAGIC,
+        entire_len: header_len + payload.len() as u64,
+        header_payload_len: header_len,
+        packet_num: client.package_number,
+        operation,
+    };
+    client.package_number += 1;
+
+    let packet = AfcPacket {
+        header,
+        header_payload,
+        payload,
+    };
+
+    client.send(packet).await
+}
+
+/// Sends a request for the file and reads the response
+async fn request(
+    client: &mut AfcClient,
+    operation: AfcOpcode,
+    header_payload: Vec<u8>,
+    payload: Vec<u8>,
+) -> Result<AfcPacket, IdeviceError> {
+    send_request(client, operation, header_payload, payload).await?;
+    client.read().await
+}
+
+/// Reads the rest of the file into a writer, keeping the client's window of requests in flight
+///
+/// Responses come back in order. Once one is empty the file has ended, and the requests
+/// still in flight are drained.
+async fn pipelined_read<W: AsyncWrite + Unpin + ?Sized>(
+    client: &mut AfcClient,
+    fd: u64,
+    writer: &mut W,
+) -> Result<u64, IdeviceError> {
+    let (window, size) = (client.window, client.transfer_size);
+    let mut in_flight = 0;
+    let mut copied = 0;
+    let mut done = false;
+    let mut error = None;
+
+    loop {
+        while !done && error.is_none() && in_flight < window {
+            let mut header_payload = fd.to_le_bytes().to_vec();
+            header_payload.extend_from_slice(&size.to_le_bytes());
+            send_request(client, AfcOpcode::Read, header_payload, Vec::new()).await?;
+            in_flight += 1;
+        }
+        if in_flight == 0 {
+            break;
+        }
+
+        let res = client.read().await;
+        in_flight -= 1;
+        match res {
+            Ok(_) if error.is_some() => {}
+            Ok(res) if res.payload.is_empty() => done = true,
+            Ok(res) => match writer.write_all(&res.payload).await {
+                Ok(()) => copied += res.payload.len() as u64,
+                Err(e) => error = Some(e.into()),
+            },
+            // The device rejected a request, but the rest still need to be drained
+            Err(e @ IdeviceError::Afc(_)) => {
+                error.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(copied),
+    }
+}
+
+/// Writes a reader into the file, keeping the client's window of requests in flight
+async fn pipelined_write<R: AsyncRead + Unpin + ?Sized>(
+    client: &mut AfcClient,
+    fd: u64,
+    reader: &mut R,
+) -> Result<u64, IdeviceError> {
+    let (window, size) = (client.window, client.transfer_size);
+    let mut in_flight = 0;
+    let mut copied = 0;
+    let mut done = false;
+    let mut error = None;
+
+    loop {
+        while !done && error.is_none() && in_flight < window {
+            let mut chunk = Vec::new();
+            match (&mut *reader).take(size).read_to_end(&mut chunk).await {
+                Ok(0) => done = true,
+                Ok(n) => {
+                    send_request(client, AfcOpcode::Write, fd.to_le_bytes().to_vec(), chunk)
+                        .await?;
+                    in_flight += 1;
+                    copied += n as u64;
+                }
+                Err(e) => error = Some(e.into()),
+            }
+        }
+        if in_flight == 0 {
+            break;
+        }
+
+        let res = client.read().await;
+        in_flight -= 1;
+        match res {
+            Ok(_) => {}
+            Err(e @ IdeviceError::Afc(_)) => {
+                error.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(copied),
+    }
+}
+
+/// Reads up to `len` bytes from the file's position
+async fn read_chunk(client: &mut AfcClient, fd: u64, len: u64) -> Result<Vec<u8>, IdeviceError> {
+    let mut header_payload = fd.to_le_bytes().to_vec();
+    header_payload.extend_from_slice(&len.to_le_bytes());
+    Ok(request(client, AfcOpcode::Read, header_payload, Vec::new())
+        .await?
+        .payload)
+}
+
+/// Writes one chunk at the file's position
+async fn write_chunk(client: &mut AfcClient, fd: u64, data: Vec<u8>) -> Result<(), IdeviceError> {
+    request(client, AfcOpcode::Write, fd.to_le_bytes().to_vec(), data).await?;
+    Ok(())
+}
+
+/// Moves the file's position and returns the new one
+async fn seek(
+    client: &mut AfcClient,
+    fd: u64,
+    whence: u64,
+    offset: i64,
+) -> Result<u64, IdeviceError> {
+    let mut header_payload = fd.to_le_bytes().to_vec();
+    header_payload.extend_from_slice(&whence.to_le_bytes());
+    header_payload.extend_from_slice(&offset.to_le_bytes());
+    request(client, AfcOpcode::FileSeek, header_payload, Vec::new()).await?;
+    tell(client, fd).await
+}
+
+/// Returns the file's position
+async fn tell(client: &mut AfcClient, fd: u64) -> Result<u64, IdeviceError> {
+    let res = request(
+        client,
+        AfcOpcode::FileTell,
+        fd.to_le_bytes().to_vec(),
+        Vec::new(),
+    )
+    .await?;
+    match res.header_payload.get(..8) {
+        Some(p) => Ok(u64::from_le_bytes(p.try_into().unwrap())),
+        None => {
+            warn!("FileTell response is missing the position");
+            Err(IdeviceError::UnexpectedResponse)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use crate::{
+        afc::opcode::AfcFopenMode,
+        mock::{AfcStub, MockDevice},
+        IdeviceService,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stream() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let fs = AfcStub::new().with_file("/DCIM/video.mov", data.clone());
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+
+        // Copy through the traits in both directions
+        let mut file = client
+            .open("/DCIM/video.mov", AfcFopenMode::RdOnly)
+            .await
+            .unwrap();
+        let mut local = Vec::new();
+        tokio::io::copy(&mut file, &mut local).await.unwrap();
+        assert_eq!(local, data);
+        file.close().await.unwrap();
+
+        let mut file = client
+            .open("/DCIM/copy.mov", AfcFopenMode::WrOnly)
+            .await
+            .unwrap();
+        tokio::io::copy(&mut &data[..], &mut file).await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(fs.file("/DCIM/copy.mov").unwrap(), data);
+
+        // Seeking accounts for data that was read ahead
+        let mut file = client
+            .open("/DCIM/video.mov", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        let mut buf = [0; 10];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data[..10]);
+        assert_eq!(file.stream_position().await.unwrap(), 10);
+        assert_eq!(file.seek(SeekFrom::End(-5)).await.unwrap(), 199_995);
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).await.unwrap();
+        assert_eq!(tail, data[199_995..]);
+
+        file.seek(SeekFrom::Start(100)).await.unwrap();
+        file.read_exact(&mut buf).await.unwrap();
+        file.write_all(b"hello").await.unwrap();
+        file.flush().await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(&fs.file("/DCIM/video.mov").unwrap()[110..115], b"hello");
+    }
+
+    #[tokio::test]
+    async fn metadata() {
+        let fs = AfcStub::new().with_file("/Downloads/a.txt", "hello world");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+
+        client.truncate("/Downloads/a.txt", 5).await.unwrap();
+        assert_eq!(fs.file("/Downloads/a.txt").unwrap(), b"hello");
+        let mtime = chrono::DateTime::from_timestamp(1_600_000_000, 0)
+            .unwrap()
+            .naive_utc();
+        client.set_mtime("/Downloads/a.txt", mtime).await.unwrap();
+        assert_eq!(
+            client
+                .get_file_info("/Downloads/a.txt")
+                .await
+                .unwrap()
+                .modified,
+            mtime
+        );
+
+        let mut file = client
+            .open("/Downloads/a.txt", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-2)).await.unwrap(), 3);
+        assert_eq!(file.tell().await.unwrap(), 3);
+        file.write(b"p!").await.unwrap();
+        file.set_len(8).await.unwrap();
+        file.lock(AfcLockOp::Exclusive).await.unwrap();
+        file.unlock().await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(fs.file("/Downloads/a.txt").unwrap(), b"help!\0\0\0");
+    }
+
+    #[tokio::test]
+    async fn pipelined() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let fs = AfcStub::new().with_dir("/Downloads");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+        client.set_window(4);
+        client.set_block_size(4096).await.unwrap();
+        assert!(client.set_block_size(0).await.is_err());
+
+        let mut file = client
+            .open("/Downloads/a.bin", AfcFopenMode::WrOnly)
+            .await
+            .unwrap();
+        file.write(&data).await.unwrap();
+        assert_eq!(file.copy_from(&mut &data[..10]).await.unwrap(), 10);
+        file.close().await.unwrap();
+        assert_eq!(fs.file("/Downloads/a.bin").unwrap()[..100_000], data);
+
+        let mut file = client
+            .open("/Downloads/a.bin", AfcFopenMode::RdOnly)
+            .await
+            .unwrap();
+        assert_eq!(file.read().await.unwrap().len(), 100_010);
+        file.seek(SeekFrom::Start(50_000)).await.unwrap();
+        let mut rest = Vec::new();
+        assert_eq!(file.copy_to(&mut rest).await.unwrap(), 50_010);
+        assert_eq!(rest[..50_000], data[50_000..]);
+
+        // The drained requests left the connection in step
+        assert_eq!(file.tell().await.unwrap(), 100_010);
+        file.close().await.unwrap();
+    }
+
+    /// Polls a future once and drops it, like a timeout that fires right away
+    async fn poll_once<F: Future>(fut: F) -> Option<F::Output> {
+        let mut fut = std::pin::pin!(fut);
+        std::future::poll_fn(|cx| match fut.as_mut().poll(cx) {
+            Poll::Ready(v) => Poll::Ready(Some(v)),
+            Poll::Pending => Poll::Ready(None),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let fs = AfcStub::new().with_file("/Downloads/a.bin", data.clone());
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs);
+        let mut client = AfcClient::connect(&device).await.unwrap();
+
+        let mut file = client
+            .open("/Downloads/a.bin", AfcFopenMode::RdOnly)
+            .await
+            .unwrap();
+        // Cancel a read from the traits, then the call that waits for its request
+        let mut buf = [0; 10];
+        assert!(poll_once(file.read_exact(&mut buf)).await.is_none());
+        assert!(poll_once(file.tell()).await.is_none());
+
+        // The next call finishes the request and gives back what it read
+        assert_eq!(file.tell().await.unwrap(), 0);
+        assert_eq!(file.read().await.unwrap(), data);
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn raw() {
+        let fs = AfcStub::new().with_file("/Downloads/a.txt", b"hello".to_vec());
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+
+        let mut file = client
+            .open("/Downloads/a.txt", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        let mut buf = [0; 2];
+        file.read_exact(&mut buf).await.unwrap();
+        let fd = file.into_raw().await.unwrap();
+
+        // The client is free while the file stays open
+        client
+            .link(
+                "a.txt",
+                "/Downloads/b",
+                crate::afc::opcode::LinkType::Symlink,
+            )
+            .await
+            .unwrap();
+        let info = client.get_file_info("/Downloads/b").await.unwrap();
+        assert_eq!(info.st_ifmt, "S_IFLNK");
+        assert_eq!(info.st_link_target.as_deref(), Some("a.txt"));
+
+        // Read-ahead was given back, so the position is where the reader left it
+        let mut file = client.file_from_raw(fd, "/Downloads/a.txt");
+        assert_eq!(file.tell().await.unwrap(), 2);
+        file.write(b"LP").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(fs.file("/Downloads/a.txt").unwrap(), b"heLPo");
+    }
 }
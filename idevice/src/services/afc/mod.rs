@@ -3,29 +3,45 @@
 //! This module provides functionality to interact with the file system of iOS devices
 //! through the AFC protocol.
 
+// Without tokio only the packet codec is built
+#[cfg(feature = "tokio")]
 use std::collections::HashMap;
 
+#[cfg(feature = "tokio")]
 use errors::AfcError;
+#[cfg(feature = "tokio")]
 use file::FileDescriptor;
+#[cfg(feature = "tokio")]
 use log::warn;
+#[cfg(feature = "tokio")]
 use opcode::{AfcFopenMode, AfcOpcode};
+#[cfg(feature = "tokio")]
 use packet::{AfcPacket, AfcPacketHeader};
 
+#[cfg(feature = "tokio")]
 use crate::{obf, Idevice, IdeviceError, IdeviceService};
 
 pub mod errors;
+#[cfg(feature = "tokio")]
 pub mod file;
 pub mod opcode;
 pub mod packet;
+#[cfg(feature = "afc_sync")]
+pub mod sync;
 
 /// The magic number used in AFC protocol communications
 pub const MAGIC: u64 = 0x4141504c36414643;
 
 /// Client for interacting with the AFC service on iOS devices
+#[cfg(feature = "tokio")]
 pub struct AfcClient {
     /// The underlying iDevice connection
     pub idevice: Idevice,
     package_number: u64,
+    /// How many requests file transfers keep in flight
+    window: usize,
+    /// Bytes moved by each read or write request
+    transfer_size: u64,
 }
 
 /// Information about a file on the device
@@ -60,19 +76,18 @@ pub struct DeviceInfo {
     pub block_size: usize,
 }
 
+#[cfg(feature = "tokio")]
 impl IdeviceService for AfcClient {
     fn service_name() -> std::borrow::Cow<'static, str> {
         obf!("com.apple.afc")
     }
 
     async fn from_stream(idevice: Idevice) -> Result<Self, IdeviceError> {
-        Ok(Self {
-            idevice,
-            package_number: 0,
-        })
+        Ok(Self::new(idevice))
     }
 }
 
+#[cfg(feature = "tokio")]
 impl AfcClient {
     /// Creates a new AFC client from an existing iDevice connection
     ///
@@ -82,7 +97,57 @@ impl AfcClient {
         Self {
             idevice,
             package_number: 0,
+            window: 1,
+            transfer_size: file::MAX_TRANSFER,
+        }
+    }
+
+    /// Sets how many read or write requests a file transfer sends before waiting for replies
+    ///
+    /// The device answers requests in order, so keeping several in flight hides the round
+    /// trip time, which dominates over Wi-Fi and tunnels. Defaults to 1.
+    ///
+    /// # Arguments
+    /// * `window` - Requests to keep in flight, at least 1
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+    }
+
+    /// Negotiates the block sizes used for the connection and its transfers
+    ///
+    /// Sends `SetFSBlockSize` and `SetSocketBlockSize`, then moves `size` bytes with each
+    /// read or write request instead of 64KB. Each request in flight buffers one block.
+    ///
+    /// # Arguments
+    /// * `size` - Block size in bytes, such as 0x800000
+    pub async fn set_block_size(&mut self, size: u64) -> Result<(), IdeviceError> {
+        if size == 0 {
+            return Err(IdeviceError::InvalidArgument);
         }
+        for operation in [AfcOpcode::SetFsBs, AfcOpcode::SetSocketBs] {
+            let header_payload = size.to_le_bytes().to_vec();
+            let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
+
+            let header = AfcPacketHeader {
+                magic: MAGIC,
+                entire_len: header_len,
+                header_payload_len: header_len,
+                packet_num: self.package_number,
+                operation,
+            };
+            self.package_number += 1;
+
+            let packet = AfcPacket {
+                header,
+                header_payload,
+                payload: Vec::new(),
+            };
+
+            self.send(packet).await?;
+            self.read().await?;
+        }
+        self.transfer_size = size;
+        Ok(())
     }
 
     /// Lists the contents of a directory on the device
@@ -408,11 +473,18 @@ impl AfcClient {
             return Err(IdeviceError::UnexpectedResponse);
         }
         let fd = u64::from_le_bytes(res.header_payload[..8].try_into().unwrap());
-        Ok(FileDescriptor {
-            client: self,
-            fd,
-            path,
-        })
+        Ok(FileDescriptor::new(self, fd, path))
+    }
+
+    /// Wraps a descriptor returned by [`FileDescriptor::into_raw`] in a handle
+    ///
+    /// The descriptor must have been opened on this client's connection and not closed.
+    ///
+    /// # Arguments
+    /// * `fd` - The device's descriptor for the file
+    /// * `path` - Path the file was opened with
+    pub fn file_from_raw(&mut self, fd: u64, path: impl Into<String>) -> FileDescriptor<'_> {
+        FileDescriptor::new(self, fd, path.into())
     }
 
     /// Creates a hard or symbolic link
@@ -500,6 +572,88 @@ impl AfcClient {
         Ok(())
     }
 
+    /// Truncates or extends a file without opening it
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file
+    /// * `size` - New size of the file in bytes
+    pub async fn truncate(
+        &mut self,
+        path: impl Into<String>,
+        size: u64,
+    ) -> Result<(), IdeviceError> {
+        let path = path.into();
+
+        let mut header_payload = size.to_le_bytes().to_vec();
+        header_payload.extend(path.as_bytes());
+        header_payload.push(0);
+
+        let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
+
+        let header = AfcPacketHeader {
+            magic: MAGIC,
+            entire_len: header_len,
+            header_payload_len: header_len,
+            packet_num: self.package_number,
+            operation: AfcOpcode::Truncate,
+        };
+        self.package_number += 1;
+
+        let packet = AfcPacket {
+            header,
+            header_payload,
+            payload: Vec::new(),
+        };
+
+        self.send(packet).await?;
+        self.read().await?;
+
+        Ok(())
+    }
+
+    /// Sets the modification time of a file or directory
+    ///
+    /// # Arguments
+    /// * `path` - Path to the file or directory
+    /// * `mtime` - New modification time, in UTC like [`FileInfo::modified`]
+    pub async fn set_mtime(
+        &mut self,
+        path: impl Into<String>,
+        mtime: chrono::NaiveDateTime,
+    ) -> Result<(), IdeviceError> {
+        let path = path.into();
+        let mtime = mtime
+            .and_utc()
+            .timestamp_nanos_opt()
+            .ok_or(IdeviceError::InvalidArgument)?;
+
+        let mut header_payload = (mtime as u64).to_le_bytes().to_vec();
+        header_payload.extend(path.as_bytes());
+        header_payload.push(0);
+
+        let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
+
+        let header = AfcPacketHeader {
+            magic: MAGIC,
+            entire_len: header_len,
+            header_payload_len: header_len,
+            packet_num: self.package_number,
+            operation: AfcOpcode::SetFileTime,
+        };
+        self.package_number += 1;
+
+        let packet = AfcPacket {
+            header,
+            header_payload,
+            payload: Vec::new(),
+        };
+
+        self.send(packet).await?;
+        self.read().await?;
+
+        Ok(())
+    }
+
     /// Reads a response packet from the device
     ///
     /// # Returns
@@ -51,6 +51,14 @@ pub enum LinkType {
     Symlink = 0x00000002,
 }
 
+/// Advisory lock operations, always non-blocking
+#[repr(u64)]
+pub enum AfcLockOp {
+    Shared = 0x00000005,    // LOCK_SH | LOCK_NB
+    Exclusive = 0x00000006, // LOCK_EX | LOCK_NB
+    Unlock = 0x0000000C,    // LOCK_UN | LOCK_NB
+}
+
 impl TryFrom<u64> for AfcOpcode {
     type Error = ();
 
@@ -85,6 +93,7 @@ impl TryFrom<u64> for AfcOpcode {
             0x0000001B => Ok(Self::FileLock),
             0x0000001C => Ok(Self::MakeLink),
             0x0000001E => Ok(Self::SetFileTime),
+            0x00000022 => Ok(Self::RemovePathAndContents),
             _ => Err(()),
         }
     }
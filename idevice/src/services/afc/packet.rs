@@ -2,7 +2,9 @@
 
 use log::debug;
 
-use crate::{Idevice, IdeviceError};
+#[cfg(feature = "tokio")]
+use crate::Idevice;
+use crate::{codec::Decoder, IdeviceError};
 
 use super::opcode::AfcOpcode;
 
@@ -37,17 +39,25 @@ impl AfcPacketHeader {
         res
     }
 
-    pub async fn read(reader: &mut Idevice) -> Result<Self, IdeviceError> {
-        let header_bytes = reader.read_raw(Self::LEN as usize).await?;
-        let mut chunks = header_bytes.chunks_exact(8);
+    /// Parses a header from the first `LEN` bytes of a packet
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if there aren't enough bytes, the magic is wrong, the
+    /// opcode is unknown or the lengths are inconsistent
+    pub fn parse(bytes: &[u8]) -> Result<Self, IdeviceError> {
+        if bytes.len() < Self::LEN as usize {
+            return Err(IdeviceError::NotEnoughBytes(
+                bytes.len(),
+                Self::LEN as usize,
+            ));
+        }
+        let field = |i: usize| u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
         let res = Self {
-            magic: u64::from_le_bytes(chunks.next().unwrap().try_into().unwrap()),
-            entire_len: u64::from_le_bytes(chunks.next().unwrap().try_into().unwrap()),
-            header_payload_len: u64::from_le_bytes(chunks.next().unwrap().try_into().unwrap()),
-            packet_num: u64::from_le_bytes(chunks.next().unwrap().try_into().unwrap()),
-            operation: match AfcOpcode::try_from(u64::from_le_bytes(
-                chunks.next().unwrap().try_into().unwrap(),
-            )) {
+            magic: field(0),
+            entire_len: field(1),
+            header_payload_len: field(2),
+            packet_num: field(3),
+            operation: match AfcOpcode::try_from(field(4)) {
                 Ok(o) => o,
                 Err(_) => {
                     return Err(IdeviceError::UnknownAfcOpcode);
@@ -57,8 +67,17 @@ impl AfcPacketHeader {
         if res.magic != super::MAGIC {
             return Err(IdeviceError::InvalidAfcMagic);
         }
+        if res.header_payload_len < Self::LEN || res.entire_len < res.header_payload_len {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
         Ok(res)
     }
+
+    #[cfg(feature = "tokio")]
+    pub async fn read(reader: &mut Idevice) -> Result<Self, IdeviceError> {
+        let header_bytes = reader.read_raw(Self::LEN as usize).await?;
+        Self::parse(&header_bytes)
+    }
 }
 
 impl AfcPacket {
@@ -72,27 +91,113 @@ impl AfcPacket {
         res
     }
 
+    #[cfg(feature = "tokio")]
     pub async fn read(reader: &mut Idevice) -> Result<Self, IdeviceError> {
-        let header = AfcPacketHeader::read(reader).await?;
-        debug!("afc header: {header:?}");
-        let header_payload = reader
-            .read_raw((header.header_payload_len - AfcPacketHeader::LEN) as usize)
-            .await?;
-
-        let payload = if header.header_payload_len == header.entire_len {
-            Vec::new() // no payload
-        } else {
-            reader
-                .read_raw((header.entire_len - header.header_payload_len) as usize)
-                .await?
-        };
+        let res = reader.read_decoded(&mut AfcPacketDecoder::new()).await?;
+        debug!("Recv afc: {res:?}");
+        Ok(res)
+    }
+}
 
-        let res = Self {
+/// Sans-IO decoder for AFC packets
+#[derive(Debug, Default)]
+pub struct AfcPacketDecoder {
+    buf: Vec<u8>,
+    header: Option<AfcPacketHeader>,
+}
+
+impl AfcPacketDecoder {
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Decoder for AfcPacketDecoder {
+    type Item = AfcPacket;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError> {
+        if self.header.is_none() {
+            if self.buf.len() < AfcPacketHeader::LEN as usize {
+                return Ok(None);
+            }
+            let header = AfcPacketHeader::parse(&self.buf)?;
+            debug!("afc header: {header:?}");
+            self.header = Some(header);
+        }
+        let header = self.header.as_ref().unwrap();
+        if (self.buf.len() as u64) < header.entire_len {
+            return Ok(None);
+        }
+
+        let header = self.header.take().unwrap();
+        let mut packet: Vec<u8> = self.buf.drain(..header.entire_len as usize).collect();
+        let payload = packet.split_off(header.header_payload_len as usize);
+        let header_payload = packet.split_off(AfcPacketHeader::LEN as usize);
+        Ok(Some(AfcPacket {
             header,
             header_payload,
             payload,
+        }))
+    }
+
+    fn bytes_needed(&self) -> usize {
+        let len = match &self.header {
+            Some(h) => h.entire_len as usize,
+            None => AfcPacketHeader::LEN as usize,
         };
-        debug!("Recv afc: {res:?}");
-        Ok(res)
+        len.saturating_sub(self.buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_in_chunks() {
+        let packet = AfcPacket {
+            header: AfcPacketHeader {
+                magic: super::super::MAGIC,
+                entire_len: AfcPacketHeader::LEN + 8 + 5,
+                header_payload_len: AfcPacketHeader::LEN + 8,
+                packet_num: 3,
+                operation: AfcOpcode::Write,
+            },
+            header_payload: 7u64.to_le_bytes().to_vec(),
+            payload: b"hello".to_vec(),
+        };
+        let bytes = packet.serialize();
+
+        let mut decoder = AfcPacketDecoder::new();
+        for chunk in bytes.chunks(7) {
+            assert!(decoder.decode().unwrap().is_none());
+            decoder.push(chunk);
+        }
+        let decoded = decoder.decode().unwrap().unwrap();
+        assert_eq!(decoded.header.packet_num, 3);
+        assert_eq!(decoded.header_payload, 7u64.to_le_bytes());
+        assert_eq!(decoded.payload, b"hello");
+        assert_eq!(decoder.bytes_needed(), AfcPacketHeader::LEN as usize);
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let mut header = AfcPacketHeader {
+            magic: super::super::MAGIC,
+            entire_len: 0,
+            header_payload_len: AfcPacketHeader::LEN,
+            packet_num: 0,
+            operation: AfcOpcode::Status,
+        }
+        .serialize();
+        header.extend([0; 8]);
+        let mut decoder = AfcPacketDecoder::new();
+        decoder.push(&header);
+        assert!(decoder.decode().is_err());
     }
 }
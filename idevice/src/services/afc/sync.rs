@@ -0,0 +1,886 @@
+// Jackson Coxson
+//! Recursive directory sync between the host and the device
+//!
+//! [`push`] copies a local directory to a path on the device and [`pull`] copies one back.
+//! Both list the two trees, work out what differs, and then copy only that. A file is up to
+//! date when its size and modification time (to the second) match, and every copied file gets
+//! its source's modification time, so a second run copies nothing.
+//!
+//! With [`SyncOptions::resume`], a destination file that is shorter than its source and newer
+//! than it may be an interrupted copy. If the last block it has matches the source, only the
+//! rest of it is copied, otherwise it's copied again from the start.
+//!
+//! Works with any [`AfcClient`], including the one from a `HouseArrestClient` for copying app
+//! containers. [`push_parallel`], [`pull_parallel`] and [`copy_files`] spread the copies over
+//! several connections to the same service.
+
+use std::{
+    collections::BTreeMap,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::{debug, warn};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::IdeviceError;
+
+use super::{errors::AfcError, opcode::AfcFopenMode, AfcClient};
+
+/// How much of an interrupted copy is compared with the source before resuming it
+const RESUME_CHECK_LEN: u64 = 64 * 1024;
+
+/// Controls what a sync copies and whether it changes anything
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    /// Globs of the files to copy. Everything is copied if empty.
+    ///
+    /// Globs containing a `/` are matched against the path relative to the synced
+    /// directory, others against the file name.
+    pub include: Vec<String>,
+    /// Globs of the files and directories to leave alone, which take precedence over
+    /// `include`. Excluded directories aren't descended into.
+    pub exclude: Vec<String>,
+    /// Removes files and directories from the destination that aren't in the source
+    pub mirror: bool,
+    /// Continues interrupted copies instead of starting them over, once the end of what
+    /// was copied is checked against the source
+    pub resume: bool,
+    /// Plans the sync without changing anything
+    pub dry_run: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            mirror: false,
+            resume: true,
+            dry_run: false,
+        }
+    }
+}
+
+/// A file to copy with [`copy_files`]
+#[derive(Clone, Debug)]
+pub enum CopyJob {
+    /// Copies a local file to the device
+    Push { local: PathBuf, remote: String },
+    /// Copies a file on the device to the host
+    Pull { remote: String, local: PathBuf },
+}
+
+/// A change made to the destination, with paths relative to the synced directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncAction {
+    /// Created a directory
+    MakeDir(String),
+    /// Copied a file, starting at `offset` when resuming
+    Copy { path: String, offset: u64, len: u64 },
+    /// Removed a file or directory, with everything in it
+    Remove(String),
+}
+
+/// What a sync did, or would have done for a dry run
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// The changes, in the order they were planned. Directories are made and removals done
+    /// before any file is copied.
+    pub actions: Vec<SyncAction>,
+    /// How many files were already up to date
+    pub up_to_date: usize,
+    /// How many bytes were copied
+    pub bytes_copied: u64,
+}
+
+/// Copies a local directory to the device
+///
+/// # Arguments
+/// * `client` - The AFC client to copy with
+/// * `local` - The directory to copy from, which must exist
+/// * `remote` - The directory on the device to copy to, created if missing
+/// * `options` - What to copy
+pub async fn push(
+    client: &mut AfcClient,
+    local: impl AsRef<Path>,
+    remote: impl Into<String>,
+    options: &SyncOptions,
+) -> Result<SyncReport, IdeviceError> {
+    push_parallel(std::slice::from_mut(client), local, remote, options).await
+}
+
+/// Copies a local directory to the device over several connections
+///
+/// The first client lists the trees and makes the directories, then each client copies
+/// the next file in line until they're all done.
+///
+/// # Arguments
+/// * `clients` - The AFC clients to copy with, connected to the same service
+/// * `local` - The directory to copy from, which must exist
+/// * `remote` - The directory on the device to copy to, created if missing
+/// * `options` - What to copy
+pub async fn push_parallel(
+    clients: &mut [AfcClient],
+    local: impl AsRef<Path>,
+    remote: impl Into<String>,
+    options: &SyncOptions,
+) -> Result<SyncReport, IdeviceError> {
+    let local = local.as_ref();
+    let remote = remote.into();
+    let filter = Filter::new(options)?;
+    let client = clients.first_mut().ok_or(IdeviceError::InvalidArgument)?;
+
+    // A missing source would list as empty, and mirroring it would empty the destination
+    match tokio::fs::metadata(local).await {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => return Err(IdeviceError::InvalidArgument),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(IdeviceError::NotFound),
+        Err(e) => return Err(e.into()),
+    }
+    let mut source = Tree::new();
+    list_local(local, "", &filter, &mut source).await?;
+    let mut dest = Tree::new();
+    list_remote(client, &remote, "", &filter, &mut dest).await?;
+    let mut report = plan(
+        &filter.retain_dirs(source.clone()),
+        &filter.retain_dirs(dest),
+        options,
+    );
+    check_resumes(client, &mut report, local, &remote).await?;
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    client.mk_dir(&remote).await?;
+    let mut transfers = Vec::new();
+    for action in &report.actions {
+        match action {
+            SyncAction::MakeDir(path) => client.mk_dir(remote_path(&remote, path)).await?,
+            SyncAction::Remove(path) => remove_remote(client, &remote_path(&remote, path)).await?,
+            SyncAction::Copy { path, offset, .. } => transfers.push(Transfer {
+                push: true,
+                local: local_path(local, path),
+                remote: remote_path(&remote, path),
+                offset: *offset,
+                mtime: Some(source[path].modified),
+            }),
+        }
+    }
+    run_transfers(clients, transfers).await?;
+    Ok(report)
+}
+
+/// Copies a directory on the device to the host
+///
+/// # Arguments
+/// * `client` - The AFC client to copy with
+/// * `remote` - The directory on the device to copy from
+/// * `local` - The directory to copy to, created if missing
+/// * `options` - What to copy
+pub async fn pull(
+    client: &mut AfcClient,
+    remote: impl Into<String>,
+    local: impl AsRef<Path>,
+    options: &SyncOptions,
+) -> Result<SyncReport, IdeviceError> {
+    pull_parallel(std::slice::from_mut(client), remote, local, options).await
+}
+
+/// Copies a directory on the device to the host over several connections
+///
+/// # Arguments
+/// * `clients` - The AFC clients to copy with, connected to the same service
+/// * `remote` - The directory on the device to copy from
+/// * `local` - The directory to copy to, created if missing
+/// * `options` - What to copy
+pub async fn pull_parallel(
+    clients: &mut [AfcClient],
+    remote: impl Into<String>,
+    local: impl AsRef<Path>,
+    options: &SyncOptions,
+) -> Result<SyncReport, IdeviceError> {
+    let remote = remote.into();
+    let local = local.as_ref();
+    let filter = Filter::new(options)?;
+    let client = clients.first_mut().ok_or(IdeviceError::InvalidArgument)?;
+
+    if client.get_file_info(&remote).await?.st_ifmt != "S_IFDIR" {
+        return Err(IdeviceError::Afc(AfcError::InvalidArg));
+    }
+    let mut source = Tree::new();
+    list_remote(client, &remote, "", &filter, &mut source).await?;
+    let mut dest = Tree::new();
+    list_local(local, "", &filter, &mut dest).await?;
+    let mut report = plan(
+        &filter.retain_dirs(source.clone()),
+        &filter.retain_dirs(dest),
+        options,
+    );
+    check_resumes(client, &mut report, local, &remote).await?;
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    tokio::fs::create_dir_all(local).await?;
+    let mut transfers = Vec::new();
+    for action in &report.actions {
+        match action {
+            SyncAction::MakeDir(path) => tokio::fs::create_dir(local_path(local, path)).await?,
+            SyncAction::Remove(path) => {
+                let path = local_path(local, path);
+                if tokio::fs::symlink_metadata(&path).await?.is_dir() {
+                    tokio::fs::remove_dir_all(path).await?;
+                } else {
+                    tokio::fs::remove_file(path).await?;
+                }
+            }
+            SyncAction::Copy { path, offset, .. } => transfers.push(Transfer {
+                push: false,
+                local: local_path(local, path),
+                remote: remote_path(&remote, path),
+                offset: *offset,
+                mtime: Some(source[path].modified),
+            }),
+        }
+    }
+    run_transfers(clients, transfers).await?;
+    Ok(report)
+}
+
+/// Copies files over several connections at once
+///
+/// Each client copies the next file in line until they're all done. Destination files are
+/// replaced, and their parent directories must already exist.
+///
+/// # Arguments
+/// * `clients` - The AFC clients to copy with, connected to the same service
+/// * `jobs` - The files to copy
+///
+/// # Returns
+/// How many bytes were copied
+///
+/// # Errors
+/// Returns the first error. No new copies are started after it, but the ones already
+/// running are finished so every client stays usable.
+pub async fn copy_files(
+    clients: &mut [AfcClient],
+    jobs: Vec<CopyJob>,
+) -> Result<u64, IdeviceError> {
+    let transfers = jobs
+        .into_iter()
+        .map(|job| match job {
+            CopyJob::Push { local, remote } => Transfer {
+                push: true,
+                local,
+                remote,
+                offset: 0,
+                mtime: None,
+            },
+            CopyJob::Pull { remote, local } => Transfer {
+                push: false,
+                local,
+                remote,
+                offset: 0,
+                mtime: None,
+            },
+        })
+        .collect();
+    run_transfers(clients, transfers).await
+}
+
+/// One file to copy, in either direction
+struct Transfer {
+    push: bool,
+    local: PathBuf,
+    remote: String,
+    /// Where to start, when resuming
+    offset: u64,
+    /// The modification time to give the copy
+    mtime: Option<chrono::NaiveDateTime>,
+}
+
+impl Transfer {
+    async fn run(&self, client: &mut AfcClient) -> Result<u64, IdeviceError> {
+        if self.push {
+            let mut src = tokio::fs::File::open(&self.local).await?;
+            src.seek(SeekFrom::Start(self.offset)).await?;
+
+            let mode = if self.offset > 0 {
+                AfcFopenMode::Rw
+            } else {
+                AfcFopenMode::WrOnly
+            };
+            let mut dst = client.open(&self.remote, mode).await?;
+            dst.seek(SeekFrom::Start(self.offset)).await?;
+            let copied = dst.copy_from(&mut src).await?;
+            dst.close().await?;
+
+            if let Some(mtime) = self.mtime {
+                client.set_mtime(&self.remote, mtime).await?;
+            }
+            Ok(copied)
+        } else {
+            let mut src = client.open(&self.remote, AfcFopenMode::RdOnly).await?;
+            src.seek(SeekFrom::Start(self.offset)).await?;
+
+            let mut dst = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(self.offset == 0)
+                .open(&self.local)
+                .await?;
+            dst.seek(SeekFrom::Start(self.offset)).await?;
+            let copied = src.copy_to(&mut dst).await?;
+            src.close().await?;
+
+            if let Some(mtime) = self.mtime.and_then(to_system_time) {
+                dst.into_std().await.set_modified(mtime)?;
+            }
+            Ok(copied)
+        }
+    }
+}
+
+/// Runs transfers with every client taking the next one in line
+async fn run_transfers(
+    clients: &mut [AfcClient],
+    transfers: Vec<Transfer>,
+) -> Result<u64, IdeviceError> {
+    if clients.is_empty() {
+        return Err(IdeviceError::InvalidArgument);
+    }
+    let queue = Mutex::new(transfers.into_iter());
+    let failed = AtomicBool::new(false);
+
+    let workers = clients.iter_mut().map(|client| {
+        let (queue, failed) = (&queue, &failed);
+        async move {
+            let mut copied = 0;
+            while !failed.load(Ordering::Relaxed) {
+                let Some(transfer) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                match transfer.run(client).await {
+                    Ok(n) => copied += n,
+                    Err(e) => {
+                        failed.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(copied)
+        }
+    });
+
+    let mut copied = 0;
+    for res in futures::future::join_all(workers).await {
+        copied += res?;
+    }
+    Ok(copied)
+}
+
+/// A file or directory in one of the trees
+#[derive(Clone, Debug)]
+struct Entry {
+    is_dir: bool,
+    size: u64,
+    modified: chrono::NaiveDateTime,
+}
+
+/// Entries by their path relative to the synced directory
+type Tree = BTreeMap<String, Entry>;
+
+/// Works out the actions that make `dest` match `source`
+fn plan(source: &Tree, dest: &Tree, options: &SyncOptions) -> SyncReport {
+    let mut report = SyncReport::default();
+    let mut removed: Vec<&str> = Vec::new();
+
+    if options.mirror {
+        for path in dest.keys().filter(|p| !source.contains_key(*p)) {
+            // Removing a directory takes everything in it along
+            if removed.iter().any(|r| is_within(path, r)) {
+                continue;
+            }
+            report.actions.push(SyncAction::Remove(path.clone()));
+            removed.push(path);
+        }
+    }
+
+    // Sorted paths list directories before what's in them
+    for (path, src) in source {
+        let existing = dest.get(path);
+        if existing.is_some_and(|d| d.is_dir != src.is_dir) {
+            report.actions.push(SyncAction::Remove(path.clone()));
+        }
+        let existing = existing.filter(|d| d.is_dir == src.is_dir);
+
+        if src.is_dir {
+            if existing.is_none() {
+                report.actions.push(SyncAction::MakeDir(path.clone()));
+            }
+            continue;
+        }
+
+        let offset = match existing {
+            Some(d) if d.size == src.size && same_time(d.modified, src.modified) => {
+                report.up_to_date += 1;
+                continue;
+            }
+            Some(d) if options.resume && d.size < src.size && d.modified >= src.modified => d.size,
+            _ => 0,
+        };
+        report.bytes_copied += src.size - offset;
+        report.actions.push(SyncAction::Copy {
+            path: path.clone(),
+            offset,
+            len: src.size - offset,
+        });
+    }
+    report
+}
+
+/// Starts resumed copies over when the end of what was copied doesn't match the source
+///
+/// A shorter, newer destination is usually an interrupted copy, but it can also be a
+/// different file that was written later, so the last block before the offset is compared
+/// on both sides.
+async fn check_resumes(
+    client: &mut AfcClient,
+    report: &mut SyncReport,
+    local: &Path,
+    remote: &str,
+) -> Result<(), IdeviceError> {
+    for action in &mut report.actions {
+        let SyncAction::Copy { path, offset, len } = action else {
+            continue;
+        };
+        if *offset == 0 {
+            continue;
+        }
+        let start = offset.saturating_sub(RESUME_CHECK_LEN);
+        let size = (*offset - start) as usize;
+
+        let mut local_block = vec![0; size];
+        let mut file = tokio::fs::File::open(local_path(local, path)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(&mut local_block).await?;
+
+        let mut remote_block = vec![0; size];
+        let mut file = client
+            .open(remote_path(remote, path), AfcFopenMode::RdOnly)
+            .await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(&mut remote_block).await?;
+        file.close().await?;
+
+        if local_block != remote_block {
+            debug!("{path} doesn't match its source, copying it from the start");
+            report.bytes_copied += *offset;
+            *len += *offset;
+            *offset = 0;
+        }
+    }
+    Ok(())
+}
+
+/// Compares modification times to the second, since not every filesystem keeps more
+fn same_time(a: chrono::NaiveDateTime, b: chrono::NaiveDateTime) -> bool {
+    a.and_utc().timestamp() == b.and_utc().timestamp()
+}
+
+fn is_within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).is_some_and(|p| p.starts_with('/'))
+}
+
+/// The compiled include and exclude globs
+struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    fn new(options: &SyncOptions) -> Result<Self, IdeviceError> {
+        let include = match options.include.is_empty() {
+            true => None,
+            false => Some(Self::compile(&options.include)?),
+        };
+        Ok(Self {
+            include,
+            exclude: Self::compile(&options.exclude)?,
+        })
+    }
+
+    fn compile(globs: &[String]) -> Result<GlobSet, IdeviceError> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            let glob = match glob.contains('/') {
+                true => glob.trim_start_matches('/').to_string(),
+                false => format!("**/{glob}"),
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| {
+                    warn!("Invalid glob {glob}: {e}");
+                    IdeviceError::InvalidArgument
+                })?;
+            builder.add(glob);
+        }
+        builder.build().map_err(|e| {
+            warn!("Invalid globs: {e}");
+            IdeviceError::InvalidArgument
+        })
+    }
+
+    /// Whether a path should be listed, and for directories, descended into
+    fn selected(&self, path: &str, is_dir: bool) -> bool {
+        if self.exclude.is_match(path) {
+            return false;
+        }
+        is_dir || self.include.as_ref().is_none_or(|i| i.is_match(path))
+    }
+
+    /// With include globs, drops the directories that don't lead to an included file
+    fn retain_dirs(&self, mut tree: Tree) -> Tree {
+        if self.include.is_some() {
+            let files: Vec<String> = tree
+                .iter()
+                .filter(|(_, e)| !e.is_dir)
+                .map(|(p, _)| p.clone())
+                .collect();
+            tree.retain(|p, e| !e.is_dir || files.iter().any(|f| is_within(f, p)));
+        }
+        tree
+    }
+}
+
+fn join(parent: &str, name: &str) -> String {
+    match parent.is_empty() {
+        true => name.to_string(),
+        false => format!("{parent}/{name}"),
+    }
+}
+
+fn remote_path(root: &str, path: &str) -> String {
+    format!("{}/{path}", root.trim_end_matches('/'))
+}
+
+fn local_path(root: &Path, path: &str) -> PathBuf {
+    path.split('/').fold(root.to_path_buf(), |p, c| p.join(c))
+}
+
+fn from_system_time(t: std::time::SystemTime) -> chrono::NaiveDateTime {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    chrono::DateTime::from_timestamp(d.as_secs() as i64, d.subsec_nanos())
+        .unwrap_or_default()
+        .naive_utc()
+}
+
+fn to_system_time(t: chrono::NaiveDateTime) -> Option<std::time::SystemTime> {
+    let nanos = u64::try_from(t.and_utc().timestamp_nanos_opt()?).ok()?;
+    Some(UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+/// Lists a local directory into `tree`, which is left empty if it doesn't exist
+async fn list_local(
+    root: &Path,
+    dir: &str,
+    filter: &Filter,
+    tree: &mut Tree,
+) -> Result<(), IdeviceError> {
+    let mut entries = match tokio::fs::read_dir(local_path(root, dir)).await {
+        Ok(e) => e,
+        Err(e) if dir.is_empty() && e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = join(dir, &entry.file_name().to_string_lossy());
+        let meta = entry.metadata().await?;
+        if !meta.is_dir() && !meta.is_file() {
+            debug!("Skipping {path}, which isn't a file or directory");
+            continue;
+        }
+        if !filter.selected(&path, meta.is_dir()) {
+            continue;
+        }
+        tree.insert(
+            path.clone(),
+            Entry {
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified: from_system_time(meta.modified()?),
+            },
+        );
+        if meta.is_dir() {
+            Box::pin(list_local(root, &path, filter, tree)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists a directory on the device into `tree`, which is left empty if it doesn't exist
+async fn list_remote(
+    client: &mut AfcClient,
+    root: &str,
+    dir: &str,
+    filter: &Filter,
+    tree: &mut Tree,
+) -> Result<(), IdeviceError> {
+    let full = match dir.is_empty() {
+        true => root.to_string(),
+        false => remote_path(root, dir),
+    };
+    let names = match client.list_dir(&full).await {
+        Ok(n) => n,
+        Err(IdeviceError::Afc(AfcError::ObjectNotFound)) if dir.is_empty() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in names.iter().filter(|n| *n != "." && *n != "..") {
+        let path = join(dir, name);
+        let info = client.get_file_info(remote_path(root, &path)).await?;
+        let is_dir = match info.st_ifmt.as_str() {
+            "S_IFDIR" => true,
+            "S_IFREG" => false,
+            _ => {
+                debug!("Skipping {path}, which is a {}", info.st_ifmt);
+                continue;
+            }
+        };
+        if !filter.selected(&path, is_dir) {
+            continue;
+        }
+        tree.insert(
+            path.clone(),
+            Entry {
+                is_dir,
+                size: if is_dir { 0 } else { info.size as u64 },
+                modified: info.modified,
+            },
+        );
+        if is_dir {
+            Box::pin(list_remote(client, root, &path, filter, tree)).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn remove_remote(client: &mut AfcClient, path: &str) -> Result<(), IdeviceError> {
+    if client.get_file_info(path).await?.st_ifmt == "S_IFDIR" {
+        client.remove_all(path).await
+    } else {
+        client.remove(path).await
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use crate::{
+        mock::{AfcStub, MockDevice},
+        IdeviceService,
+    };
+
+    use super::*;
+
+    /// A fresh local directory for a test
+    fn local_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("idevice-sync-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        std::fs::write(dir.join("sub/b.bin"), vec![7; 100_000]).unwrap();
+        std::fs::write(dir.join("sub/c.tmp"), "scratch").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn push_and_pull() {
+        let local = local_dir("push");
+        let fs = AfcStub::new().with_file("/Documents/old.txt", "stale");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+        let options = SyncOptions {
+            exclude: vec!["*.tmp".into()],
+            mirror: true,
+            ..Default::default()
+        };
+
+        let dry = SyncOptions {
+            dry_run: true,
+            ..options.clone()
+        };
+        let report = push(&mut client, &local, "/Documents", &dry).await.unwrap();
+        assert_eq!(report.bytes_copied, 100_005);
+        assert!(fs.file("/Documents/a.txt").is_none());
+
+        let report = push(&mut client, &local, "/Documents", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.actions[0], SyncAction::Remove("old.txt".to_string()));
+        assert_eq!(fs.file("/Documents/a.txt").unwrap(), b"hello");
+        assert_eq!(fs.file("/Documents/sub/b.bin").unwrap(), vec![7; 100_000]);
+        assert!(!fs.contains("/Documents/sub/c.tmp"));
+        assert!(!fs.contains("/Documents/old.txt"));
+
+        // Nothing changed, so nothing is copied
+        let report = push(&mut client, &local, "/Documents", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.actions, vec![]);
+        assert_eq!(report.up_to_date, 2);
+
+        // An interrupted copy only copies the rest
+        client
+            .truncate("/Documents/sub/b.bin", 40_000)
+            .await
+            .unwrap();
+        let report = push(&mut client, &local, "/Documents", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.actions,
+            vec![SyncAction::Copy {
+                path: "sub/b.bin".into(),
+                offset: 40_000,
+                len: 60_000
+            }]
+        );
+        assert_eq!(fs.file("/Documents/sub/b.bin").unwrap(), vec![7; 100_000]);
+
+        let back = local.with_extension("back");
+        let _ = std::fs::remove_dir_all(&back);
+        let options = SyncOptions {
+            include: vec!["sub/*.bin".into()],
+            ..Default::default()
+        };
+        let report = pull(&mut client, "/Documents", &back, &options)
+            .await
+            .unwrap();
+        assert_eq!(report.bytes_copied, 100_000);
+        assert_eq!(
+            std::fs::read(back.join("sub/b.bin")).unwrap(),
+            vec![7; 100_000]
+        );
+        assert!(!back.join("a.txt").exists());
+        let report = pull(&mut client, "/Documents", &back, &options)
+            .await
+            .unwrap();
+        assert_eq!(report.up_to_date, 1);
+
+        std::fs::remove_dir_all(local).unwrap();
+        std::fs::remove_dir_all(back).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_source() {
+        let local = local_dir("missing");
+        let fs = AfcStub::new().with_file("/Documents/keep.txt", "keep");
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+        let options = SyncOptions {
+            mirror: true,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            push(&mut client, local.join("typo"), "/Documents", &options).await,
+            Err(IdeviceError::NotFound)
+        ));
+        assert!(matches!(
+            push(&mut client, local.join("a.txt"), "/Documents", &options).await,
+            Err(IdeviceError::InvalidArgument)
+        ));
+        assert_eq!(fs.file("/Documents/keep.txt").unwrap(), b"keep");
+
+        std::fs::remove_dir_all(local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_different_file() {
+        let local = local_dir("resume");
+        let fs = AfcStub::new();
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut client = AfcClient::connect(&device).await.unwrap();
+        let options = SyncOptions::default();
+        push(&mut client, &local, "/Documents", &options)
+            .await
+            .unwrap();
+
+        // Shorter and newer than the source, but not a prefix of it
+        let mut file = client
+            .open("/Documents/sub/b.bin", AfcFopenMode::WrOnly)
+            .await
+            .unwrap();
+        file.write(&[9; 40_000]).await.unwrap();
+        file.close().await.unwrap();
+
+        let copy = SyncAction::Copy {
+            path: "sub/b.bin".into(),
+            offset: 0,
+            len: 100_000,
+        };
+        let dry = SyncOptions {
+            dry_run: true,
+            ..options.clone()
+        };
+        let report = push(&mut client, &local, "/Documents", &dry).await.unwrap();
+        assert_eq!(report.actions, vec![copy.clone()]);
+        assert_eq!(report.bytes_copied, 100_000);
+
+        let report = push(&mut client, &local, "/Documents", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.actions, vec![copy]);
+        assert_eq!(fs.file("/Documents/sub/b.bin").unwrap(), vec![7; 100_000]);
+
+        std::fs::remove_dir_all(local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parallel() {
+        let local = local_dir("parallel");
+        let fs = AfcStub::new();
+        let device = MockDevice::new().with_service("com.apple.afc", false, fs.clone());
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(AfcClient::connect(&device).await.unwrap());
+        }
+
+        let report = push_parallel(&mut clients, &local, "/Documents", &SyncOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(report.bytes_copied, 100_012);
+        assert_eq!(fs.file("/Documents/sub/c.tmp").unwrap(), b"scratch");
+
+        let back = local.with_extension("parallel");
+        std::fs::create_dir_all(&back).unwrap();
+        let jobs = ["a.txt", "sub/b.bin", "sub/c.tmp"]
+            .iter()
+            .map(|p| CopyJob::Pull {
+                remote: format!("/Documents/{p}"),
+                local: back.join(p.replace('/', "-")),
+            })
+            .collect();
+        assert_eq!(copy_files(&mut clients, jobs).await.unwrap(), 100_012);
+        assert_eq!(
+            std::fs::read(back.join("sub-b.bin")).unwrap(),
+            vec![7; 100_000]
+        );
+
+        let jobs = vec![CopyJob::Pull {
+            remote: "/Documents/missing".into(),
+            local: back.join("missing"),
+        }];
+        assert!(copy_files(&mut clients, jobs).await.is_err());
+        assert!(copy_files(&mut [], Vec::new()).await.is_err());
+
+        std::fs::remove_dir_all(local).unwrap();
+        std::fs::remove_dir_all(back).unwrap();
+    }
+}
@@ -13,7 +13,9 @@
 //! # Features
 //! - `tunnel_tcp_stack`: Enables software TCP/IP tunnel creation using a virtual adapter. See the tcp moduel.
 
-use crate::{obf, Idevice, IdeviceError, IdeviceService};
+use crate::IdeviceError;
+#[cfg(feature = "tokio")]
+use crate::{obf, Idevice, IdeviceService};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use serde::{Deserialize, Serialize};
@@ -82,6 +84,7 @@ impl CDTunnelPacket {
 /// A high-level client for the `com.apple.internal.devicecompute.CoreDeviceProxy` service.
 ///
 /// Handles session negotiation, handshake, and tunnel communication.
+#[cfg(feature = "tokio")]
 pub struct CoreDeviceProxy {
     /// The underlying idevice connection used for communication.
     pub idevice: Idevice,
@@ -91,6 +94,7 @@ pub struct CoreDeviceProxy {
     pub mtu: u32,
 }
 
+#[cfg(feature = "tokio")]
 impl IdeviceService for CoreDeviceProxy {
     /// Returns the name of the service used for launching the CoreDeviceProxy.
     fn service_name() -> std::borrow::Cow<'static, str> {
@@ -103,6 +107,7 @@ impl IdeviceService for CoreDeviceProxy {
 }
 
 /// Request sent to initiate the handshake with the CoreDeviceProxy.
+#[cfg(feature = "tokio")]
 #[derive(Serialize)]
 struct HandshakeRequest {
     #[serde(rename = "type")]
@@ -134,6 +139,7 @@ pub struct HandshakeResponse {
     pub server_rsd_port: u16,
 }
 
+#[cfg(feature = "tokio")]
 impl CoreDeviceProxy {
     const DEFAULT_MTU: u32 = 16000;
 
@@ -225,3 +231,33 @@ impl CoreDeviceProxy {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cdtunnel_roundtrip() {
+        let packet = CDTunnelPacket {
+            body: br#"{"type":"clientHandshakeRequest","mtu":16000}"#.to_vec(),
+        };
+        let bytes = packet.serialize().unwrap();
+        assert_eq!(CDTunnelPacket::parse(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn cdtunnel_rejects_malformed() {
+        assert!(matches!(
+            CDTunnelPacket::parse(b"CDTunnel\x00"),
+            Err(IdeviceError::CdtunnelPacketTooShort)
+        ));
+        assert!(matches!(
+            CDTunnelPacket::parse(b"CDTunnex\x00\x00"),
+            Err(IdeviceError::CdtunnelPacketInvalidMagic)
+        ));
+        assert!(matches!(
+            CDTunnelPacket::parse(b"CDTunnel\x00\x05{}"),
+            Err(IdeviceError::PacketSizeMismatch)
+        ));
+    }
+}
@@ -9,7 +9,7 @@
 
 use log::{debug, warn};
 
-use crate::{afc::AfcClient, lockdown::LockdownClient, obf, Idevice, IdeviceError, IdeviceService};
+use crate::{afc::AfcClient, obf, Idevice, IdeviceError, IdeviceService};
 
 /// Client for managing crash logs on an iOS device.
 ///
@@ -122,12 +122,7 @@ const EXPECTED_FLUSH: [u8; 4] = [0x70, 0x69, 0x6E, 0x67]; // 'ping'
 pub async fn flush_reports(
     provider: &dyn crate::provider::IdeviceProvider,
 ) -> Result<(), IdeviceError> {
-    let mut lockdown = LockdownClient::connect(provider).await?;
-    lockdown
-        .start_session(&provider.get_pairing_file().await?)
-        .await?;
-
-    let (port, ssl) = lockdown
+    let (port, ssl) = provider
         .start_service(obf!("com.apple.crashreportmover"))
         .await?;
 
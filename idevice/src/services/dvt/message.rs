@@ -59,9 +59,12 @@
 //! # }
 
 use plist::Value;
-use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncRead;
 
-use crate::IdeviceError;
+#[cfg(feature = "tokio")]
+use crate::codec;
+use crate::{codec::Decoder, IdeviceError};
 
 /// Message header containing metadata about the message
 ///
@@ -169,7 +172,7 @@ impl Aux {
     /// * `IdeviceError` for other parsing failures
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, IdeviceError> {
         if bytes.len() < 16 {
-            return Err(IdeviceError::NotEnoughBytes(bytes.len(), 24));
+            return Err(IdeviceError::NotEnoughBytes(bytes.len(), 16));
         }
 
         let header = AuxHeader {
@@ -218,7 +221,7 @@ impl Aux {
                 }
                 0x06 => {
                     if bytes.len() < 8 {
-                        return Err(IdeviceError::NotEnoughBytes(8, bytes.len()));
+                        return Err(IdeviceError::NotEnoughBytes(bytes.len(), 8));
                     }
                     values.push(AuxValue::I64(i64::from_le_bytes([
                         bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6],
@@ -391,56 +394,9 @@ impl Message {
     ///
     /// # Errors
     /// * Various IdeviceError variants for IO and parsing failures
+    #[cfg(feature = "tokio")]
     pub async fn from_reader<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self, IdeviceError> {
-        let mut buf = [0u8; 32];
-        reader.read_exact(&mut buf).await?;
-
-        let mheader = MessageHeader {
-            magic: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
-            header_len: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
-            fragment_id: u16::from_le_bytes([buf[8], buf[9]]),
-            fragment_count: u16::from_le_bytes([buf[10], buf[11]]),
-            length: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
-            identifier: u32::from_le_bytes([buf[16], buf[17], buf[18], buf[19]]),
-            conversation_index: u32::from_le_bytes([buf[20], buf[21], buf[22], buf[23]]),
-            channel: u32::from_le_bytes([buf[24], buf[25], buf[26], buf[27]]),
-            expects_reply: u32::from_le_bytes([buf[28], buf[29], buf[30], buf[31]]) == 1,
-        };
-
-        let mut buf = [0u8; 16];
-        reader.read_exact(&mut buf).await?;
-
-        let pheader = PayloadHeader {
-            flags: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
-            aux_length: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
-            total_length: u64::from_le_bytes([
-                buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15],
-            ]),
-        };
-
-        let aux = if pheader.aux_length > 0 {
-            let mut buf = vec![0u8; pheader.aux_length as usize];
-            reader.read_exact(&mut buf).await?;
-            Some(Aux::from_bytes(buf)?)
-        } else {
-            None
-        };
-
-        let mut buf = vec![0u8; (pheader.total_length - pheader.aux_length as u64) as usize];
-        reader.read_exact(&mut buf).await?;
-
-        let data = if buf.is_empty() {
-            None
-        } else {
-            Some(ns_keyed_archive::decode::from_bytes(&buf)?)
-        };
-
-        Ok(Message {
-            message_header: mheader,
-            payload_header: pheader,
-            aux,
-            data,
-        })
+        codec::read_from(&mut MessageDecoder::new(), reader).await
     }
 
     /// Creates a new message
@@ -513,3 +469,172 @@ impl std::fmt::Debug for AuxValue {
         }
     }
 }
+
+/// Sans-IO decoder for DVT messages
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    buf: Vec<u8>,
+    headers: Option<(MessageHeader, PayloadHeader)>,
+}
+
+impl MessageDecoder {
+    /// Length of the message and payload headers
+    const HEADERS_LEN: usize = 48;
+
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the whole message, once its headers are parsed
+    fn message_len(&self) -> usize {
+        match &self.headers {
+            Some((_, p)) => {
+                Self::HEADERS_LEN.saturating_add(p.total_length.try_into().unwrap_or(usize::MAX))
+            }
+            None => Self::HEADERS_LEN,
+        }
+    }
+
+    fn parse_headers(buf: &[u8]) -> Result<(MessageHeader, PayloadHeader), IdeviceError> {
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(buf[i..i + 2].try_into().unwrap());
+
+        let mheader = MessageHeader {
+            magic: u32_at(0),
+            header_len: u32_at(4),
+            fragment_id: u16_at(8),
+            fragment_count: u16_at(10),
+            length: u32_at(12),
+            identifier: u32_at(16),
+            conversation_index: u32_at(20),
+            channel: u32_at(24),
+            expects_reply: u32_at(28) == 1,
+        };
+        let pheader = PayloadHeader {
+            flags: u32_at(32),
+            aux_length: u32_at(36),
+            total_length: u64::from_le_bytes(buf[40..48].try_into().unwrap()),
+        };
+        if pheader.total_length < pheader.aux_length as u64 {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        Ok((mheader, pheader))
+    }
+}
+
+impl Decoder for MessageDecoder {
+    type Item = Message;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError> {
+        if self.headers.is_none() {
+            if self.buf.len() < Self::HEADERS_LEN {
+                return Ok(None);
+            }
+            self.headers = Some(Self::parse_headers(&self.buf)?);
+        }
+        let len = self.message_len();
+        if self.buf.len() < len {
+            return Ok(None);
+        }
+
+        let (mheader, pheader) = self.headers.take().unwrap();
+        let mut payload: Vec<u8> = self.buf.drain(..len).skip(Self::HEADERS_LEN).collect();
+        let data = payload.split_off(pheader.aux_length as usize);
+
+        let aux = if pheader.aux_length > 0 {
+            Some(Aux::from_bytes(payload)?)
+        } else {
+            None
+        };
+        let data = if data.is_empty() {
+            None
+        } else {
+            Some(ns_keyed_archive::decode::from_bytes(&data)?)
+        };
+
+        Ok(Some(Message {
+            message_header: mheader,
+            payload_header: pheader,
+            aux,
+            data,
+        }))
+    }
+
+    fn bytes_needed(&self) -> usize {
+        self.message_len().saturating_sub(self.buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_in_chunks() {
+        let message = Message::new(
+            MessageHeader::new(0, 1, 5, 0, 2, true),
+            PayloadHeader::new(),
+            Some(Aux::from_values(vec![
+                AuxValue::U32(7),
+                AuxValue::String("hello".into()),
+            ])),
+            Some(Value::String("_requestChannelWithCode:identifier:".into())),
+        );
+        let bytes = message.serialize();
+
+        let mut decoder = MessageDecoder::new();
+        for chunk in bytes.chunks(5) {
+            assert!(decoder.decode().unwrap().is_none());
+            decoder.push(chunk);
+        }
+        let decoded = decoder.decode().unwrap().unwrap();
+        assert_eq!(decoded.message_header.identifier, 5);
+        assert_eq!(decoded.message_header.channel, 2);
+        assert_eq!(decoded.aux.unwrap().values, message.aux.unwrap().values);
+        assert_eq!(decoded.data, message.data);
+        assert_eq!(decoder.bytes_needed(), MessageDecoder::HEADERS_LEN);
+    }
+
+    /// An aux buffer with an empty header followed by `values`
+    fn aux_bytes(values: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 16];
+        bytes.extend_from_slice(values);
+        bytes
+    }
+
+    #[test]
+    fn aux_rejects_malformed() {
+        assert!(matches!(
+            Aux::from_bytes(vec![0; 15]),
+            Err(IdeviceError::NotEnoughBytes(15, 16))
+        ));
+
+        // A string claiming more bytes than are left
+        let mut bytes = aux_bytes(&[1, 0, 0, 0, 100, 0, 0, 0]);
+        bytes.extend_from_slice(b"abc");
+        assert!(matches!(
+            Aux::from_bytes(bytes),
+            Err(IdeviceError::NotEnoughBytes(3, 100))
+        ));
+
+        let bytes = aux_bytes(&[1, 0, 0, 0, 2, 0, 0, 0, 0xff, 0xfe]);
+        assert!(matches!(Aux::from_bytes(bytes), Err(IdeviceError::Utf8(_))));
+
+        let bytes = aux_bytes(&[6, 0, 0, 0, 1, 2, 3, 4]);
+        assert!(matches!(
+            Aux::from_bytes(bytes),
+            Err(IdeviceError::NotEnoughBytes(4, 8))
+        ));
+
+        let bytes = aux_bytes(&[0x42, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(
+            Aux::from_bytes(bytes),
+            Err(IdeviceError::UnknownAuxValueType(0x42))
+        ));
+    }
+}
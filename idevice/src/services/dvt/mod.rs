@@ -1,13 +1,17 @@
 // Jackson Coxson
 
+#[cfg(feature = "rsd")]
 use crate::{obf, IdeviceError, ReadWrite, RsdService};
 
 #[cfg(feature = "location_simulation")]
 pub mod location_simulation;
 pub mod message;
+#[cfg(feature = "tokio")]
 pub mod process_control;
+#[cfg(feature = "tokio")]
 pub mod remote_server;
 
+#[cfg(feature = "rsd")]
 impl RsdService for remote_server::RemoteServerClient<Box<dyn ReadWrite>> {
     fn rsd_service_name() -> std::borrow::Cow<'static, str> {
         obf!("com.apple.instruments.dtservicehub")
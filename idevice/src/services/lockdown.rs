@@ -5,7 +5,7 @@
 
 use log::error;
 use plist::Value;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{obf, pairing_file, Idevice, IdeviceError, IdeviceService};
 
@@ -18,6 +18,8 @@ use crate::{obf, pairing_file, Idevice, IdeviceError, IdeviceService};
 pub struct LockdownClient {
     /// The underlying device connection with established lockdown service
     pub idevice: crate::Idevice,
+    /// The ID of the active TLS session, if one was started
+    session_id: Option<String>,
 }
 
 impl IdeviceService for LockdownClient {
@@ -39,7 +41,8 @@ impl IdeviceService for LockdownClient {
     async fn connect(
         provider: &dyn crate::provider::IdeviceProvider,
     ) -> Result<Self, IdeviceError> {
-        let idevice = provider.connect(Self::LOCKDOWND_PORT).await?;
+        let mut idevice = provider.connect(Self::LOCKDOWND_PORT).await?;
+        idevice.set_service_name(Self::service_name());
         Ok(Self::new(idevice))
     }
 
@@ -57,6 +60,195 @@ struct LockdownRequest {
     request: String,
 }
 
+/// Storage usage of the device, from the `com.apple.disk_usage` domain
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DiskUsage {
+    /// Total size of the storage in bytes
+    pub total_disk_capacity: Option<u64>,
+    /// Size of the data partition in bytes
+    pub total_data_capacity: Option<u64>,
+    /// Free space on the data partition in bytes
+    pub total_data_available: Option<u64>,
+    /// Size of the system partition in bytes
+    pub total_system_capacity: Option<u64>,
+    /// Free space on the system partition in bytes
+    pub total_system_available: Option<u64>,
+    /// Free space usable by apps in bytes
+    pub amount_data_available: Option<u64>,
+    /// Space reserved by the system in bytes
+    pub amount_data_reserved: Option<u64>,
+}
+
+/// Battery state of the device, from the `com.apple.mobile.battery` domain
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BatteryInfo {
+    /// Charge level as a percentage
+    pub battery_current_capacity: Option<u64>,
+    /// Whether the battery is charging
+    pub battery_is_charging: Option<bool>,
+    /// Whether external power is connected
+    pub external_connected: Option<bool>,
+    /// Whether the connected power source can charge the device
+    pub external_charge_capable: Option<bool>,
+    /// Whether the battery is fully charged
+    pub fully_charged: Option<bool>,
+    /// Whether the device has a battery
+    pub has_battery: Option<bool>,
+}
+
+/// Well-known values of the root lockdown domain
+///
+/// Keys the device doesn't report, such as `PhoneNumber` on devices without a SIM, are `None`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceInfo {
+    /// The user-assigned name of the device
+    pub device_name: Option<String>,
+    /// The kind of device, such as `iPhone` or `iPad`
+    pub device_class: Option<String>,
+    /// The color code of the device
+    pub device_color: Option<String>,
+    /// The iOS version, such as `17.4.1`
+    pub product_version: Option<String>,
+    /// The iOS build, such as `21E236`
+    pub build_version: Option<String>,
+    /// The marketing model identifier, such as `iPhone14,2`
+    pub product_type: Option<String>,
+    /// The board identifier, such as `D63AP`
+    pub hardware_model: Option<String>,
+    /// The model number sold in a region, such as `MLPF3`
+    pub model_number: Option<String>,
+    /// The region the device was sold in, such as `LL/A`
+    pub region_info: Option<String>,
+    /// The CPU architecture, such as `arm64e`
+    #[serde(rename = "CPUArchitecture")]
+    pub cpu_architecture: Option<String>,
+    /// The UDID of the device
+    #[serde(rename = "UniqueDeviceID")]
+    pub unique_device_id: Option<String>,
+    /// The ECID of the device
+    #[serde(rename = "UniqueChipID")]
+    pub unique_chip_id: Option<u64>,
+    /// The ID of the device's SoC
+    #[serde(rename = "ChipID")]
+    pub chip_id: Option<u64>,
+    /// The serial number of the device
+    pub serial_number: Option<String>,
+    /// The activation state, such as `Activated` or `Unactivated`
+    pub activation_state: Option<String>,
+    /// The time zone of the device, such as `America/Denver`
+    pub time_zone: Option<String>,
+    /// The Wi-Fi MAC address of the device
+    #[serde(rename = "WiFiAddress")]
+    pub wifi_address: Option<String>,
+    /// The Bluetooth MAC address of the device
+    pub bluetooth_address: Option<String>,
+    /// The phone number of the SIM
+    pub phone_number: Option<String>,
+    /// Whether a passcode is set on the device
+    pub password_protected: Option<bool>,
+}
+
+impl DeviceInfo {
+    /// Returns the parsed iOS version, if the device reported one
+    pub fn ios_version(&self) -> Option<IosVersion> {
+        self.product_version.as_deref()?.parse().ok()
+    }
+}
+
+/// A parsed iOS version, ordered so features can be gated on it
+///
+/// # Example
+/// ```rust
+/// use idevice::lockdown::IosVersion;
+///
+/// let version: IosVersion = "17.4.1".parse()?;
+/// assert!(version >= IosVersion::new(17, 4, 0));
+/// assert!(version.uses_rsd());
+/// assert_eq!(version.to_string(), "17.4.1");
+/// # Ok::<(), idevice::IdeviceError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IosVersion {
+    /// The major version, such as 17 in 17.4.1
+    pub major: u16,
+    /// The minor version, such as 4 in 17.4.1
+    pub minor: u16,
+    /// The patch version, such as 1 in 17.4.1. 0 if the version has no patch component.
+    pub patch: u16,
+}
+
+impl IosVersion {
+    /// Creates a version from its components
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Returns whether services are reached through RSD over a CoreDeviceProxy tunnel instead of
+    /// lockdown's StartService. This is the case for developer services starting with iOS 17.
+    pub fn uses_rsd(&self) -> bool {
+        self.major >= 17
+    }
+}
+
+impl std::str::FromStr for IosVersion {
+    type Err = IdeviceError;
+
+    /// Parses versions like `17`, `17.4` and `17.4.1`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('.').map(|p| p.parse::<u16>());
+        let mut next = |required: bool| match parts.next() {
+            Some(Ok(p)) => Ok(p),
+            None if !required => Ok(0),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        };
+        let version = Self::new(next(true)?, next(false)?, next(false)?);
+        if parts.next().is_some() {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        Ok(version)
+    }
+}
+
+impl std::fmt::Display for IosVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch != 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// A PEM encoded certificate along with its private key
+#[cfg(feature = "pair")]
+#[derive(Clone, Debug)]
+pub struct PairingIdentity {
+    /// The certificate, PEM or DER encoded
+    pub certificate: Vec<u8>,
+    /// The RSA private key, PEM encoded in PKCS#8 or PKCS#1 form
+    pub private_key: Vec<u8>,
+}
+
+/// Options for [`LockdownClient::pair_with_options`]
+#[cfg(feature = "pair")]
+#[derive(Clone, Debug, Default)]
+pub struct PairingOptions {
+    /// An existing root CA to issue the pair record from, such as an organisation-wide root.
+    /// A new root is generated when `None`. A reused root's private key only signs the host and
+    /// device certificates, it isn't stored in the record.
+    pub root_ca: Option<PairingIdentity>,
+    /// The supervisor identity the device was supervised with. Supervised devices pair without
+    /// a trust dialog when the pairing challenge is signed with it.
+    pub supervisor: Option<PairingIdentity>,
+}
+
 impl LockdownClient {
     /// The default TCP port for the lockdown service
     pub const LOCKDOWND_PORT: u16 = 62078;
@@ -66,7 +258,26 @@ impl LockdownClient {
     /// # Arguments
     /// * `idevice` - Pre-established device connection
     pub fn new(idevice: Idevice) -> Self {
-        Self { idevice }
+        Self {
+            idevice,
+            session_id: None,
+        }
+    }
+
+    /// Returns the ID of the active session, if one was started
+    pub fn session_id(&self) -> Option<&str> {
+        self.session_id.as_deref()
+    }
+
+    /// Queries the type of the service on the other end of the connection
+    ///
+    /// # Returns
+    /// The service type, `com.apple.mobile.lockdown` for lockdownd
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn query_type(&mut self) -> Result<String, IdeviceError> {
+        self.idevice.get_type().await
     }
 
     /// Retrieves a specific value from the device
@@ -114,6 +325,85 @@ impl LockdownClient {
         }
     }
 
+    /// Retrieves every value in a domain and deserializes it
+    ///
+    /// # Arguments
+    /// * `domain` - The domain to retrieve, such as `com.apple.mobile.battery`
+    ///
+    /// # Returns
+    /// The domain deserialized into `T`
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the domain doesn't match `T`
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn example(
+    /// #     client: &mut idevice::lockdown::LockdownClient,
+    /// # ) -> Result<(), idevice::IdeviceError> {
+    /// let battery: plist::Dictionary = client.get_domain("com.apple.mobile.battery").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_domain<T: DeserializeOwned>(
+        &mut self,
+        domain: &str,
+    ) -> Result<T, IdeviceError> {
+        let value = self.get_value(None, Some(domain)).await?;
+        Ok(plist::from_value(&value)?)
+    }
+
+    /// Retrieves every value in the root domain
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn get_all_values(&mut self) -> Result<plist::Dictionary, IdeviceError> {
+        match self.get_value(None, None).await? {
+            Value::Dictionary(d) => Ok(d),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Retrieves the well-known values of the root domain
+    ///
+    /// Some values, such as the serial number, are only returned once a session is started.
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn get_device_info(&mut self) -> Result<DeviceInfo, IdeviceError> {
+        let values = self.get_all_values().await?;
+        Ok(plist::from_value(&Value::Dictionary(values))?)
+    }
+
+    /// Retrieves and parses the iOS version of the device
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::UnexpectedResponse` if the version can't be parsed, or another
+    /// `IdeviceError` if communication fails
+    pub async fn ios_version(&mut self) -> Result<IosVersion, IdeviceError> {
+        self.get_value(Some("ProductVersion"), None)
+            .await?
+            .as_string()
+            .ok_or(IdeviceError::UnexpectedResponse)?
+            .parse()
+    }
+
+    /// Retrieves the storage usage of the device
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn get_disk_usage(&mut self) -> Result<DiskUsage, IdeviceError> {
+        self.get_domain("com.apple.disk_usage").await
+    }
+
+    /// Retrieves the battery state of the device
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn get_battery(&mut self) -> Result<BatteryInfo, IdeviceError> {
+        self.get_domain("com.apple.mobile.battery").await
+    }
+
     /// Sets a value on the device
     ///
     /// # Arguments
@@ -156,6 +446,107 @@ impl LockdownClient {
         Ok(())
     }
 
+    /// Enables or disables connecting to the device over Wi-Fi
+    ///
+    /// Once enabled, the device advertises itself over mDNS and accepts lockdown connections
+    /// on the network. Requires an active session.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether Wi-Fi connections should be accepted
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the device refuses the change
+    pub async fn set_wifi_connections(&mut self, enabled: bool) -> Result<(), IdeviceError> {
+        self.set_value(
+            "EnableWifiConnections",
+            enabled.into(),
+            Some("com.apple.mobile.wireless_lockdown"),
+        )
+        .await
+    }
+
+    /// Checks whether the device accepts connections over Wi-Fi
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the response is malformed
+    pub async fn wifi_connections_enabled(&mut self) -> Result<bool, IdeviceError> {
+        let value = self
+            .get_value(
+                Some("EnableWifiConnections"),
+                Some("com.apple.mobile.wireless_lockdown"),
+            )
+            .await?;
+        value.as_boolean().ok_or(IdeviceError::UnexpectedResponse)
+    }
+
+    /// Removes a value from the device
+    ///
+    /// # Arguments
+    /// * `key` - The key to remove, or `None` to remove the whole domain
+    /// * `domain` - An optional domain to remove from
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the device refuses the removal
+    pub async fn remove_value(
+        &mut self,
+        key: Option<&str>,
+        domain: Option<&str>,
+    ) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "RemoveValue".into());
+
+        if let Some(key) = key {
+            req.insert("Key".into(), key.into());
+        }
+        if let Some(domain) = domain {
+            req.insert("Domain".into(), domain.into());
+        }
+
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+
+        Ok(())
+    }
+
+    /// Reboots the device into recovery mode
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails or the device refuses
+    pub async fn enter_recovery(&mut self) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "EnterRecovery".into());
+
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+
+        Ok(())
+    }
+
+    /// Tells lockdownd that the client is done with the connection
+    ///
+    /// lockdownd closes the connection after replying, so the client is consumed.
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if communication fails
+    pub async fn goodbye(mut self) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "Goodbye".into());
+
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+
+        Ok(())
+    }
+
     /// Starts a secure TLS session with the device
     ///
     /// # Arguments
@@ -211,11 +602,46 @@ impl LockdownClient {
                 return Err(IdeviceError::UnexpectedResponse);
             }
         }
+        self.session_id = response
+            .get("SessionID")
+            .and_then(|x| x.as_string())
+            .map(|x| x.to_string());
 
         self.idevice.start_session(pairing_file).await?;
         Ok(())
     }
 
+    /// Stops the active session with the device
+    ///
+    /// lockdownd drops back to plaintext after this while the connection is still wrapped in
+    /// TLS, so the client is consumed. Connect again to start a new session.
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if:
+    /// - No session is active
+    /// - The device refuses to stop the session
+    pub async fn stop_session(mut self) -> Result<(), IdeviceError> {
+        let session_id = match self.session_id.take() {
+            Some(s) => s,
+            None => return Err(IdeviceError::SessionInactive),
+        };
+
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "StopSession".into());
+        req.insert("SessionID".into(), session_id.into());
+
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        let res = self.idevice.read_plist().await?;
+
+        match res.get("Result").and_then(|x| x.as_string()) {
+            Some("Success") => Ok(()),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
     /// Requests to start a service on the device
     ///
     /// # Arguments
@@ -265,6 +691,63 @@ impl LockdownClient {
         }
     }
 
+    /// Asks the device to check a pairing record
+    ///
+    /// Newer iOS versions may not implement this, prefer [`pairing_file::PairingFile::verify_against`].
+    ///
+    /// # Arguments
+    /// * `pairing_file` - The pairing record to validate
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::InvalidHostID` if the device doesn't know the record, or another
+    /// `IdeviceError` if communication fails
+    pub async fn validate_pair(
+        &mut self,
+        pairing_file: &pairing_file::PairingFile,
+    ) -> Result<(), IdeviceError> {
+        self.send_pair_request("ValidatePair", pairing_file).await
+    }
+
+    /// Removes the device's trust of a pairing record
+    ///
+    /// This does not delete the host's copy of the record. When the record is stored in usbmuxd,
+    /// remove it there as well.
+    ///
+    /// # Arguments
+    /// * `pairing_file` - The pairing record to revoke
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::InvalidHostID` if the device doesn't know the record, or another
+    /// `IdeviceError` if communication fails
+    pub async fn unpair(
+        &mut self,
+        pairing_file: &pairing_file::PairingFile,
+    ) -> Result<(), IdeviceError> {
+        self.send_pair_request("Unpair", pairing_file).await
+    }
+
+    async fn send_pair_request(
+        &mut self,
+        request: &str,
+        pairing_file: &pairing_file::PairingFile,
+    ) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), request.into());
+        req.insert(
+            "PairRecord".into(),
+            plist::Value::Dictionary(pairing_file.public_pair_record()),
+        );
+        req.insert("ProtocolVersion".into(), "2".into());
+
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+
+        Ok(())
+    }
+
     /// Generates a pairing file and sends it to the device for trusting.
     /// Note that this does NOT save the file to usbmuxd's cache. That's a responsibility of the
     /// caller.
@@ -284,6 +767,32 @@ impl LockdownClient {
         &mut self,
         host_id: impl Into<String>,
         system_buid: impl Into<String>,
+    ) -> Result<crate::pairing_file::PairingFile, IdeviceError> {
+        self.pair_with_options(host_id, system_buid, &PairingOptions::default())
+            .await
+    }
+
+    /// Generates a pairing file using the given options and sends it to the device for trusting.
+    /// Note that this does NOT save the file to usbmuxd's cache. That's a responsibility of the
+    /// caller.
+    ///
+    /// # Arguments
+    /// * `host_id` - The host ID, in the form of a UUID. Typically generated from the host name
+    /// * `system_buid` - UUID fetched from usbmuxd. Doesn't appear to affect function.
+    /// * `pairing_options` - The root CA and supervisor identity to pair with
+    ///
+    /// # Returns
+    /// The newly generated pairing record
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::CertificateError` if the supplied identities can't be used, or
+    /// another `IdeviceError` if pairing fails
+    #[cfg(feature = "pair")]
+    pub async fn pair_with_options(
+        &mut self,
+        host_id: impl Into<String>,
+        system_buid: impl Into<String>,
+        pairing_options: &PairingOptions,
     ) -> Result<crate::pairing_file::PairingFile, IdeviceError> {
         let host_id = host_id.into();
         let system_buid = system_buid.into();
@@ -306,25 +815,51 @@ impl LockdownClient {
             }
         };
 
-        let ca = crate::ca::generate_certificates(&pub_key, None).unwrap();
+        let root = match &pairing_options.root_ca {
+            Some(root) => Some((
+                crate::ca::parse_private_key(&root.private_key)
+                    .map_err(|e| IdeviceError::CertificateError(e.to_string()))?,
+                crate::ca::parse_cert(&root.certificate)
+                    .map_err(|e| IdeviceError::CertificateError(e.to_string()))?,
+            )),
+            None => None,
+        };
+        let ca = crate::ca::generate_certificates(&pub_key, root.as_ref().map(|(k, c)| (k, c)))
+            .map_err(|e| IdeviceError::CertificateError(e.to_string()))?;
+
+        let supervisor = match &pairing_options.supervisor {
+            Some(s) => Some((
+                crate::ca::parse_cert(&s.certificate)
+                    .map_err(|e| IdeviceError::CertificateError(e.to_string()))?,
+                crate::ca::parse_private_key(&s.private_key)
+                    .map_err(|e| IdeviceError::CertificateError(e.to_string()))?,
+            )),
+            None => None,
+        };
+
         let mut pair_record = plist::Dictionary::new();
         pair_record.insert("DevicePublicKey".into(), plist::Value::Data(pub_key));
         pair_record.insert("DeviceCertificate".into(), plist::Value::Data(ca.dev_cert));
-        pair_record.insert(
-            "HostCertificate".into(),
-            plist::Value::Data(ca.host_cert),
-        );
+        pair_record.insert("HostCertificate".into(), plist::Value::Data(ca.host_cert));
         pair_record.insert("HostID".into(), host_id.into());
         pair_record.insert("RootCertificate".into(), plist::Value::Data(ca.root_cert));
-        pair_record.insert(
-            "RootPrivateKey".into(),
-            plist::Value::Data(ca.private_key.clone()),
-        );
+        // A reused root's key stays with the caller, only generated roots are stored
+        if let Some(root_private_key) = ca.root_private_key {
+            pair_record.insert(
+                "RootPrivateKey".into(),
+                plist::Value::Data(root_private_key),
+            );
+        }
         pair_record.insert("WiFiMACAddress".into(), wifi_mac.into());
         pair_record.insert("SystemBUID".into(), system_buid.into());
 
         let mut options = plist::Dictionary::new();
         options.insert("ExtendedPairingErrors".into(), true.into());
+        if let Some((cert, _)) = &supervisor {
+            let cert = x509_cert::der::Encode::to_der(cert)
+                .map_err(|e| IdeviceError::CertificateError(e.to_string()))?;
+            options.insert("SupervisorCertificate".into(), plist::Value::Data(cert));
+        }
 
         let mut req = plist::Dictionary::new();
         req.insert("Label".into(), self.idevice.label.clone().into());
@@ -340,7 +875,10 @@ impl LockdownClient {
             self.idevice.send_plist(req.clone().into()).await?;
             match self.idevice.read_plist().await {
                 Ok(escrow) => {
-                    pair_record.insert("HostPrivateKey".into(), plist::Value::Data(ca.private_key));
+                    pair_record.insert(
+                        "HostPrivateKey".into(),
+                        plist::Value::Data(ca.host_private_key),
+                    );
                     if let Some(escrow) = escrow.get("EscrowBag").and_then(|x| x.as_data()) {
                         pair_record.insert("EscrowBag".into(), plist::Value::Data(escrow.to_vec()));
                     }
@@ -354,6 +892,19 @@ impl LockdownClient {
                 Err(IdeviceError::PairingDialogResponsePending) => {
                     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 }
+                Err(IdeviceError::PairingChallengeRequired(challenge)) => {
+                    let (cert, key) = match &supervisor {
+                        Some(s) => s,
+                        None => break Err(IdeviceError::PairingChallengeRequired(challenge)),
+                    };
+                    let response = crate::ca::sign_challenge(&challenge, cert, key)
+                        .map_err(|e| IdeviceError::CertificateError(e.to_string()))?;
+
+                    let mut options = plist::Dictionary::new();
+                    options.insert("ExtendedPairingErrors".into(), true.into());
+                    options.insert("ChallengeResponse".into(), plist::Value::Data(response));
+                    req.insert("PairingOptions".into(), plist::Value::Dictionary(options));
+                }
                 Err(e) => break Err(e),
             }
         }
@@ -366,3 +917,228 @@ impl From<Idevice> for LockdownClient {
         Self::new(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "mock")]
+    use crate::provider::IdeviceProvider;
+
+    #[test]
+    fn ios_version() {
+        assert_eq!(
+            "17.4.1".parse::<IosVersion>().unwrap(),
+            IosVersion::new(17, 4, 1)
+        );
+        assert_eq!(
+            "16.7".parse::<IosVersion>().unwrap(),
+            IosVersion::new(16, 7, 0)
+        );
+        assert_eq!(
+            "18".parse::<IosVersion>().unwrap(),
+            IosVersion::new(18, 0, 0)
+        );
+        assert!("17.a".parse::<IosVersion>().is_err());
+        assert!("17.4.1.2".parse::<IosVersion>().is_err());
+        assert!("".parse::<IosVersion>().is_err());
+
+        assert!(IosVersion::new(17, 4, 1) > IosVersion::new(17, 4, 0));
+        assert!(IosVersion::new(16, 7, 10) < IosVersion::new(17, 0, 0));
+        assert!(IosVersion::new(17, 0, 0).uses_rsd());
+        assert!(!IosVersion::new(16, 7, 0).uses_rsd());
+        assert_eq!(IosVersion::new(17, 4, 0).to_string(), "17.4");
+        assert_eq!(IosVersion::new(17, 4, 1).to_string(), "17.4.1");
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn device_info() {
+        let device = crate::mock::MockDevice::new().with_value(None, "UniqueChipID", 1234u64);
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+
+        let info = lockdown.get_device_info().await.unwrap();
+        assert_eq!(info.product_type.as_deref(), Some("iPhone14,2"));
+        assert_eq!(info.unique_chip_id, Some(1234));
+        assert_eq!(info.ios_version(), Some(IosVersion::new(17, 0, 0)));
+        assert_eq!(info.phone_number, None);
+        assert_eq!(
+            lockdown.ios_version().await.unwrap(),
+            IosVersion::new(17, 0, 0)
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn stop_session() {
+        let device = crate::mock::MockDevice::new();
+        let pairing_file = device.get_pairing_file().await.unwrap();
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        lockdown.start_session(&pairing_file).await.unwrap();
+        assert_eq!(lockdown.session_id(), Some("MOCK-SESSION"));
+        lockdown.stop_session().await.unwrap();
+
+        let lockdown = LockdownClient::connect(&device).await.unwrap();
+        assert!(matches!(
+            lockdown.stop_session().await,
+            Err(IdeviceError::SessionInactive)
+        ));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn remove_value() {
+        let device = crate::mock::MockDevice::new()
+            .with_value(Some("com.example"), "First", 1u64)
+            .with_value(Some("com.example"), "Second", 2u64);
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+
+        lockdown
+            .remove_value(Some("First"), Some("com.example"))
+            .await
+            .unwrap();
+        assert_eq!(device.value(Some("com.example"), "First"), None);
+        assert_eq!(
+            device.value(Some("com.example"), "Second"),
+            Some(2u64.into())
+        );
+
+        lockdown
+            .remove_value(None, Some("com.example"))
+            .await
+            .unwrap();
+        assert_eq!(device.value(Some("com.example"), "Second"), None);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn get_domain() {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Battery {
+            battery_current_capacity: u64,
+            battery_is_charging: bool,
+        }
+
+        let device = crate::mock::MockDevice::new()
+            .with_value(
+                Some("com.apple.mobile.battery"),
+                "BatteryCurrentCapacity",
+                80u64,
+            )
+            .with_value(Some("com.apple.mobile.battery"), "BatteryIsCharging", true);
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+
+        let battery: Battery = lockdown
+            .get_domain("com.apple.mobile.battery")
+            .await
+            .unwrap();
+        assert_eq!(battery.battery_current_capacity, 80);
+        assert!(battery.battery_is_charging);
+
+        assert!(lockdown
+            .get_domain::<Battery>("com.example.missing")
+            .await
+            .is_err());
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn enter_recovery_and_goodbye() {
+        let device = crate::mock::MockDevice::new();
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        lockdown.enter_recovery().await.unwrap();
+        lockdown.goodbye().await.unwrap();
+
+        // lockdownd closes the connection after Goodbye, but still accepts new ones
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        assert_eq!(
+            lockdown.query_type().await.unwrap(),
+            "com.apple.mobile.lockdown"
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn validate_and_unpair() {
+        let device = crate::mock::MockDevice::new();
+        let pairing_file = device.get_pairing_file().await.unwrap();
+        let mut unknown = pairing_file.clone();
+        unknown.host_id = "00000000-0000-0000-0000-000000000000".to_string();
+
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        lockdown.validate_pair(&pairing_file).await.unwrap();
+        assert!(matches!(
+            lockdown.validate_pair(&unknown).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+        assert!(matches!(
+            lockdown.unpair(&unknown).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+        assert!(device.is_paired());
+
+        lockdown.unpair(&pairing_file).await.unwrap();
+        assert!(!device.is_paired());
+        assert!(matches!(
+            lockdown.validate_pair(&pairing_file).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+    }
+
+    #[cfg(all(feature = "mock", feature = "pair"))]
+    #[tokio::test]
+    async fn pair_with_reused_root() {
+        use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+        use x509_cert::der::Encode;
+
+        let root_key = rsa::RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+        let root_cert = crate::ca::make_cert(
+            &root_key,
+            &rsa::RsaPublicKey::from(&root_key),
+            Some("Fleet CA"),
+            true,
+            x509_cert::builder::Profile::Root,
+        )
+        .unwrap();
+        let options = PairingOptions {
+            root_ca: Some(PairingIdentity {
+                certificate: root_cert.to_der().unwrap(),
+                private_key: root_key
+                    .to_pkcs8_pem(LineEnding::LF)
+                    .unwrap()
+                    .as_bytes()
+                    .to_vec(),
+            }),
+            supervisor: None,
+        };
+
+        let device = crate::mock::MockDevice::new();
+        let mut lockdown = LockdownClient::connect(&device).await.unwrap();
+        let pairing_file = lockdown
+            .pair_with_options("host-id", "system-buid", &options)
+            .await
+            .unwrap();
+
+        // The root's key must never be written to the record
+        assert!(pairing_file.root_private_key.is_empty());
+        assert_ne!(
+            crate::ca::parse_private_key(&pairing_file.host_private_key).unwrap(),
+            root_key
+        );
+        assert_eq!(
+            crate::ca::parse_cert(&pairing_file.host_certificate)
+                .unwrap()
+                .tbs_certificate
+                .issuer,
+            root_cert.tbs_certificate.subject
+        );
+        assert_eq!(pairing_file.escrow_bag, b"escrow");
+
+        let serialized = pairing_file.serialize().unwrap();
+        let record: plist::Dictionary = plist::from_bytes(&serialized).unwrap();
+        assert!(!record.contains_key("RootPrivateKey"));
+        assert!(record.contains_key("HostPrivateKey"));
+    }
+}
@@ -22,6 +22,7 @@ pub mod heartbeat;
 pub mod house_arrest;
 #[cfg(feature = "installation_proxy")]
 pub mod installation_proxy;
+#[cfg(feature = "tokio")]
 pub mod lockdown;
 #[cfg(feature = "misagent")]
 pub mod misagent;
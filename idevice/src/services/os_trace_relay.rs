@@ -206,88 +206,91 @@ impl OsTraceRelayReceiver {
         let packet_length = u32::from_le_bytes([pl[0], pl[1], pl[2], pl[3]]);
 
         let packet = self.inner.idevice.read_raw(packet_length as usize).await?;
+        OsTraceLog::parse(&packet)
+    }
+}
+
+/// Splits the first `n` bytes off a packet
+fn take<'a>(packet: &mut &'a [u8], n: usize) -> Result<&'a [u8], IdeviceError> {
+    if packet.len() < n {
+        return Err(IdeviceError::NotEnoughBytes(packet.len(), n));
+    }
+    let (head, tail) = packet.split_at(n);
+    *packet = tail;
+    Ok(head)
+}
+
+/// Reads a string of `n` bytes, including its null terminator
+fn take_string(packet: &mut &[u8], n: usize) -> Result<String, IdeviceError> {
+    let bytes = take(packet, n)?;
+    let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+impl OsTraceLog {
+    /// Parses a log entry from the body of a packet sent by the relay
+    ///
+    /// # Arguments
+    /// * `packet` - The packet, without its marker byte and length
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the packet is truncated or malformed
+    pub fn parse(mut packet: &[u8]) -> Result<Self, IdeviceError> {
+        let packet = &mut packet;
+        let u16_at = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+        let u32_at = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
 
         // 9 bytes of padding
-        let packet = &packet[9..];
+        take(packet, 9)?;
 
         // Parse PID (4 bytes)
-        let pid = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
-        let packet = &packet[4..];
+        let pid = u32_at(take(packet, 4)?);
 
         // Skip 42 unknown bytes
-        let packet = &packet[42..];
+        take(packet, 42)?;
 
         // Parse timestamp (seconds + microseconds)
-        let seconds = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
-        let packet = &packet[8..]; // skip 4 bytes padding after seconds
-        let microseconds = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
-        let packet = &packet[4..];
+        let seconds = u32_at(take(packet, 8)?); // 4 bytes padding after seconds
+        let microseconds = u32_at(take(packet, 4)?);
 
         // Skip 1 byte padding
-        let packet = &packet[1..];
+        take(packet, 1)?;
 
         // Parse log level
-        let log_level = packet[0];
-        let log_level: LogLevel = log_level.try_into()?;
-        let packet = &packet[1..];
+        let log_level: LogLevel = take(packet, 1)?[0].try_into()?;
 
         // Skip 38 unknown bytes
-        let packet = &packet[38..];
+        take(packet, 38)?;
 
         // Parse string sizes
-        let image_name_size = u16::from_le_bytes([packet[0], packet[1]]) as usize;
-        let packet = &packet[2..];
-        let message_size = u16::from_le_bytes([packet[0], packet[1]]) as usize;
-        let packet = &packet[2..];
+        let image_name_size = u16_at(take(packet, 2)?) as usize;
+        let message_size = u16_at(take(packet, 2)?) as usize;
 
         // Skip 6 bytes
-        let packet = &packet[6..];
+        take(packet, 6)?;
 
         // Parse subsystem and category sizes
-        let subsystem_size =
-            u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]) as usize;
-        let packet = &packet[4..];
-        let category_size =
-            u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]) as usize;
-        let packet = &packet[4..];
+        let subsystem_size = u32_at(take(packet, 4)?) as usize;
+        let category_size = u32_at(take(packet, 4)?) as usize;
 
         // Skip 4 bytes
-        let packet = &packet[4..];
+        take(packet, 4)?;
 
         // Parse filename (null-terminated string)
         let filename_end = packet
             .iter()
             .position(|&b| b == 0)
             .ok_or(IdeviceError::UnexpectedResponse)?;
-        let filename = String::from_utf8_lossy(&packet[..filename_end]).into_owned();
-        let packet = &packet[filename_end + 1..];
-
-        // Parse image name
-        let image_name_bytes = &packet[..image_name_size];
-        let image_name =
-            String::from_utf8_lossy(&image_name_bytes[..image_name_bytes.len() - 1]).into_owned();
-        let packet = &packet[image_name_size..];
+        let filename = take_string(packet, filename_end + 1)?;
 
-        // Parse message
-        let message_bytes = &packet[..message_size];
-        let message =
-            String::from_utf8_lossy(&message_bytes[..message_bytes.len() - 1]).into_owned();
-        let packet = &packet[message_size..];
+        let image_name = take_string(packet, image_name_size)?;
+        let message = take_string(packet, message_size)?;
 
         // Parse label if subsystem and category exist
         let label = if subsystem_size > 0 && category_size > 0 && !packet.is_empty() {
-            let subsystem_bytes = &packet[..subsystem_size];
-            let subsystem =
-                String::from_utf8_lossy(&subsystem_bytes[..subsystem_bytes.len() - 1]).into_owned();
-            let packet = &packet[subsystem_size..];
-
-            let category_bytes = &packet[..category_size];
-            let category =
-                String::from_utf8_lossy(&category_bytes[..category_bytes.len() - 1]).into_owned();
-
             Some(SyslogLabel {
-                subsystem,
-                category,
+                subsystem: take_string(packet, subsystem_size)?,
+                category: take_string(packet, category_size)?,
             })
         } else {
             None
@@ -324,3 +327,52 @@ impl TryFrom<u8> for LogLevel {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet() -> Vec<u8> {
+        let mut packet = vec![0; 9];
+        packet.extend_from_slice(&42_u32.to_le_bytes());
+        packet.extend_from_slice(&[0; 42]);
+        packet.extend_from_slice(&1_700_000_000_u64.to_le_bytes());
+        packet.extend_from_slice(&5_u32.to_le_bytes());
+        packet.extend_from_slice(&[0, 0x10]);
+        packet.extend_from_slice(&[0; 38]);
+        packet.extend_from_slice(&12_u16.to_le_bytes());
+        packet.extend_from_slice(&6_u16.to_le_bytes());
+        packet.extend_from_slice(&[0; 6]);
+        packet.extend_from_slice(&4_u32.to_le_bytes());
+        packet.extend_from_slice(&3_u32.to_le_bytes());
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(b"/usr/libexec/foo\0springboard\0hello\0com\0ui\0");
+        packet
+    }
+
+    #[test]
+    fn parse() {
+        let log = OsTraceLog::parse(&packet()).unwrap();
+        assert_eq!(log.pid, 42);
+        assert_eq!(log.filename, "/usr/libexec/foo");
+        assert_eq!(log.level, LogLevel::Error);
+        assert_eq!(log.image_name, "springboard");
+        assert_eq!(log.message, "hello");
+        assert_eq!(
+            log.label,
+            Some(SyslogLabel {
+                subsystem: "com".into(),
+                category: "ui".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let packet = packet();
+        // The label is optional, so stop before it
+        for len in 0..packet.len() - 7 {
+            assert!(OsTraceLog::parse(&packet[..len]).is_err());
+        }
+    }
+}
@@ -540,7 +540,8 @@ impl Adapter {
     pub(crate) async fn process_tcp_packet(&mut self) -> Result<(), std::io::Error> {
         loop {
             let ip_packet = self.read_ip_packet().await?;
-            let res = TcpPacket::parse(&ip_packet)?;
+            let res = TcpPacket::parse(&ip_packet)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
             let mut ack_me = None;
             if let Some(state) = self.states.get_mut(&res.destination_port) {
                 if state.peer_seq > res.sequence_number {
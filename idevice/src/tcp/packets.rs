@@ -11,6 +11,8 @@ use tokio::{
     sync::Mutex,
 };
 
+use crate::IdeviceError;
+
 pub enum ProtocolNumber {
     Tcp = 6,
 }
@@ -35,17 +37,20 @@ pub struct Ipv4Packet {
 }
 
 impl Ipv4Packet {
-    pub fn parse(packet: &[u8]) -> Option<Self> {
+    pub fn parse(packet: &[u8]) -> Result<Self, IdeviceError> {
         if packet.len() < 20 {
-            return None;
+            return Err(IdeviceError::NotEnoughBytes(packet.len(), 20));
         }
 
         let version_ihl = packet[0];
         let version = version_ihl >> 4;
         let ihl = (version_ihl & 0x0F) * 4; // send help I don't understand bitwise ops
 
-        if version != 4 || packet.len() < ihl as usize {
-            return None;
+        if version != 4 || ihl < 20 {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        if packet.len() < ihl as usize {
+            return Err(IdeviceError::NotEnoughBytes(packet.len(), ihl as usize));
         }
 
         let tos = packet[1];
@@ -68,12 +73,18 @@ impl Ipv4Packet {
         };
 
         let payload = if total_length as usize > options_end {
-            packet[options_end..total_length as usize].to_vec()
+            packet
+                .get(options_end..total_length as usize)
+                .ok_or(IdeviceError::NotEnoughBytes(
+                    packet.len(),
+                    total_length as usize,
+                ))?
+                .to_vec()
         } else {
             Vec::new()
         };
 
-        Some(Self {
+        Ok(Self {
             version,
             ihl,
             tos,
@@ -221,14 +232,14 @@ pub struct Ipv6Packet {
 }
 
 impl Ipv6Packet {
-    pub fn parse(packet: &[u8]) -> Option<Self> {
+    pub fn parse(packet: &[u8]) -> Result<Self, IdeviceError> {
         if packet.len() < 40 {
-            return None;
+            return Err(IdeviceError::NotEnoughBytes(packet.len(), 40));
         }
 
         let version = packet[0] >> 4;
         if version != 6 {
-            return None;
+            return Err(IdeviceError::UnexpectedResponse);
         }
 
         let traffic_class = ((packet[0] & 0x0F) << 4) | (packet[1] >> 4);
@@ -260,7 +271,7 @@ impl Ipv6Packet {
         );
         let payload = packet[40..].to_vec();
 
-        Some(Self {
+        Ok(Self {
             version,
             traffic_class,
             flow_label,
@@ -455,12 +466,9 @@ pub struct TcpPacket {
 }
 
 impl TcpPacket {
-    pub fn parse(packet: &[u8]) -> Result<Self, std::io::Error> {
+    pub fn parse(packet: &[u8]) -> Result<Self, IdeviceError> {
         if packet.len() < 20 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Not enough bytes for TCP header",
-            ));
+            return Err(IdeviceError::NotEnoughBytes(packet.len(), 20));
         }
 
         let source_port = u16::from_be_bytes([packet[0], packet[1]]);
@@ -469,6 +477,10 @@ impl TcpPacket {
         let acknowledgment_number =
             u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
         let data_offset = (packet[12] >> 4) * 4; // Convert from 32-bit words to bytes
+        if data_offset < 20 {
+            // The offset can't point inside the fixed header
+            return Err(IdeviceError::UnexpectedResponse);
+        }
         let flags = TcpFlags::from_byte(packet[13]); // Parse flags
         let window_size = u16::from_be_bytes([packet[14], packet[15]]);
         let checksum = u16::from_be_bytes([packet[16], packet[17]]);
@@ -713,4 +725,74 @@ mod tests {
         let t1 = TcpPacket::parse(&b1);
         println!("{t1:#?}");
     }
+
+    #[test]
+    fn malformed() {
+        let mut b1 = Ipv4Packet::create(
+            Ipv4Addr::new(127, 0, 0, 1),
+            Ipv4Addr::new(1, 1, 1, 1),
+            ProtocolNumber::Tcp,
+            255,
+            &[1, 2, 3, 4, 5],
+        );
+        b1.truncate(b1.len() - 1);
+        assert!(matches!(
+            Ipv4Packet::parse(&b1),
+            Err(IdeviceError::NotEnoughBytes(24, 25))
+        ));
+        b1[0] = 0x41;
+        assert!(matches!(
+            Ipv4Packet::parse(&b1),
+            Err(IdeviceError::UnexpectedResponse)
+        ));
+        assert!(matches!(
+            Ipv4Packet::parse(&b1[..19]),
+            Err(IdeviceError::NotEnoughBytes(19, 20))
+        ));
+
+        let mut b2 = TcpPacket::create(
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            1234,
+            5678,
+            420,
+            6969,
+            TcpFlags {
+                urg: false,
+                ack: true,
+                psh: false,
+                rst: false,
+                syn: false,
+                fin: false,
+            },
+            5555,
+            &[],
+        );
+        b2[12] = 0;
+        assert!(matches!(
+            TcpPacket::parse(&b2),
+            Err(IdeviceError::UnexpectedResponse)
+        ));
+        assert!(matches!(
+            TcpPacket::parse(&b2[..10]),
+            Err(IdeviceError::NotEnoughBytes(10, 20))
+        ));
+
+        let mut b3 = Ipv6Packet::create(
+            Ipv6Addr::LOCALHOST,
+            Ipv6Addr::LOCALHOST,
+            ProtocolNumber::Tcp,
+            255,
+            &[1, 2, 3],
+        );
+        assert!(matches!(
+            Ipv6Packet::parse(&b3[..39]),
+            Err(IdeviceError::NotEnoughBytes(39, 40))
+        ));
+        b3[0] = 0x40;
+        assert!(matches!(
+            Ipv6Packet::parse(&b3),
+            Err(IdeviceError::UnexpectedResponse)
+        ));
+    }
 }
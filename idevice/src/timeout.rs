@@ -0,0 +1,211 @@
+// Jackson Coxson
+// Read and write deadlines for device sockets
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::ReadWrite;
+
+/// Read and write timeouts shared between an `Idevice` and its socket
+///
+/// Stored as milliseconds, with 0 meaning no timeout.
+#[derive(Debug, Default)]
+pub(crate) struct IoTimeouts {
+    read: AtomicU64,
+    write: AtomicU64,
+}
+
+impl IoTimeouts {
+    pub(crate) fn read(&self) -> Option<Duration> {
+        Self::load(&self.read)
+    }
+
+    pub(crate) fn write(&self) -> Option<Duration> {
+        Self::load(&self.write)
+    }
+
+    pub(crate) fn set_read(&self, timeout: Option<Duration>) {
+        Self::store(&self.read, timeout)
+    }
+
+    pub(crate) fn set_write(&self, timeout: Option<Duration>) {
+        Self::store(&self.write, timeout)
+    }
+
+    fn load(v: &AtomicU64) -> Option<Duration> {
+        match v.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    fn store(v: &AtomicU64, timeout: Option<Duration>) {
+        // Round sub-millisecond timeouts up so they don't turn into "no timeout"
+        let ms = timeout.map(|t| (t.as_millis() as u64).max(1)).unwrap_or(0);
+        v.store(ms, Ordering::Relaxed);
+    }
+}
+
+/// A socket that fails reads and writes that make no progress within the configured timeouts
+///
+/// The timer starts when an operation first returns pending and is reset whenever it completes,
+/// so slow but steady transfers never time out.
+pub(crate) struct TimeoutStream {
+    inner: Box<dyn ReadWrite>,
+    timeouts: Arc<IoTimeouts>,
+    read_sleep: Option<Pin<Box<Sleep>>>,
+    write_sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl TimeoutStream {
+    pub(crate) fn new(inner: Box<dyn ReadWrite>, timeouts: Arc<IoTimeouts>) -> Self {
+        Self {
+            inner,
+            timeouts,
+            read_sleep: None,
+            write_sleep: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for TimeoutStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimeoutStream")
+            .field("inner", &self.inner)
+            .field("timeouts", &self.timeouts)
+            .finish()
+    }
+}
+
+/// Polls the deadline of a pending operation, starting it if needed
+fn poll_deadline(
+    sleep: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+    op: &str,
+) -> Poll<io::Error> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None => {
+            *sleep = None;
+            return Poll::Pending;
+        }
+    };
+    let s = sleep.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    match s.as_mut().poll(cx) {
+        Poll::Ready(()) => {
+            *sleep = None;
+            Poll::Ready(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{op} timed out after {timeout:?}"),
+            ))
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+/// Resets the deadline of an operation once it completes
+fn finish<T>(sleep: &mut Option<Pin<Box<Sleep>>>, res: Poll<T>) -> Poll<T> {
+    if res.is_ready() {
+        *sleep = None;
+    }
+    res
+}
+
+impl AsyncRead for TimeoutStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Pending => {
+                poll_deadline(&mut this.read_sleep, this.timeouts.read(), cx, "read").map(Err)
+            }
+            res => finish(&mut this.read_sleep, res),
+        }
+    }
+}
+
+impl AsyncWrite for TimeoutStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Pending => {
+                poll_deadline(&mut this.write_sleep, this.timeouts.write(), cx, "write").map(Err)
+            }
+            res => finish(&mut this.write_sleep, res),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_flush(cx) {
+            Poll::Pending => {
+                poll_deadline(&mut this.write_sleep, this.timeouts.write(), cx, "flush").map(Err)
+            }
+            res => finish(&mut this.write_sleep, res),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_shutdown(cx) {
+            Poll::Pending => {
+                poll_deadline(&mut this.write_sleep, this.timeouts.write(), cx, "shutdown").map(Err)
+            }
+            res => finish(&mut this.write_sleep, res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn read_times_out() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let timeouts = Arc::new(IoTimeouts::default());
+        timeouts.set_read(Some(Duration::from_millis(50)));
+        let mut stream = TimeoutStream::new(Box::new(client), timeouts.clone());
+
+        server.write_all(b"hi").await.unwrap();
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+
+        let e = stream.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        // Clearing the timeout waits for data again
+        timeouts.set_read(None);
+        let read = tokio::spawn(async move {
+            stream.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.write_all(b"yo").await.unwrap();
+        assert_eq!(&read.await.unwrap(), b"yo");
+    }
+}
@@ -20,6 +20,8 @@ use crate::{
 
 mod des;
 mod raw_packet;
+#[cfg(feature = "usbmuxd_server")]
+pub mod server;
 
 /// Represents the connection type of a device
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -43,10 +45,48 @@ pub struct UsbmuxdDevice {
     pub device_id: u32,
 }
 
+/// Encoding used for plist messages exchanged with usbmuxd
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UsbmuxdPlistFormat {
+    /// XML plists, understood by every muxer
+    #[default]
+    Xml,
+    /// Binary plists, understood by Apple's usbmuxd and some third-party muxers
+    Binary,
+}
+
+impl UsbmuxdPlistFormat {
+    /// Detects the encoding of a serialized plist
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"bplist00") {
+            Self::Binary
+        } else {
+            Self::Xml
+        }
+    }
+}
+
 /// Active connection to the usbmuxd service
 pub struct UsbmuxdConnection {
     socket: Box<dyn ReadWrite>,
     tag: u32,
+    plist_format: UsbmuxdPlistFormat,
+}
+
+/// A device event sent by usbmuxd to listening clients
+#[derive(Debug, Clone)]
+pub enum UsbmuxdListenEvent {
+    /// A device was attached to the muxer
+    Attached(UsbmuxdDevice),
+    /// The device with this usbmuxd-assigned ID was detached
+    Detached(u32),
+    /// The device with this usbmuxd-assigned ID was paired with the host
+    Paired(u32),
+}
+
+/// A usbmuxd connection that has subscribed to device events
+pub struct UsbmuxdListener {
+    inner: UsbmuxdConnection,
 }
 
 /// Address of the usbmuxd service
@@ -92,6 +132,33 @@ impl UsbmuxdAddr {
         Ok(UsbmuxdConnection::new(socket, tag))
     }
 
+    /// Creates a new usbmuxd connection, speaking binary plists if the muxer supports them
+    ///
+    /// Some muxers close the connection when they receive a plist they can't parse, so the
+    /// probe is done on a throwaway connection and a fresh one is opened afterwards.
+    ///
+    /// # Arguments
+    /// * `tag` - Connection tag/identifier
+    ///
+    /// # Returns
+    /// A connected `UsbmuxdConnection` using the best supported plist format
+    pub async fn connect_negotiated(&self, tag: u32) -> Result<UsbmuxdConnection, IdeviceError> {
+        let mut probe = self.connect(tag).await?;
+        let format = match probe.probe_binary_plist().await {
+            Ok(true) => UsbmuxdPlistFormat::Binary,
+            Ok(false) => UsbmuxdPlistFormat::Xml,
+            Err(e) => {
+                debug!("Binary plist probe failed, falling back to XML: {e:?}");
+                UsbmuxdPlistFormat::Xml
+            }
+        };
+        debug!("Negotiated usbmuxd plist format: {format:?}");
+
+        let mut conn = self.connect(tag).await?;
+        conn.set_plist_format(format);
+        Ok(conn)
+    }
+
     /// Creates a UsbmuxdAddr from environment variable
     ///
     /// Checks `USBMUXD_SOCKET_ADDRESS` environment variable, falls back to default
@@ -140,6 +207,16 @@ impl UsbmuxdConnection {
 
     /// Result message type
     pub const RESULT_MESSAGE_TYPE: u32 = 1;
+    /// Legacy connect message type
+    pub const CONNECT_MESSAGE_TYPE: u32 = 2;
+    /// Legacy listen message type
+    pub const LISTEN_MESSAGE_TYPE: u32 = 3;
+    /// Legacy device attached message type
+    pub const DEVICE_ADD_MESSAGE_TYPE: u32 = 4;
+    /// Legacy device detached message type
+    pub const DEVICE_REMOVE_MESSAGE_TYPE: u32 = 5;
+    /// Legacy device paired message type
+    pub const DEVICE_PAIRED_MESSAGE_TYPE: u32 = 6;
     /// PLIST message type
     pub const PLIST_MESSAGE_TYPE: u32 = 8;
 
@@ -155,6 +232,7 @@ impl UsbmuxdConnection {
         Ok(Self {
             socket: Box::new(socket),
             tag: 0,
+            plist_format: UsbmuxdPlistFormat::Xml,
         })
     }
 
@@ -164,7 +242,51 @@ impl UsbmuxdConnection {
     /// * `socket` - The transport stream
     /// * `tag` - Connection tag/identifier
     pub fn new(socket: Box<dyn ReadWrite>, tag: u32) -> Self {
-        Self { socket, tag }
+        Self {
+            socket,
+            tag,
+            plist_format: UsbmuxdPlistFormat::Xml,
+        }
+    }
+
+    /// Returns the plist format used for outgoing messages
+    pub fn plist_format(&self) -> UsbmuxdPlistFormat {
+        self.plist_format
+    }
+
+    /// Sets the plist format used for outgoing messages
+    ///
+    /// Responses are always parsed in whichever format the muxer replies with.
+    pub fn set_plist_format(&mut self, format: UsbmuxdPlistFormat) {
+        self.plist_format = format;
+    }
+
+    /// Checks whether the muxer accepts binary plists
+    ///
+    /// Sends a `ReadBUID` request encoded as a binary plist. On success the connection keeps
+    /// speaking binary, otherwise it is switched back to XML.
+    ///
+    /// # Returns
+    /// `true` if the muxer understood the binary request
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the muxer closed the connection. The connection should not be
+    /// reused afterwards.
+    pub async fn probe_binary_plist(&mut self) -> Result<bool, IdeviceError> {
+        self.plist_format = UsbmuxdPlistFormat::Binary;
+        let res = self.get_buid().await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(IdeviceError::Socket(e)) => {
+                self.plist_format = UsbmuxdPlistFormat::Xml;
+                Err(IdeviceError::Socket(e))
+            }
+            Err(e) => {
+                debug!("Muxer rejected binary plist: {e:?}");
+                self.plist_format = UsbmuxdPlistFormat::Xml;
+                Ok(false)
+            }
+        }
     }
 
     /// Lists all connected devices
@@ -187,64 +309,10 @@ impl UsbmuxdConnection {
         let res = plist::to_value(&res)?;
         let res = plist::from_value::<des::ListDevicesResponse>(&res)?;
 
-        let mut devs = Vec::new();
-        for dev in res.device_list {
-            let connection_type = match dev.properties.connection_type.as_str() {
-                "Network" => {
-                    if let Some(addr) = dev.properties.network_address {
-                        let addr = &Into::<Vec<u8>>::into(addr);
-                        if addr.len() < 8 {
-                            warn!("Device address bytes len < 8");
-                            return Err(IdeviceError::UnexpectedResponse);
-                        }
-
-                        match addr[0] {
-                            0x02 => {
-                                // IPv4
-                                Connection::Network(IpAddr::V4(Ipv4Addr::new(
-                                    addr[4], addr[5], addr[6], addr[7],
-                                )))
-                            }
-                            0x1E => {
-                                // IPv6
-                                if addr.len() < 24 {
-                                    warn!("IPv6 address is less than 24 bytes");
-                                    return Err(IdeviceError::UnexpectedResponse);
-                                }
-
-                                Connection::Network(IpAddr::V6(Ipv6Addr::new(
-                                    u16::from_be_bytes([addr[8], addr[9]]),
-                                    u16::from_be_bytes([addr[10], addr[11]]),
-                                    u16::from_be_bytes([addr[12], addr[13]]),
-                                    u16::from_be_bytes([addr[14], addr[15]]),
-                                    u16::from_be_bytes([addr[16], addr[17]]),
-                                    u16::from_be_bytes([addr[18], addr[19]]),
-                                    u16::from_be_bytes([addr[20], addr[21]]),
-                                    u16::from_be_bytes([addr[22], addr[23]]),
-                                )))
-                            }
-                            _ => {
-                                warn!("Unknown IP address protocol: {:02X}", addr[0]);
-                                Connection::Unknown(format!("Network {:02X}", addr[0]))
-                            }
-                        }
-                    } else {
-                        warn!("Device is network attached, but has no network info");
-                        return Err(IdeviceError::UnexpectedResponse);
-                    }
-                }
-                "USB" => Connection::Usb,
-                _ => Connection::Unknown(dev.properties.connection_type),
-            };
-            debug!("Connection type: {connection_type:?}");
-            devs.push(UsbmuxdDevice {
-                connection_type,
-                udid: dev.properties.serial_number,
-                device_id: dev.device_id,
-            })
-        }
-
-        Ok(devs)
+        res.device_list
+            .into_iter()
+            .map(UsbmuxdDevice::try_from)
+            .collect()
     }
 
     /// Gets a specific device by UDID
@@ -283,6 +351,57 @@ impl UsbmuxdConnection {
         }
     }
 
+    /// Stores a pairing record in usbmuxd
+    ///
+    /// Other tools on the host that talk to usbmuxd, such as libimobiledevice and Xcode, will
+    /// pick up the record. The record's `system_buid` should match the muxer's BUID from
+    /// [`Self::get_buid`], otherwise the device will not accept it from those tools.
+    ///
+    /// # Arguments
+    /// * `device_id` - usbmuxd device ID, used by the muxer to send a `Paired` event
+    /// * `udid` - The device UDID the record belongs to
+    /// * `pairing_file` - The pairing record to save
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if serialization fails or usbmuxd rejects the record
+    pub async fn save_pair_record(
+        &mut self,
+        device_id: u32,
+        udid: &str,
+        pairing_file: &PairingFile,
+    ) -> Result<(), IdeviceError> {
+        debug!("Saving pair record for {udid}");
+        let mut req = plist::Dictionary::new();
+        req.insert("MessageType".into(), "SavePairRecord".into());
+        req.insert("PairRecordID".into(), udid.into());
+        req.insert(
+            "PairRecordData".into(),
+            plist::Value::Data(pairing_file.clone().serialize()?),
+        );
+        req.insert("DeviceID".into(), device_id.into());
+        self.write_plist(req).await?;
+        self.read_result().await
+    }
+
+    /// Deletes a pairing record from usbmuxd
+    ///
+    /// This only removes the host's copy of the record. To revoke the trust on the device
+    /// itself, unpair through lockdown as well.
+    ///
+    /// # Arguments
+    /// * `udid` - The device UDID whose record should be removed
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if usbmuxd has no such record or rejects the request
+    pub async fn delete_pair_record(&mut self, udid: &str) -> Result<(), IdeviceError> {
+        debug!("Deleting pair record for {udid}");
+        let mut req = plist::Dictionary::new();
+        req.insert("MessageType".into(), "DeletePairRecord".into());
+        req.insert("PairRecordID".into(), udid.into());
+        self.write_plist(req).await?;
+        self.read_result().await
+    }
+
     /// Gets the BUID
     ///
     /// # Returns
@@ -322,9 +441,35 @@ impl UsbmuxdConnection {
         req.insert("DeviceID".into(), device_id.into());
         req.insert("PortNumber".into(), port.into());
         self.write_plist(req).await?;
+        self.read_result().await?;
+        Ok(Idevice::new(self.socket, label))
+    }
+
+    /// Subscribes to device attach and detach notifications
+    ///
+    /// Consumes the connection, as usbmuxd only sends events on the socket
+    /// after a `Listen` request has been accepted.
+    ///
+    /// # Returns
+    /// A `UsbmuxdListener` yielding events as devices come and go
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if usbmuxd rejects the request
+    pub async fn listen(mut self) -> Result<UsbmuxdListener, IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("MessageType".into(), "Listen".into());
+        req.insert("ClientVersionString".into(), "idevice-rs".into());
+        req.insert("kLibUSBMuxVersion".into(), 3.into());
+        self.write_plist(req).await?;
+        self.read_result().await?;
+        Ok(UsbmuxdListener { inner: self })
+    }
+
+    /// Reads a `Result` message from usbmuxd and maps its code to an error
+    async fn read_result(&mut self) -> Result<(), IdeviceError> {
         match self.read_plist().await?.get("Number") {
             Some(plist::Value::Integer(i)) => match i.as_unsigned() {
-                Some(0) => Ok(Idevice::new(self.socket, label)),
+                Some(0) => Ok(()),
                 Some(1) => Err(IdeviceError::UsbBadCommand),
                 Some(2) => Err(IdeviceError::UsbBadDevice),
                 Some(3) => Err(IdeviceError::UsbConnectionRefused),
@@ -337,11 +482,12 @@ impl UsbmuxdConnection {
 
     /// Writes a PLIST message to usbmuxd
     async fn write_plist(&mut self, req: plist::Dictionary) -> Result<(), IdeviceError> {
-        let raw = raw_packet::RawPacket::new(
+        let raw = raw_packet::RawPacket::new_with_format(
             req,
             Self::XML_PLIST_VERSION,
             Self::PLIST_MESSAGE_TYPE,
             self.tag,
+            self.plist_format,
         );
 
         let raw: Vec<u8> = raw.into();
@@ -351,24 +497,159 @@ impl UsbmuxdConnection {
     }
 
     /// Reads a PLIST message from usbmuxd
+    ///
+    /// Legacy binary messages (protocol version 0) are translated into the equivalent
+    /// plist dictionary, so callers only ever deal with one shape.
     async fn read_plist(&mut self) -> Result<plist::Dictionary, IdeviceError> {
         let mut header_buffer = [0; 16];
         self.socket.read_exact(&mut header_buffer).await?;
 
         // We are safe to unwrap as it only panics if the buffer isn't 4
-        let packet_size = u32::from_le_bytes(header_buffer[..4].try_into().unwrap()) - 16;
+        let packet_size = u32::from_le_bytes(header_buffer[..4].try_into().unwrap());
+        let version = u32::from_le_bytes(header_buffer[4..8].try_into().unwrap());
+        let message = u32::from_le_bytes(header_buffer[8..12].try_into().unwrap());
+        let packet_size = match packet_size.checked_sub(16) {
+            Some(p) => p,
+            None => {
+                warn!("Muxer sent a packet smaller than its header");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
         debug!("Reading {packet_size} bytes from muxer");
 
         let mut body_buffer = vec![0; packet_size as usize];
         self.socket.read_exact(&mut body_buffer).await?;
 
-        let res = plist::from_bytes(&body_buffer)?;
+        let res = if version == Self::BINARY_PLIST_VERSION && message != Self::PLIST_MESSAGE_TYPE {
+            legacy_to_plist(message, &body_buffer)?
+        } else {
+            plist::from_bytes(&body_buffer)?
+        };
         debug!("Read from muxer: {}", crate::pretty_print_dictionary(&res));
 
         Ok(res)
     }
 }
 
+/// Translates a legacy binary usbmuxd message into its plist equivalent
+fn legacy_to_plist(message: u32, body: &[u8]) -> Result<plist::Dictionary, IdeviceError> {
+    let read_u32 = |offset: usize| -> Result<u32, IdeviceError> {
+        body.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .ok_or(IdeviceError::NotEnoughBytes(body.len(), offset + 4))
+    };
+
+    let mut res = plist::Dictionary::new();
+    match message {
+        UsbmuxdConnection::RESULT_MESSAGE_TYPE => {
+            res.insert("MessageType".into(), "Result".into());
+            res.insert("Number".into(), read_u32(0)?.into());
+        }
+        UsbmuxdConnection::DEVICE_ADD_MESSAGE_TYPE => {
+            // struct usbmuxd_device_record {
+            //     u32 device_id; u16 product_id; char serial_number[256];
+            //     u16 padding; u32 location;
+            // }
+            if body.len() < 268 {
+                return Err(IdeviceError::NotEnoughBytes(body.len(), 268));
+            }
+            let device_id = read_u32(0)?;
+            let product_id = u16::from_le_bytes([body[4], body[5]]);
+            let serial = &body[6..262];
+            let serial = match serial.iter().position(|x| *x == 0) {
+                Some(end) => &serial[..end],
+                None => serial,
+            };
+            let serial = String::from_utf8(serial.to_vec())?;
+            let location = read_u32(264)?;
+
+            let mut props = plist::Dictionary::new();
+            props.insert("ConnectionType".into(), "USB".into());
+            props.insert("DeviceID".into(), device_id.into());
+            props.insert("LocationID".into(), location.into());
+            props.insert("ProductID".into(), product_id.into());
+            props.insert("SerialNumber".into(), serial.into());
+
+            res.insert("MessageType".into(), "Attached".into());
+            res.insert("DeviceID".into(), device_id.into());
+            res.insert("Properties".into(), props.into());
+        }
+        UsbmuxdConnection::DEVICE_REMOVE_MESSAGE_TYPE => {
+            res.insert("MessageType".into(), "Detached".into());
+            res.insert("DeviceID".into(), read_u32(0)?.into());
+        }
+        UsbmuxdConnection::DEVICE_PAIRED_MESSAGE_TYPE => {
+            res.insert("MessageType".into(), "Paired".into());
+            res.insert("DeviceID".into(), read_u32(0)?.into());
+        }
+        _ => {
+            warn!("Unknown legacy usbmuxd message type {message}");
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+    }
+    Ok(res)
+}
+
+impl TryFrom<des::DeviceListResponse> for UsbmuxdDevice {
+    type Error = IdeviceError;
+
+    fn try_from(dev: des::DeviceListResponse) -> Result<Self, Self::Error> {
+        let connection_type = match dev.properties.connection_type.as_str() {
+            "Network" => {
+                if let Some(addr) = dev.properties.network_address {
+                    let addr = &Into::<Vec<u8>>::into(addr);
+                    if addr.len() < 8 {
+                        warn!("Device address bytes len < 8");
+                        return Err(IdeviceError::UnexpectedResponse);
+                    }
+
+                    match addr[0] {
+                        0x02 => {
+                            // IPv4
+                            Connection::Network(IpAddr::V4(Ipv4Addr::new(
+                                addr[4], addr[5], addr[6], addr[7],
+                            )))
+                        }
+                        0x1E => {
+                            // IPv6
+                            if addr.len() < 24 {
+                                warn!("IPv6 address is less than 24 bytes");
+                                return Err(IdeviceError::UnexpectedResponse);
+                            }
+
+                            Connection::Network(IpAddr::V6(Ipv6Addr::new(
+                                u16::from_be_bytes([addr[8], addr[9]]),
+                                u16::from_be_bytes([addr[10], addr[11]]),
+                                u16::from_be_bytes([addr[12], addr[13]]),
+                                u16::from_be_bytes([addr[14], addr[15]]),
+                                u16::from_be_bytes([addr[16], addr[17]]),
+                                u16::from_be_bytes([addr[18], addr[19]]),
+                                u16::from_be_bytes([addr[20], addr[21]]),
+                                u16::from_be_bytes([addr[22], addr[23]]),
+                            )))
+                        }
+                        _ => {
+                            warn!("Unknown IP address protocol: {:02X}", addr[0]);
+                            Connection::Unknown(format!("Network {:02X}", addr[0]))
+                        }
+                    }
+                } else {
+                    warn!("Device is network attached, but has no network info");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            }
+            "USB" => Connection::Usb,
+            _ => Connection::Unknown(dev.properties.connection_type),
+        };
+        debug!("Connection type: {connection_type:?}");
+        Ok(UsbmuxdDevice {
+            connection_type,
+            udid: dev.properties.serial_number,
+            device_id: dev.device_id,
+        })
+    }
+}
+
 impl UsbmuxdDevice {
     /// Creates a provider for this device
     ///
@@ -391,3 +672,209 @@ impl UsbmuxdDevice {
         }
     }
 }
+
+impl UsbmuxdListener {
+    /// Waits for the next device event from usbmuxd
+    ///
+    /// [`Self::into_stream`] gives the events as a [`futures::Stream`] instead.
+    ///
+    /// # Returns
+    /// The next attach, detach or pair event
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the socket closes or the event is malformed
+    pub async fn next(&mut self) -> Result<UsbmuxdListenEvent, IdeviceError> {
+        loop {
+            let res = self.inner.read_plist().await?;
+            let device_id = || {
+                res.get("DeviceID")
+                    .and_then(|x| x.as_unsigned_integer())
+                    .map(|x| x as u32)
+                    .ok_or(IdeviceError::UnexpectedResponse)
+            };
+
+            match res.get("MessageType").and_then(|x| x.as_string()) {
+                Some("Attached") => {
+                    let dev = plist::from_value::<des::DeviceListResponse>(
+                        &plist::Value::Dictionary(res),
+                    )?;
+                    return Ok(UsbmuxdListenEvent::Attached(dev.try_into()?));
+                }
+                Some("Detached") => return Ok(UsbmuxdListenEvent::Detached(device_id()?)),
+                Some("Paired") => return Ok(UsbmuxdListenEvent::Paired(device_id()?)),
+                Some(t) => {
+                    warn!("Unknown usbmuxd event type: {t}");
+                }
+                None => return Err(IdeviceError::UnexpectedResponse),
+            }
+        }
+    }
+
+    /// Turns the listener into a stream of device events
+    ///
+    /// The stream ends after the first error, as the connection can't be read from after one.
+    pub fn into_stream(
+        self,
+    ) -> impl futures::Stream<Item = Result<UsbmuxdListenEvent, IdeviceError>> + Send {
+        futures::stream::unfold(Some(self), |listener| async move {
+            let mut listener = listener?;
+            match listener.next().await {
+                Ok(event) => Some((Ok(event), Some(listener))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    async fn send_to_client(socket: &mut tokio::io::DuplexStream, plist: plist::Dictionary) {
+        let raw: Vec<u8> = raw_packet::RawPacket::new(
+            plist,
+            UsbmuxdConnection::XML_PLIST_VERSION,
+            UsbmuxdConnection::PLIST_MESSAGE_TYPE,
+            0,
+        )
+        .into();
+        socket.write_all(&raw).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn listen_events() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let muxer = tokio::spawn(async move {
+            let mut header = [0u8; 16];
+            server.read_exact(&mut header).await.unwrap();
+            let size = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize - 16;
+            let mut body = vec![0; size];
+            server.read_exact(&mut body).await.unwrap();
+            let req: plist::Dictionary = plist::from_bytes(&body).unwrap();
+            assert_eq!(
+                req.get("MessageType").and_then(|x| x.as_string()),
+                Some("Listen")
+            );
+
+            let mut res = plist::Dictionary::new();
+            res.insert("MessageType".into(), "Result".into());
+            res.insert("Number".into(), 0.into());
+            send_to_client(&mut server, res).await;
+
+            let mut props = plist::Dictionary::new();
+            props.insert("ConnectionType".into(), "USB".into());
+            props.insert("SerialNumber".into(), "00008030-001".into());
+            let mut attached = plist::Dictionary::new();
+            attached.insert("MessageType".into(), "Attached".into());
+            attached.insert("DeviceID".into(), 7.into());
+            attached.insert("Properties".into(), props.into());
+            send_to_client(&mut server, attached).await;
+
+            let mut detached = plist::Dictionary::new();
+            detached.insert("MessageType".into(), "Detached".into());
+            detached.insert("DeviceID".into(), 7.into());
+            send_to_client(&mut server, detached).await;
+        });
+
+        let mut listener = UsbmuxdConnection::new(Box::new(client), 0)
+            .listen()
+            .await
+            .unwrap();
+
+        match listener.next().await.unwrap() {
+            UsbmuxdListenEvent::Attached(dev) => {
+                assert_eq!(dev.device_id, 7);
+                assert_eq!(dev.udid, "00008030-001");
+                assert_eq!(dev.connection_type, Connection::Usb);
+            }
+            e => panic!("unexpected event {e:?}"),
+        }
+        muxer.await.unwrap();
+
+        // The muxer hung up after the last event
+        let mut events = Box::pin(listener.into_stream());
+        assert!(matches!(
+            events.next().await.unwrap(),
+            Ok(UsbmuxdListenEvent::Detached(7))
+        ));
+        assert!(events.next().await.unwrap().is_err());
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn legacy_messages() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let muxer = tokio::spawn(async move {
+            let mut record = vec![0u8; 268];
+            record[..4].copy_from_slice(&3u32.to_le_bytes());
+            record[4..6].copy_from_slice(&0x12a8u16.to_le_bytes());
+            record[6..18].copy_from_slice(b"00008030-001");
+            record[264..].copy_from_slice(&0x1234u32.to_le_bytes());
+
+            let mut packet = Vec::new();
+            packet.extend_from_slice(&(16 + record.len() as u32).to_le_bytes());
+            packet.extend_from_slice(&UsbmuxdConnection::BINARY_PLIST_VERSION.to_le_bytes());
+            packet.extend_from_slice(&UsbmuxdConnection::DEVICE_ADD_MESSAGE_TYPE.to_le_bytes());
+            packet.extend_from_slice(&0u32.to_le_bytes());
+            packet.extend_from_slice(&record);
+
+            packet.extend_from_slice(&20u32.to_le_bytes());
+            packet.extend_from_slice(&UsbmuxdConnection::BINARY_PLIST_VERSION.to_le_bytes());
+            packet.extend_from_slice(&UsbmuxdConnection::DEVICE_REMOVE_MESSAGE_TYPE.to_le_bytes());
+            packet.extend_from_slice(&0u32.to_le_bytes());
+            packet.extend_from_slice(&3u32.to_le_bytes());
+            server.write_all(&packet).await.unwrap();
+        });
+
+        let mut listener = UsbmuxdListener {
+            inner: UsbmuxdConnection::new(Box::new(client), 0),
+        };
+        match listener.next().await.unwrap() {
+            UsbmuxdListenEvent::Attached(dev) => {
+                assert_eq!(dev.device_id, 3);
+                assert_eq!(dev.udid, "00008030-001");
+            }
+            e => panic!("unexpected event {e:?}"),
+        }
+        assert!(matches!(
+            listener.next().await.unwrap(),
+            UsbmuxdListenEvent::Detached(3)
+        ));
+        muxer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn binary_plist_requests() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let muxer = tokio::spawn(async move {
+            let mut header = [0u8; 16];
+            server.read_exact(&mut header).await.unwrap();
+            let size = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize - 16;
+            let mut body = vec![0; size];
+            server.read_exact(&mut body).await.unwrap();
+            assert_eq!(
+                UsbmuxdPlistFormat::detect(&body),
+                UsbmuxdPlistFormat::Binary
+            );
+
+            let mut res = plist::Dictionary::new();
+            res.insert("BUID".into(), "1234".into());
+            let raw: Vec<u8> = raw_packet::RawPacket::new_with_format(
+                res,
+                UsbmuxdConnection::XML_PLIST_VERSION,
+                UsbmuxdConnection::PLIST_MESSAGE_TYPE,
+                0,
+                UsbmuxdPlistFormat::Binary,
+            )
+            .into();
+            server.write_all(&raw).await.unwrap();
+        });
+
+        let mut conn = UsbmuxdConnection::new(Box::new(client), 0);
+        assert!(conn.probe_binary_plist().await.unwrap());
+        assert_eq!(conn.plist_format(), UsbmuxdPlistFormat::Binary);
+        muxer.await.unwrap();
+    }
+}
@@ -1,8 +1,10 @@
 // Jackson Coxson
 
-use crate::util::plist_to_xml_bytes;
+use crate::util::{plist_to_binary_bytes, plist_to_xml_bytes};
 use log::warn;
 
+use super::UsbmuxdPlistFormat;
+
 #[derive(Debug)]
 pub struct RawPacket {
     pub size: u32,
@@ -10,11 +12,22 @@ pub struct RawPacket {
     pub message: u32,
     pub tag: u32,
     pub plist: plist::Dictionary,
+    pub format: UsbmuxdPlistFormat,
 }
 
 impl RawPacket {
     pub fn new(plist: plist::Dictionary, version: u32, message: u32, tag: u32) -> RawPacket {
-        let plist_bytes = plist_to_xml_bytes(&plist);
+        Self::new_with_format(plist, version, message, tag, UsbmuxdPlistFormat::Xml)
+    }
+
+    pub fn new_with_format(
+        plist: plist::Dictionary,
+        version: u32,
+        message: u32,
+        tag: u32,
+        format: UsbmuxdPlistFormat,
+    ) -> RawPacket {
+        let plist_bytes = plist_bytes(&plist, format);
         let size = plist_bytes.len() as u32 + 16;
         RawPacket {
             size,
@@ -22,10 +35,18 @@ impl RawPacket {
             message,
             tag,
             plist,
+            format,
         }
     }
 }
 
+fn plist_bytes(plist: &plist::Dictionary, format: UsbmuxdPlistFormat) -> Vec<u8> {
+    match format {
+        UsbmuxdPlistFormat::Xml => plist_to_xml_bytes(plist),
+        UsbmuxdPlistFormat::Binary => plist_to_binary_bytes(plist),
+    }
+}
+
 impl From<RawPacket> for Vec<u8> {
     fn from(raw_packet: RawPacket) -> Vec<u8> {
         let mut packet = vec![];
@@ -33,7 +54,7 @@ impl From<RawPacket> for Vec<u8> {
         packet.extend_from_slice(&raw_packet.version.to_le_bytes());
         packet.extend_from_slice(&raw_packet.message.to_le_bytes());
         packet.extend_from_slice(&raw_packet.tag.to_le_bytes());
-        packet.extend_from_slice(&plist_to_xml_bytes(&raw_packet.plist));
+        packet.extend_from_slice(&plist_bytes(&raw_packet.plist, raw_packet.format));
         packet
     }
 }
@@ -97,7 +118,13 @@ impl TryFrom<&[u8]> for RawPacket {
             }
         });
 
+        if packet_size < 16 {
+            warn!("Packet size is smaller than the header");
+            return Err(());
+        }
+
         let plist = &packet[16..packet_size as usize];
+        let format = UsbmuxdPlistFormat::detect(plist);
         let plist = if let Ok(p) = plist::from_bytes(plist) {
             p
         } else {
@@ -111,6 +138,7 @@ impl TryFrom<&[u8]> for RawPacket {
             message,
             tag: packet_tag,
             plist,
+            format,
         })
     }
 }
@@ -0,0 +1,646 @@
+//! In-process usbmuxd server
+//!
+//! Speaks the usbmuxd protocol to clients and forwards their connections to any
+//! [`IdeviceProvider`]. This lets devices reachable over the network or a tunnel be
+//! exposed to unmodified tools that only know how to talk to usbmuxd.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use log::{debug, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::broadcast,
+};
+
+use super::{
+    raw_packet::RawPacket, Connection, UsbmuxdAddr, UsbmuxdConnection, UsbmuxdDevice,
+    UsbmuxdListenEvent, UsbmuxdPlistFormat,
+};
+use crate::{provider::IdeviceProvider, IdeviceError, ReadWrite};
+
+const RESULT_OK: u32 = 0;
+const RESULT_BAD_COMMAND: u32 = 1;
+const RESULT_BAD_DEVICE: u32 = 2;
+const RESULT_CONNECTION_REFUSED: u32 = 3;
+
+/// The largest packet a client may send, well above any pair record
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// A device served by a [`UsbmuxdServer`]
+struct ServedDevice {
+    device: UsbmuxdDevice,
+    provider: Arc<dyn IdeviceProvider>,
+}
+
+struct ServerState {
+    devices: Vec<ServedDevice>,
+    pair_records: HashMap<String, Vec<u8>>,
+    next_device_id: u32,
+}
+
+/// A usbmuxd server backed by [`IdeviceProvider`]s
+///
+/// Cloning the server is cheap, and all clones share the same device list.
+#[derive(Clone)]
+pub struct UsbmuxdServer {
+    state: Arc<Mutex<ServerState>>,
+    events: broadcast::Sender<UsbmuxdListenEvent>,
+    buid: String,
+}
+
+impl UsbmuxdServer {
+    /// Creates a new server with no devices
+    ///
+    /// # Arguments
+    /// * `buid` - The BUID reported to clients that send `ReadBUID`
+    pub fn new(buid: impl Into<String>) -> Self {
+        let (events, _) = broadcast::channel(32);
+        Self {
+            state: Arc::new(Mutex::new(ServerState {
+                devices: Vec::new(),
+                pair_records: HashMap::new(),
+                next_device_id: 1,
+            })),
+            events,
+            buid: buid.into(),
+        }
+    }
+
+    /// Adds a device to the server and notifies listening clients
+    ///
+    /// # Arguments
+    /// * `udid` - The device UDID reported to clients
+    /// * `connection_type` - How the device is reported to be connected
+    /// * `provider` - The provider used for `Connect` and `ReadPairRecord` requests
+    ///
+    /// # Returns
+    /// The device ID assigned to the device
+    pub fn add_device(
+        &self,
+        udid: impl Into<String>,
+        connection_type: Connection,
+        provider: Box<dyn IdeviceProvider>,
+    ) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let device_id = state.next_device_id;
+        state.next_device_id += 1;
+
+        let device = UsbmuxdDevice {
+            connection_type,
+            udid: udid.into(),
+            device_id,
+        };
+        debug!("Serving device {device:?}");
+        state.devices.push(ServedDevice {
+            device: device.clone(),
+            provider: Arc::from(provider),
+        });
+
+        // An error only means nobody is listening
+        let _ = self.events.send(UsbmuxdListenEvent::Attached(device));
+        device_id
+    }
+
+    /// Removes a device from the server and notifies listening clients
+    ///
+    /// # Returns
+    /// `true` if the device was being served
+    pub fn remove_device(&self, device_id: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.devices.len();
+        state.devices.retain(|d| d.device.device_id != device_id);
+        if state.devices.len() == len {
+            return false;
+        }
+
+        let _ = self.events.send(UsbmuxdListenEvent::Detached(device_id));
+        true
+    }
+
+    /// Returns the devices currently being served
+    pub fn devices(&self) -> Vec<UsbmuxdDevice> {
+        let state = self.state.lock().unwrap();
+        state.devices.iter().map(|d| d.device.clone()).collect()
+    }
+
+    /// Binds to the address and serves clients until an accept fails
+    ///
+    /// Each client is handled on its own task.
+    ///
+    /// # Arguments
+    /// * `addr` - The address to listen on. Unix socket paths must not already exist.
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if binding or accepting fails
+    pub async fn serve(&self, addr: &UsbmuxdAddr) -> Result<(), IdeviceError> {
+        match addr {
+            #[cfg(unix)]
+            UsbmuxdAddr::UnixSocket(path) => {
+                let listener = tokio::net::UnixListener::bind(path)?;
+                loop {
+                    let (socket, _) = listener.accept().await?;
+                    self.spawn_client(Box::new(socket));
+                }
+            }
+            UsbmuxdAddr::TcpSocket(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                loop {
+                    let (socket, _) = listener.accept().await?;
+                    self.spawn_client(Box::new(socket));
+                }
+            }
+        }
+    }
+
+    fn spawn_client(&self, socket: Box<dyn ReadWrite>) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.handle_client(socket).await {
+                debug!("usbmuxd client finished: {e:?}");
+            }
+        });
+    }
+
+    /// Handles a single client connection until it disconnects
+    ///
+    /// A client that sends `Connect` is proxied to the device, and a client that sends
+    /// `Listen` receives device events, for the rest of the connection's lifetime.
+    ///
+    /// # Errors
+    /// Returns `IdeviceError` if the client sends a malformed packet or the socket fails
+    pub async fn handle_client(&self, mut socket: Box<dyn ReadWrite>) -> Result<(), IdeviceError> {
+        loop {
+            let req = match read_packet(&mut socket).await? {
+                Some(r) => r,
+                None => return Ok(()),
+            };
+            let tag = req.tag;
+            let format = req.format;
+            let message_type = req
+                .plist
+                .get("MessageType")
+                .and_then(|x| x.as_string())
+                .unwrap_or_default()
+                .to_string();
+            debug!("usbmuxd client request: {message_type}");
+
+            match message_type.as_str() {
+                "ListDevices" => {
+                    let list = self
+                        .devices()
+                        .iter()
+                        .map(|d| plist::Value::Dictionary(attached_plist(d)))
+                        .collect::<Vec<_>>();
+                    let mut res = plist::Dictionary::new();
+                    res.insert("DeviceList".into(), list.into());
+                    write_packet(&mut socket, res, tag, format).await?;
+                }
+                "ReadBUID" => {
+                    let mut res = plist::Dictionary::new();
+                    res.insert("BUID".into(), self.buid.clone().into());
+                    write_packet(&mut socket, res, tag, format).await?;
+                }
+                "ReadPairRecord" => {
+                    let udid = req
+                        .plist
+                        .get("PairRecordID")
+                        .and_then(|x| x.as_string())
+                        .unwrap_or_default();
+                    match self.read_pair_record(udid).await {
+                        Some(data) => {
+                            let mut res = plist::Dictionary::new();
+                            res.insert("PairRecordData".into(), plist::Value::Data(data));
+                            write_packet(&mut socket, res, tag, format).await?;
+                        }
+                        None => write_result(&mut socket, RESULT_BAD_DEVICE, tag, format).await?,
+                    }
+                }
+                "SavePairRecord" => {
+                    let udid = req.plist.get("PairRecordID").and_then(|x| x.as_string());
+                    let data = req.plist.get("PairRecordData").and_then(|x| x.as_data());
+                    let code = match (udid, data) {
+                        (Some(udid), Some(data)) => {
+                            self.state
+                                .lock()
+                                .unwrap()
+                                .pair_records
+                                .insert(udid.to_string(), data.to_vec());
+                            if let Some(id) = req
+                                .plist
+                                .get("DeviceID")
+                                .and_then(|x| x.as_unsigned_integer())
+                            {
+                                let _ = self.events.send(UsbmuxdListenEvent::Paired(id as u32));
+                            }
+                            RESULT_OK
+                        }
+                        _ => RESULT_BAD_COMMAND,
+                    };
+                    write_result(&mut socket, code, tag, format).await?;
+                }
+                "DeletePairRecord" => {
+                    let udid = req
+                        .plist
+                        .get("PairRecordID")
+                        .and_then(|x| x.as_string())
+                        .unwrap_or_default();
+                    let removed = self.state.lock().unwrap().pair_records.remove(udid);
+                    let code = match removed {
+                        Some(_) => RESULT_OK,
+                        None => RESULT_BAD_DEVICE,
+                    };
+                    write_result(&mut socket, code, tag, format).await?;
+                }
+                "Listen" => {
+                    // Subscribe before snapshotting so no event falls in between
+                    let mut events = self.events.subscribe();
+                    write_result(&mut socket, RESULT_OK, tag, format).await?;
+                    for dev in self.devices() {
+                        write_packet(&mut socket, attached_plist(&dev), 0, format).await?;
+                    }
+                    return listen(socket, &mut events, format).await;
+                }
+                "Connect" => {
+                    return self.connect(socket, &req.plist, tag, format).await;
+                }
+                _ => {
+                    warn!("Unsupported usbmuxd request: {message_type}");
+                    write_result(&mut socket, RESULT_BAD_COMMAND, tag, format).await?;
+                }
+            }
+        }
+    }
+
+    async fn read_pair_record(&self, udid: &str) -> Option<Vec<u8>> {
+        let provider = {
+            let state = self.state.lock().unwrap();
+            if let Some(r) = state.pair_records.get(udid) {
+                return Some(r.clone());
+            }
+            state
+                .devices
+                .iter()
+                .find(|d| d.device.udid == udid)
+                .map(|d| d.provider.clone())?
+        };
+
+        match provider.get_pairing_file().await {
+            Ok(p) => p.serialize().ok(),
+            Err(e) => {
+                warn!("Failed to get pairing file for {udid}: {e:?}");
+                None
+            }
+        }
+    }
+
+    async fn connect(
+        &self,
+        mut socket: Box<dyn ReadWrite>,
+        req: &plist::Dictionary,
+        tag: u32,
+        format: UsbmuxdPlistFormat,
+    ) -> Result<(), IdeviceError> {
+        let device_id = req
+            .get("DeviceID")
+            .and_then(|x| x.as_unsigned_integer())
+            .map(|x| x as u32);
+        // Clients send the port in network byte order
+        let port = req
+            .get("PortNumber")
+            .and_then(|x| x.as_unsigned_integer())
+            .map(|x| u16::from_be(x as u16));
+
+        let (device_id, port) = match (device_id, port) {
+            (Some(d), Some(p)) => (d, p),
+            _ => return write_result(&mut socket, RESULT_BAD_COMMAND, tag, format).await,
+        };
+
+        let provider = {
+            let state = self.state.lock().unwrap();
+            state
+                .devices
+                .iter()
+                .find(|d| d.device.device_id == device_id)
+                .map(|d| d.provider.clone())
+        };
+        let provider = match provider {
+            Some(p) => p,
+            None => return write_result(&mut socket, RESULT_BAD_DEVICE, tag, format).await,
+        };
+
+        debug!("Connecting client to device {device_id} on port {port}");
+        let device_socket = match provider.connect(port).await.map(|d| d.get_socket()) {
+            Ok(Some(s)) => s,
+            Ok(None) => {
+                return write_result(&mut socket, RESULT_CONNECTION_REFUSED, tag, format).await;
+            }
+            Err(e) => {
+                warn!("Failed to connect to device {device_id} port {port}: {e:?}");
+                return write_result(&mut socket, RESULT_CONNECTION_REFUSED, tag, format).await;
+            }
+        };
+        write_result(&mut socket, RESULT_OK, tag, format).await?;
+
+        let mut device_socket = device_socket;
+        tokio::io::copy_bidirectional(&mut socket, &mut device_socket).await?;
+        Ok(())
+    }
+}
+
+async fn listen(
+    mut socket: Box<dyn ReadWrite>,
+    events: &mut broadcast::Receiver<UsbmuxdListenEvent>,
+    format: UsbmuxdPlistFormat,
+) -> Result<(), IdeviceError> {
+    loop {
+        let event = match events.recv().await {
+            Ok(e) => e,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("usbmuxd listener lagged behind by {n} events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+
+        let msg = match event {
+            UsbmuxdListenEvent::Attached(dev) => attached_plist(&dev),
+            UsbmuxdListenEvent::Detached(id) => id_event_plist("Detached", id),
+            UsbmuxdListenEvent::Paired(id) => id_event_plist("Paired", id),
+        };
+        write_packet(&mut socket, msg, 0, format).await?;
+    }
+}
+
+/// Reads a packet from a client, returning `None` on a clean disconnect
+async fn read_packet(socket: &mut Box<dyn ReadWrite>) -> Result<Option<RawPacket>, IdeviceError> {
+    let mut header = [0u8; 16];
+    match socket.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let size = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    if !(16..=MAX_PACKET_SIZE).contains(&size) {
+        return Err(IdeviceError::UnexpectedResponse);
+    }
+
+    let mut packet = header.to_vec();
+    packet.resize(size, 0);
+    socket.read_exact(&mut packet[16..]).await?;
+
+    match RawPacket::try_from(packet.as_slice()) {
+        Ok(p) => Ok(Some(p)),
+        Err(_) => Err(IdeviceError::UnexpectedResponse),
+    }
+}
+
+async fn write_packet(
+    socket: &mut Box<dyn ReadWrite>,
+    plist: plist::Dictionary,
+    tag: u32,
+    format: UsbmuxdPlistFormat,
+) -> Result<(), IdeviceError> {
+    let raw: Vec<u8> = RawPacket::new_with_format(
+        plist,
+        UsbmuxdConnection::XML_PLIST_VERSION,
+        UsbmuxdConnection::PLIST_MESSAGE_TYPE,
+        tag,
+        format,
+    )
+    .into();
+    socket.write_all(&raw).await?;
+    socket.flush().await?;
+    Ok(())
+}
+
+async fn write_result(
+    socket: &mut Box<dyn ReadWrite>,
+    code: u32,
+    tag: u32,
+    format: UsbmuxdPlistFormat,
+) -> Result<(), IdeviceError> {
+    let mut res = plist::Dictionary::new();
+    res.insert("MessageType".into(), "Result".into());
+    res.insert("Number".into(), code.into());
+    write_packet(socket, res, tag, format).await
+}
+
+fn id_event_plist(message_type: &str, device_id: u32) -> plist::Dictionary {
+    let mut res = plist::Dictionary::new();
+    res.insert("MessageType".into(), message_type.into());
+    res.insert("DeviceID".into(), device_id.into());
+    res
+}
+
+/// Builds the `Attached` message for a device, as used by both `ListDevices` and `Listen`
+fn attached_plist(dev: &UsbmuxdDevice) -> plist::Dictionary {
+    let mut props = plist::Dictionary::new();
+    props.insert("DeviceID".into(), dev.device_id.into());
+    props.insert("SerialNumber".into(), dev.udid.clone().into());
+    match &dev.connection_type {
+        Connection::Usb => {
+            props.insert("ConnectionType".into(), "USB".into());
+        }
+        Connection::Network(ip) => {
+            props.insert("ConnectionType".into(), "Network".into());
+            props.insert(
+                "NetworkAddress".into(),
+                plist::Value::Data(network_address(ip)),
+            );
+        }
+        Connection::Unknown(t) => {
+            props.insert("ConnectionType".into(), t.clone().into());
+        }
+    }
+
+    let mut res = id_event_plist("Attached", dev.device_id);
+    res.insert("Properties".into(), props.into());
+    res
+}
+
+/// Encodes an address as the sockaddr bytes usbmuxd sends for network devices
+fn network_address(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mut addr = vec![0x02, 0, 0, 0];
+            addr.extend_from_slice(&ip.octets());
+            addr.extend_from_slice(&[0; 8]);
+            addr
+        }
+        IpAddr::V6(ip) => {
+            let mut addr = vec![0x1E, 0, 0, 0, 0, 0, 0, 0];
+            addr.extend_from_slice(&ip.octets());
+            addr.extend_from_slice(&[0; 4]);
+            addr
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, net::Ipv4Addr, pin::Pin};
+
+    use super::*;
+    use crate::{pairing_file::PairingFile, Idevice};
+
+    /// A provider whose every port is an echo server
+    #[derive(Debug)]
+    struct EchoProvider;
+
+    impl IdeviceProvider for EchoProvider {
+        fn connect(
+            &self,
+            _port: u16,
+        ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+            Box::pin(async move {
+                let (client, mut server) = tokio::io::duplex(1024);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 64];
+                    while let Ok(n) = server.read(&mut buf).await {
+                        if n == 0 || server.write_all(&buf[..n]).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(Idevice::new(Box::new(client), "echo"))
+            })
+        }
+
+        fn label(&self) -> &str {
+            "echo"
+        }
+
+        fn get_pairing_file(
+            &self,
+        ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+            Box::pin(async move { Err(IdeviceError::NotFound) })
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serve_unix_socket() {
+        let path = std::env::temp_dir().join(format!("idevice-usbmuxd-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = UsbmuxdAddr::UnixSocket(path.to_string_lossy().to_string());
+
+        let server = UsbmuxdServer::new("TEST-BUID");
+        server.add_device("udid-usb", Connection::Usb, Box::new(EchoProvider));
+        server.add_device(
+            "udid-net",
+            Connection::Network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+            Box::new(EchoProvider),
+        );
+        let s = server.clone();
+        let a = addr.clone();
+        tokio::spawn(async move { s.serve(&a).await });
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while !path.exists() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "the server didn't start"
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let mut conn = addr.connect(1).await.unwrap();
+        assert_eq!(conn.get_buid().await.unwrap(), "TEST-BUID");
+        let devs = conn.get_devices().await.unwrap();
+        assert_eq!(devs.len(), 2);
+        assert_eq!(
+            devs[1].connection_type,
+            Connection::Network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+        );
+        assert!(conn.get_pair_record("udid-usb").await.is_err());
+
+        let mut listener = addr.connect(2).await.unwrap().listen().await.unwrap();
+        assert!(matches!(
+            listener.next().await.unwrap(),
+            UsbmuxdListenEvent::Attached(d) if d.udid == "udid-usb"
+        ));
+        assert!(matches!(
+            listener.next().await.unwrap(),
+            UsbmuxdListenEvent::Attached(d) if d.udid == "udid-net"
+        ));
+        server.remove_device(devs[1].device_id);
+        assert!(matches!(
+            listener.next().await.unwrap(),
+            UsbmuxdListenEvent::Detached(id) if id == devs[1].device_id
+        ));
+
+        let mut record = plist::Dictionary::new();
+        for field in [
+            "DeviceCertificate",
+            "HostCertificate",
+            "HostPrivateKey",
+            "RootCertificate",
+            "RootPrivateKey",
+        ] {
+            record.insert(field.into(), plist::Value::Data(vec![1, 2, 3]));
+        }
+        for field in ["SystemBUID", "HostID", "WiFiMACAddress"] {
+            record.insert(field.into(), "00:11:22:33:44:55".into());
+        }
+        let record = PairingFile::from_value(&record.into()).unwrap();
+        conn.save_pair_record(devs[0].device_id, "udid-usb", &record)
+            .await
+            .unwrap();
+        assert!(matches!(
+            listener.next().await.unwrap(),
+            UsbmuxdListenEvent::Paired(id) if id == devs[0].device_id
+        ));
+
+        let mut idevice = addr
+            .connect_negotiated(3)
+            .await
+            .unwrap()
+            .connect_to_device(devs[0].device_id, 62078, "test")
+            .await
+            .unwrap();
+        idevice.send_raw(b"marco").await.unwrap();
+        assert_eq!(idevice.read_raw(5).await.unwrap(), b"marco");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn oversized_packet() {
+        let server = UsbmuxdServer::new("TEST-BUID");
+        let (mut client, socket) = tokio::io::duplex(64);
+        let mut header = [0u8; 16];
+        header[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        client.write_all(&header).await.unwrap();
+
+        assert!(matches!(
+            server.handle_client(Box::new(socket)).await,
+            Err(IdeviceError::UnexpectedResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn pair_records() {
+        let server = UsbmuxdServer::new("TEST-BUID");
+        let (client, socket) = tokio::io::duplex(4096);
+        let s = server.clone();
+        tokio::spawn(async move { s.handle_client(Box::new(socket)).await });
+        let mut conn = UsbmuxdConnection::new(Box::new(client), 1);
+
+        let record = plist::Value::Dictionary(crate::pairing_file::test_record());
+        let record = PairingFile::from_value(&record).unwrap();
+        conn.save_pair_record(1, "udid-usb", &record).await.unwrap();
+        let saved = conn.get_pair_record("udid-usb").await.unwrap();
+        assert_eq!(
+            saved.serialize().unwrap(),
+            record.clone().serialize().unwrap()
+        );
+
+        conn.delete_pair_record("udid-usb").await.unwrap();
+        assert!(conn.get_pair_record("udid-usb").await.is_err());
+        assert!(conn.delete_pair_record("udid-usb").await.is_err());
+    }
+}
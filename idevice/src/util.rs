@@ -31,6 +31,24 @@ pub fn plist_to_xml_bytes(p: &plist::Dictionary) -> Vec<u8> {
     writer.into_inner().unwrap()
 }
 
+/// Converts a PLIST dictionary to binary-formatted bytes
+///
+/// # Arguments
+/// * `p` - The PLIST dictionary to serialize
+///
+/// # Returns
+/// A byte vector containing the binary representation
+///
+/// # Panics
+/// Will panic if serialization fails (should only happen with invalid data)
+pub fn plist_to_binary_bytes(p: &plist::Dictionary) -> Vec<u8> {
+    let buf = Vec::new();
+    let mut writer = std::io::BufWriter::new(buf);
+    plist::to_writer_binary(&mut writer, &p).unwrap();
+
+    writer.into_inner().unwrap()
+}
+
 /// Pretty-prints a PLIST value with indentation
 ///
 /// # Arguments
@@ -8,7 +8,10 @@ use indexmap::IndexMap;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::IdeviceError;
+use crate::{codec::Decoder, IdeviceError};
+
+/// How deeply dictionaries and arrays may be nested before decoding gives up
+const MAX_DEPTH: usize = 64;
 
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
@@ -260,10 +263,14 @@ impl XPCObject {
             return Err(IdeviceError::UnexpectedXpcVersion);
         }
 
-        Self::decode_object(&mut Cursor::new(&buf[8..]))
+        Self::decode_object(&mut Cursor::new(&buf[8..]), 0)
     }
 
-    fn decode_object(mut cursor: &mut Cursor<&[u8]>) -> Result<Self, IdeviceError> {
+    fn decode_object(mut cursor: &mut Cursor<&[u8]>, depth: usize) -> Result<Self, IdeviceError> {
+        if depth > MAX_DEPTH {
+            warn!("XPCObject is nested too deeply");
+            return Err(IdeviceError::MalformedXpc);
+        }
         let mut buf_32: [u8; 4] = Default::default();
         cursor.read_exact(&mut buf_32)?;
         let xpc_type = u32::from_le_bytes(buf_32);
@@ -291,7 +298,7 @@ impl XPCObject {
                     let padding = Self::calculate_padding(key.len() + 1);
 
                     BufRead::consume(&mut cursor, padding);
-                    ret.insert(key, Self::decode_object(cursor)?);
+                    ret.insert(key, Self::decode_object(cursor, depth + 1)?);
                 }
                 Ok(XPCObject::Dictionary(ret))
             }
@@ -303,7 +310,7 @@ impl XPCObject {
 
                 let mut ret = Vec::new();
                 for _i in 0..num_entries {
-                    ret.push(Self::decode_object(cursor)?);
+                    ret.push(Self::decode_object(cursor, depth + 1)?);
                 }
                 Ok(XPCObject::Array(ret))
             }
@@ -338,6 +345,7 @@ impl XPCObject {
                 let l = u32::from_le_bytes(buf_32) as usize;
                 let padding = Self::calculate_padding(l);
 
+                Self::check_remaining(cursor, l)?;
                 let mut key_buf = vec![0; l];
                 cursor.read_exact(&mut key_buf)?;
                 let key = match CString::from_vec_with_nul(key_buf)
@@ -360,6 +368,7 @@ impl XPCObject {
                 let l = u32::from_le_bytes(buf_32) as usize;
                 let padding = Self::calculate_padding(l);
 
+                Self::check_remaining(cursor, l)?;
                 let mut data = vec![0; l];
                 cursor.read_exact(&mut data)?;
                 BufRead::consume(&mut cursor, padding);
@@ -417,6 +426,18 @@ impl XPCObject {
         }
     }
 
+    /// Makes sure a length read from the device fits in what's left, before allocating it
+    fn check_remaining(cursor: &Cursor<&[u8]>, len: usize) -> Result<(), IdeviceError> {
+        let remaining = cursor
+            .get_ref()
+            .len()
+            .saturating_sub(cursor.position() as usize);
+        if len > remaining {
+            return Err(IdeviceError::NotEnoughBytes(remaining, len));
+        }
+        Ok(())
+    }
+
     fn calculate_padding(len: usize) -> usize {
         let c = ((len as f64) / 4.0).ceil();
         (c * 4.0 - (len as f64)) as usize
@@ -467,7 +488,7 @@ impl XPCMessage {
         let message_id = u64::from_le_bytes([
             data[16], data[17], data[18], data[19], data[20], data[21], data[22], data[23],
         ]);
-        if body_len + 24 > data.len() as u64 {
+        if body_len > data.len() as u64 - 24 {
             warn!(
                 "Body length is {body_len}, but received bytes is {}",
                 data.len()
@@ -541,3 +562,93 @@ impl std::fmt::Debug for XPCMessage {
         )
     }
 }
+
+/// Sans-IO decoder for XPC messages
+///
+/// Push the payloads of a stream's DATA frames in; messages may span several frames.
+#[derive(Debug, Default)]
+pub struct XPCMessageDecoder {
+    buf: Vec<u8>,
+}
+
+impl XPCMessageDecoder {
+    /// Length of the message header
+    const HEADER_LEN: usize = 24;
+
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the message at the start of the buffer, once its header is in
+    fn message_len(&self) -> usize {
+        match self.buf.get(8..16) {
+            Some(l) => {
+                let body_len = u64::from_le_bytes(l.try_into().unwrap());
+                Self::HEADER_LEN.saturating_add(body_len.try_into().unwrap_or(usize::MAX))
+            }
+            None => Self::HEADER_LEN,
+        }
+    }
+}
+
+impl Decoder for XPCMessageDecoder {
+    type Item = XPCMessage;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError> {
+        let len = self.message_len();
+        if self.buf.len() < Self::HEADER_LEN || self.buf.len() < len {
+            return Ok(None);
+        }
+        let message: Vec<u8> = self.buf.drain(..len).collect();
+        XPCMessage::decode(&message).map(Some)
+    }
+
+    fn bytes_needed(&self) -> usize {
+        self.message_len().saturating_sub(self.buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut buf = 0x42133742_u32.to_le_bytes().to_vec();
+        buf.extend_from_slice(&5_u32.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn rejects_oversized_string() {
+        let mut buf = header();
+        buf.extend_from_slice(&0x00009000_u32.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(b"abc\0");
+        assert!(XPCObject::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let mut buf = header();
+        for _ in 0..10_000 {
+            buf.extend_from_slice(&0x0000e000_u32.to_le_bytes());
+            buf.extend_from_slice(&0_u32.to_le_bytes());
+            buf.extend_from_slice(&1_u32.to_le_bytes());
+        }
+        assert!(XPCObject::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_body() {
+        let mut buf = 0x29b00b92_u32.to_le_bytes().to_vec();
+        buf.extend_from_slice(&1_u32.to_le_bytes());
+        buf.extend_from_slice(&u64::MAX.to_le_bytes());
+        buf.extend_from_slice(&0_u64.to_le_bytes());
+        assert!(XPCMessage::decode(&buf).is_err());
+    }
+}
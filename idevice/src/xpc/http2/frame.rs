@@ -1,8 +1,11 @@
 // Jackson Coxson
 
-use crate::{IdeviceError, ReadWrite};
-use tokio::io::AsyncReadExt;
+#[cfg(feature = "tokio")]
+use crate::{codec, ReadWrite};
+use crate::{codec::Decoder, IdeviceError};
 
+// Only the HTTP/2 client sends frames, and it needs tokio
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
 pub trait HttpFrame {
     fn serialize(&self) -> Vec<u8>;
 }
@@ -17,20 +20,18 @@ pub enum Frame {
 }
 
 impl Frame {
-    pub async fn next(mut socket: &mut impl ReadWrite) -> Result<Self, IdeviceError> {
-        // Read the len of the frame
-        let mut buf = [0u8; 3];
-        tokio::io::AsyncReadExt::read_exact(&mut socket, &mut buf).await?;
-        let frame_len = u32::from_be_bytes([0x00, buf[0], buf[1], buf[2]]);
-
-        // Read the fields
-        let frame_type = socket.read_u8().await?;
-        let flags = socket.read_u8().await?;
-        let stream_id = socket.read_u32().await?;
-
-        let mut body = vec![0; frame_len as usize];
-        socket.read_exact(&mut body).await?;
+    #[cfg(feature = "tokio")]
+    pub async fn next(socket: &mut impl ReadWrite) -> Result<Self, IdeviceError> {
+        codec::read_from(&mut FrameDecoder::new(), socket).await
+    }
 
+    /// Parses a frame from its header fields and body
+    fn parse(
+        frame_type: u8,
+        flags: u8,
+        stream_id: u32,
+        body: Vec<u8>,
+    ) -> Result<Self, IdeviceError> {
         Ok(match frame_type {
             0x00 => {
                 // data
@@ -46,19 +47,17 @@ impl Frame {
             0x03 => return Err(IdeviceError::HttpStreamReset),
             0x04 => {
                 // settings
-                let mut body = std::io::Cursor::new(body);
                 let mut settings = Vec::new();
-
-                while let Ok(setting_type) = body.read_u16().await {
+                for setting in body.chunks(6) {
+                    if setting.len() != 6 {
+                        return Err(IdeviceError::UnexpectedResponse);
+                    }
+                    let setting_type = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value =
+                        u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
                     settings.push(match setting_type {
-                        0x03 => {
-                            let max_streams = body.read_u32().await?;
-                            Setting::MaxConcurrentStreams(max_streams)
-                        }
-                        0x04 => {
-                            let window_size = body.read_u32().await?;
-                            Setting::InitialWindowSize(window_size)
-                        }
+                        0x03 => Setting::MaxConcurrentStreams(value),
+                        0x04 => Setting::InitialWindowSize(value),
                         _ => {
                             return Err(IdeviceError::UnknownHttpSetting(setting_type));
                         }
@@ -97,6 +96,53 @@ impl Frame {
     }
 }
 
+/// Sans-IO decoder for the HTTP/2 frames RemoteXPC is carried in
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Length of the frame header
+    const HEADER_LEN: usize = 9;
+
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the frame at the start of the buffer, once its header is in
+    fn frame_len(&self) -> usize {
+        match self.buf.get(..3) {
+            Some(l) => Self::HEADER_LEN + u32::from_be_bytes([0, l[0], l[1], l[2]]) as usize,
+            None => Self::HEADER_LEN,
+        }
+    }
+}
+
+impl Decoder for FrameDecoder {
+    type Item = Frame;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn decode(&mut self) -> Result<Option<Self::Item>, IdeviceError> {
+        let len = self.frame_len();
+        if self.buf.len() < Self::HEADER_LEN || self.buf.len() < len {
+            return Ok(None);
+        }
+        let mut frame: Vec<u8> = self.buf.drain(..len).collect();
+        let body = frame.split_off(Self::HEADER_LEN);
+        let stream_id = u32::from_be_bytes([frame[5], frame[6], frame[7], frame[8]]);
+        Frame::parse(frame[3], frame[4], stream_id, body).map(Some)
+    }
+
+    fn bytes_needed(&self) -> usize {
+        self.frame_len().saturating_sub(self.buf.len())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SettingsFrame {
     pub settings: Vec<Setting>,
@@ -110,6 +156,7 @@ pub enum Setting {
     InitialWindowSize(u32),
 }
 
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
 impl Setting {
     fn serialize(&self) -> Vec<u8> {
         match self {
@@ -194,3 +241,39 @@ impl HttpFrame for DataFrame {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_frames() {
+        let mut bytes = SettingsFrame {
+            settings: vec![
+                Setting::MaxConcurrentStreams(100),
+                Setting::InitialWindowSize(1048576),
+            ],
+            stream_id: 0,
+            flags: 0,
+        }
+        .serialize();
+        bytes.extend(
+            DataFrame {
+                stream_id: 3,
+                payload: b"xpc".to_vec(),
+            }
+            .serialize(),
+        );
+
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        for b in bytes {
+            decoder.push(&[b]);
+            if let Some(f) = decoder.decode().unwrap() {
+                frames.push(f);
+            }
+        }
+        assert!(matches!(&frames[0], Frame::Settings(s) if s.settings.len() == 2));
+        assert!(matches!(&frames[1], Frame::Data(d) if d.stream_id == 3 && d.payload == b"xpc"));
+    }
+}
@@ -1,22 +1,32 @@
 // Jackson Coxson
 
+// Without tokio only the frame codec is built
+#[cfg(feature = "tokio")]
 use frame::HttpFrame;
+#[cfg(feature = "tokio")]
 use log::{debug, warn};
+#[cfg(feature = "tokio")]
 use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "tokio")]
 use tokio::io::AsyncWriteExt;
 
+#[cfg(feature = "tokio")]
 use crate::{IdeviceError, ReadWrite};
 
 pub mod frame;
+#[cfg(feature = "tokio")]
 pub use frame::Setting;
 
+#[cfg(feature = "tokio")]
 const HTTP2_MAGIC: &[u8] = "PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".as_bytes();
 
+#[cfg(feature = "tokio")]
 pub struct Http2Client<R: ReadWrite> {
     inner: R,
     cache: HashMap<u32, VecDeque<Vec<u8>>>,
 }
 
+#[cfg(feature = "tokio")]
 impl<'a, R: ReadWrite + 'a> Http2Client<R> {
     /// Writes the magic and inits the caches
     pub async fn new(mut inner: R) -> Result<Self, IdeviceError> {
@@ -1,25 +1,35 @@
 // Jackson Coxson
 
+// Without tokio only the message and frame codecs are built
+#[cfg(feature = "tokio")]
 use http2::Setting;
+#[cfg(feature = "tokio")]
 use log::debug;
 
-use crate::{IdeviceError, ReadWrite};
+#[cfg(feature = "tokio")]
+use crate::{codec::Decoder, IdeviceError, ReadWrite};
 
 mod format;
 mod http2;
 
+#[cfg(feature = "tokio")]
 use format::XPCFlag;
-pub use format::{Dictionary, XPCMessage, XPCObject};
+pub use format::{Dictionary, XPCMessage, XPCMessageDecoder, XPCObject};
+pub use http2::frame::{Frame, FrameDecoder};
 
+#[cfg(feature = "tokio")]
 const ROOT_CHANNEL: u32 = 1;
+#[cfg(feature = "tokio")]
 const REPLY_CHANNEL: u32 = 3;
 
+#[cfg(feature = "tokio")]
 pub struct RemoteXpcClient<R: ReadWrite> {
     h2_client: http2::Http2Client<R>,
     root_id: u64,
     reply_id: u64,
 }
 
+#[cfg(feature = "tokio")]
 impl<'a, R: ReadWrite + 'a> RemoteXpcClient<R> {
     pub async fn new(socket: R) -> Result<Self, IdeviceError> {
         Ok(Self {
@@ -75,29 +85,23 @@ impl<'a, R: ReadWrite + 'a> RemoteXpcClient<R> {
     }
 
     async fn recv_from_channel(&mut self, channel: u32) -> Result<plist::Value, IdeviceError> {
-        let mut msg_buffer = Vec::new();
+        let mut decoder = XPCMessageDecoder::new();
         loop {
-            msg_buffer.extend(self.h2_client.read(channel).await?);
-            let msg = match XPCMessage::decode(&msg_buffer) {
-                Ok(m) => m,
-                Err(IdeviceError::PacketSizeMismatch) => continue,
-                Err(e) => break Err(e),
-            };
-
-            match msg.message {
-                Some(msg) => {
-                    if let Some(d) = msg.as_dictionary() {
-                        if d.is_empty() {
-                            msg_buffer.clear();
+            decoder.push(&self.h2_client.read(channel).await?);
+            while let Some(msg) = decoder.decode()? {
+                match msg.message {
+                    Some(msg) => {
+                        if let Some(d) = msg.as_dictionary()
+                            && d.is_empty()
+                        {
                             continue;
                         }
+                        return Ok(msg.to_plist());
+                    }
+                    None => {
+                        // don't care didn't ask
+                        continue;
                     }
-                    break Ok(msg.to_plist());
-                }
-                None => {
-                    // don't care didn't ask
-                    msg_buffer.clear();
-                    continue;
                 }
             }
         }
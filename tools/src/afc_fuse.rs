@@ -0,0 +1,1040 @@
+// Jackson Coxson
+// Mounts AFC as a FUSE filesystem on Linux
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("afc_fuse is only supported on Linux");
+}
+
+#[cfg(target_os = "linux")]
+mod common;
+
+#[cfg(target_os = "linux")]
+#[tokio::main]
+async fn main() {
+    linux::main().await
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        collections::HashMap,
+        ffi::OsStr,
+        io::SeekFrom,
+        path::{Path, PathBuf},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use clap::{value_parser, Arg, Command};
+    use fuser::{
+        FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData,
+        ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request,
+        TimeOrNow, FUSE_ROOT_ID,
+    };
+    use idevice::{
+        afc::{
+            errors::AfcError,
+            opcode::{AfcFopenMode, LinkType},
+            AfcClient, FileInfo,
+        },
+        house_arrest::HouseArrestClient,
+        IdeviceError, IdeviceService,
+    };
+    use tokio::io::AsyncReadExt;
+
+    use super::common;
+
+    pub async fn main() {
+        env_logger::init();
+
+        let matches = Command::new("afc_fuse")
+            .about("Mount the device's files with FUSE")
+            .arg(
+                Arg::new("host")
+                    .long("host")
+                    .value_name("HOST")
+                    .help("IP address of the device"),
+            )
+            .arg(
+                Arg::new("pairing_file")
+                    .long("pairing-file")
+                    .value_name("PATH")
+                    .help("Path to the pairing file"),
+            )
+            .arg(
+                Arg::new("udid")
+                    .long("udid")
+                    .value_name("UDID")
+                    .help("UDID of the device (overrides host/pairing file)"),
+            )
+            .arg(
+                Arg::new("documents")
+                    .long("documents")
+                    .value_name("BUNDLE_ID")
+                    .help("Mount the documents of a bundle"),
+            )
+            .arg(
+                Arg::new("container")
+                    .long("container")
+                    .value_name("BUNDLE_ID")
+                    .help("Mount the container of a bundle"),
+            )
+            .arg(
+                Arg::new("ttl")
+                    .long("ttl")
+                    .value_name("SECONDS")
+                    .help("How long attributes are cached for")
+                    .default_value("1")
+                    .value_parser(value_parser!(f64)),
+            )
+            .arg(
+                Arg::new("allow_other")
+                    .long("allow-other")
+                    .help("Let other users access the mount")
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("mountpoint")
+                    .required_unless_present("about")
+                    .index(1)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                Arg::new("about")
+                    .long("about")
+                    .help("Show about information")
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .get_matches();
+
+        if matches.get_flag("about") {
+            println!("afc_fuse - mount the device's files with FUSE");
+            println!("Copyright (c) 2025 Jackson Coxson");
+            return;
+        }
+
+        let mountpoint = matches
+            .get_one::<PathBuf>("mountpoint")
+            .expect("No mountpoint passed");
+        let ttl = Duration::from_secs_f64(*matches.get_one::<f64>("ttl").unwrap());
+        let udid = matches.get_one::<String>("udid");
+        let host = matches.get_one::<String>("host");
+        let pairing_file = matches.get_one::<String>("pairing_file");
+
+        let provider =
+            match common::get_provider(udid, host, pairing_file, "afc_fuse-jkcoxson").await {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("{e}");
+                    return;
+                }
+            };
+
+        let client = if let Some(bundle_id) = matches.get_one::<String>("container") {
+            let h = HouseArrestClient::connect(&*provider)
+                .await
+                .expect("Failed to connect to house arrest");
+            h.vend_container(bundle_id)
+                .await
+                .expect("Failed to vend container")
+        } else if let Some(bundle_id) = matches.get_one::<String>("documents") {
+            let h = HouseArrestClient::connect(&*provider)
+                .await
+                .expect("Failed to connect to house arrest");
+            h.vend_documents(bundle_id)
+                .await
+                .expect("Failed to vend documents")
+        } else {
+            AfcClient::connect(&*provider)
+                .await
+                .expect("Unable to connect to AFC")
+        };
+
+        let mut options = vec![MountOption::FSName("afc".to_string())];
+        if matches.get_flag("allow_other") {
+            options.push(MountOption::AllowOther);
+        }
+        let fs = AfcFs::new(client, ttl, tokio::runtime::Handle::current());
+        let session = fuser::spawn_mount2(fs, mountpoint, &options).expect("Failed to mount");
+        println!(
+            "Mounted on {}, press Ctrl-C to unmount",
+            mountpoint.display()
+        );
+
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+        // Unmounting waits for the filesystem to close its files on the runtime
+        tokio::task::spawn_blocking(move || drop(session))
+            .await
+            .unwrap();
+    }
+
+    /// Attributes of a path as last fetched from the device
+    struct Cached {
+        attr: FileAttr,
+        link: Option<String>,
+        fetched: Instant,
+    }
+
+    /// A file opened through the mount
+    struct Handle {
+        fd: u64,
+        path: String,
+        /// The device's position in the file, if known
+        position: Option<u64>,
+    }
+
+    /// A directory listing taken when the directory was opened
+    struct DirEntry {
+        name: String,
+        inode: u64,
+        kind: FileType,
+    }
+
+    /// Maps FUSE requests onto AFC
+    ///
+    /// The kernel addresses files by inode number, AFC by path. Inodes are handed out per
+    /// path and never reused during the mount, so forgetting them needs no bookkeeping.
+    ///
+    /// fuser calls the filesystem from its own thread, which blocks on the runtime for
+    /// each request.
+    struct AfcFs {
+        client: AfcClient,
+        rt: tokio::runtime::Handle,
+        ttl: Duration,
+        paths: HashMap<u64, String>,
+        inodes: HashMap<String, u64>,
+        next_inode: u64,
+        attrs: HashMap<String, Cached>,
+        files: HashMap<u64, Handle>,
+        dirs: HashMap<u64, Vec<DirEntry>>,
+        next_handle: u64,
+        uid: u32,
+        gid: u32,
+    }
+
+    impl AfcFs {
+        fn new(client: AfcClient, ttl: Duration, rt: tokio::runtime::Handle) -> Self {
+            let mut paths = HashMap::new();
+            paths.insert(FUSE_ROOT_ID, "/".to_string());
+            let mut inodes = HashMap::new();
+            inodes.insert("/".to_string(), FUSE_ROOT_ID);
+            Self {
+                client,
+                rt,
+                ttl,
+                paths,
+                inodes,
+                next_inode: FUSE_ROOT_ID + 1,
+                attrs: HashMap::new(),
+                files: HashMap::new(),
+                dirs: HashMap::new(),
+                next_handle: 1,
+                // SAFETY: getuid and getgid can't fail
+                uid: unsafe { libc::getuid() },
+                gid: unsafe { libc::getgid() },
+            }
+        }
+
+        /// Closes the files the kernel didn't release before the unmount
+        async fn close_all(&mut self) {
+            for (_, handle) in self.files.drain() {
+                let file = self.client.file_from_raw(handle.fd, handle.path);
+                if let Err(e) = file.close().await {
+                    eprintln!("Failed to close file: {e:?}");
+                }
+            }
+        }
+
+        fn path(&self, inode: u64) -> Result<String, i32> {
+            self.paths.get(&inode).cloned().ok_or(libc::ENOENT)
+        }
+
+        fn child(&self, parent: u64, name: &OsStr) -> Result<String, i32> {
+            let parent = self.path(parent)?;
+            let name = name.to_str().ok_or(libc::EINVAL)?;
+            Ok(match parent.as_str() {
+                "/" => format!("/{name}"),
+                _ => format!("{parent}/{name}"),
+            })
+        }
+
+        fn inode(&mut self, path: &str) -> u64 {
+            if let Some(inode) = self.inodes.get(path) {
+                return *inode;
+            }
+            let inode = self.next_inode;
+            self.next_inode += 1;
+            self.inodes.insert(path.to_string(), inode);
+            self.paths.insert(inode, path.to_string());
+            inode
+        }
+
+        /// Returns the attributes of a path, from the cache if they're fresh enough
+        async fn attr(&mut self, path: &str) -> Result<(FileAttr, Option<String>), i32> {
+            if let Some(cached) = self.attrs.get(path) {
+                if cached.fetched.elapsed() < self.ttl {
+                    return Ok((cached.attr, cached.link.clone()));
+                }
+            }
+            let info = self.client.get_file_info(path).await.map_err(errno)?;
+            let attr = self.file_attr(path, &info);
+            self.attrs.insert(
+                path.to_string(),
+                Cached {
+                    attr,
+                    link: info.st_link_target.clone(),
+                    fetched: Instant::now(),
+                },
+            );
+            Ok((attr, info.st_link_target))
+        }
+
+        fn file_attr(&mut self, path: &str, info: &FileInfo) -> FileAttr {
+            let (kind, perm) = match info.st_ifmt.as_str() {
+                "S_IFDIR" => (FileType::Directory, 0o755),
+                "S_IFLNK" => (FileType::Symlink, 0o777),
+                _ => (FileType::RegularFile, 0o644),
+            };
+            let time = |t: &chrono::NaiveDateTime| {
+                let t = t.and_utc();
+                UNIX_EPOCH + Duration::new(t.timestamp().max(0) as u64, t.timestamp_subsec_nanos())
+            };
+            let mtime = time(&info.modified);
+            FileAttr {
+                ino: self.inode(path),
+                size: info.size as u64,
+                blocks: info.blocks as u64,
+                // AFC doesn't track access times
+                atime: mtime,
+                mtime,
+                ctime: time(&info.creation),
+                crtime: time(&info.creation),
+                kind,
+                perm,
+                nlink: info.st_nlink.parse().unwrap_or(1),
+                uid: self.uid,
+                gid: self.gid,
+                rdev: 0,
+                blksize: 4096,
+                flags: 0,
+            }
+        }
+
+        /// Drops the cached attributes of a path and its parent, whose size and time change
+        /// with it
+        fn invalidate(&mut self, path: &str) {
+            self.attrs.remove(path);
+            self.attrs.remove(parent(path));
+        }
+
+        async fn lookup_child(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+            let path = self.child(parent, name)?;
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn getattr_inode(&mut self, inode: u64) -> Result<FileAttr, i32> {
+            let path = self.path(inode)?;
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn set_attr(
+            &mut self,
+            inode: u64,
+            size: Option<u64>,
+            mtime: Option<TimeOrNow>,
+            fh: Option<u64>,
+        ) -> Result<FileAttr, i32> {
+            let path = self.path(inode)?;
+            if let Some(size) = size {
+                let res = match fh.and_then(|fh| self.files.get_mut(&fh)) {
+                    Some(handle) => {
+                        let mut file = self.client.file_from_raw(handle.fd, handle.path.clone());
+                        let res = file.set_len(size).await;
+                        if file.into_raw().await.is_err() {
+                            handle.position = None;
+                        }
+                        res
+                    }
+                    None => self.client.truncate(&path, size).await,
+                };
+                res.map_err(errno)?;
+            }
+            if let Some(mtime) = mtime {
+                let mtime = match mtime {
+                    TimeOrNow::SpecificTime(t) => t,
+                    TimeOrNow::Now => SystemTime::now(),
+                };
+                let since = mtime.duration_since(UNIX_EPOCH).map_err(|_| libc::EINVAL)?;
+                let mtime =
+                    chrono::DateTime::from_timestamp(since.as_secs() as i64, since.subsec_nanos())
+                        .ok_or(libc::EINVAL)?;
+                self.client
+                    .set_mtime(&path, mtime.naive_utc())
+                    .await
+                    .map_err(errno)?;
+            }
+            // AFC has no owners or permissions, so mode and owner changes are accepted and
+            // ignored like on FAT
+            self.invalidate(&path);
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn readlink_inode(&mut self, inode: u64) -> Result<String, i32> {
+            let path = self.path(inode)?;
+            match self.attr(&path).await? {
+                (_, Some(target)) => Ok(target),
+                _ => Err(libc::EINVAL),
+            }
+        }
+
+        async fn make_symlink(
+            &mut self,
+            parent: u64,
+            name: &OsStr,
+            target: &Path,
+        ) -> Result<FileAttr, i32> {
+            let path = self.child(parent, name)?;
+            let target = target.to_str().ok_or(libc::EINVAL)?;
+            self.client
+                .link(target, &path, LinkType::Symlink)
+                .await
+                .map_err(errno)?;
+            self.invalidate(&path);
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn make_link(
+            &mut self,
+            inode: u64,
+            parent: u64,
+            name: &OsStr,
+        ) -> Result<FileAttr, i32> {
+            let target = self.path(inode)?;
+            let path = self.child(parent, name)?;
+            self.client
+                .link(&target, &path, LinkType::Hardlink)
+                .await
+                .map_err(errno)?;
+            self.invalidate(&target);
+            self.invalidate(&path);
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn make_dir(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+            let path = self.child(parent, name)?;
+            self.client.mk_dir(&path).await.map_err(errno)?;
+            self.invalidate(&path);
+            Ok(self.attr(&path).await?.0)
+        }
+
+        async fn remove(&mut self, parent: u64, name: &OsStr) -> Result<(), i32> {
+            let path = self.child(parent, name)?;
+            self.client.remove(&path).await.map_err(errno)?;
+            self.invalidate(&path);
+            if let Some(inode) = self.inodes.remove(&path) {
+                self.paths.remove(&inode);
+            }
+            Ok(())
+        }
+
+        async fn rename_child(
+            &mut self,
+            parent: u64,
+            name: &OsStr,
+            new_parent: u64,
+            new_name: &OsStr,
+            flags: u32,
+        ) -> Result<(), i32> {
+            // RENAME_NOREPLACE and RENAME_EXCHANGE can't be done atomically over AFC
+            if flags != 0 {
+                return Err(libc::EINVAL);
+            }
+            let from = self.child(parent, name)?;
+            let to = self.child(new_parent, new_name)?;
+            self.client.rename(&from, &to).await.map_err(errno)?;
+
+            // Everything under both paths moved or was replaced
+            let within = |p: &str, dir: &str| p == dir || p.starts_with(&format!("{dir}/"));
+            self.attrs
+                .retain(|p, _| !within(p, &from) && !within(p, &to));
+            self.invalidate(&from);
+            self.invalidate(&to);
+            let replaced: Vec<String> = self
+                .inodes
+                .keys()
+                .filter(|p| within(p, &to))
+                .cloned()
+                .collect();
+            for path in replaced {
+                let inode = self.inodes.remove(&path).unwrap();
+                self.paths.remove(&inode);
+            }
+            let moved: Vec<String> = self
+                .inodes
+                .keys()
+                .filter(|p| within(p, &from))
+                .cloned()
+                .collect();
+            for old in moved {
+                let inode = self.inodes.remove(&old).unwrap();
+                let new = format!("{to}{}", &old[from.len()..]);
+                self.paths.insert(inode, new.clone());
+                self.inodes.insert(new, inode);
+            }
+            Ok(())
+        }
+
+        fn add_file(&mut self, fd: u64, path: String) -> u64 {
+            let fh = self.next_handle;
+            self.next_handle += 1;
+            self.files.insert(
+                fh,
+                Handle {
+                    fd,
+                    path,
+                    position: Some(0),
+                },
+            );
+            fh
+        }
+
+        async fn open_inode(&mut self, inode: u64, flags: i32) -> Result<u64, i32> {
+            let path = self.path(inode)?;
+            // O_TRUNC comes as a separate setattr, so writers open without truncating
+            let mode = match flags & libc::O_ACCMODE {
+                libc::O_RDONLY => AfcFopenMode::RdOnly,
+                _ => AfcFopenMode::Rw,
+            };
+            let file = self.client.open(&path, mode).await.map_err(errno)?;
+            let fd = file.into_raw().await.map_err(errno)?;
+            Ok(self.add_file(fd, path))
+        }
+
+        async fn create_child(
+            &mut self,
+            parent: u64,
+            name: &OsStr,
+        ) -> Result<(FileAttr, u64), i32> {
+            let path = self.child(parent, name)?;
+            let file = self
+                .client
+                .open(&path, AfcFopenMode::Rw)
+                .await
+                .map_err(errno)?;
+            let fd = file.into_raw().await.map_err(errno)?;
+            let fh = self.add_file(fd, path.clone());
+            self.invalidate(&path);
+            Ok((self.attr(&path).await?.0, fh))
+        }
+
+        async fn read_file(&mut self, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>, i32> {
+            let handle = self.files.get_mut(&fh).ok_or(libc::EBADF)?;
+            let mut file = self.client.file_from_raw(handle.fd, handle.path.clone());
+
+            let res = async {
+                if handle.position != Some(offset) {
+                    file.seek(SeekFrom::Start(offset)).await?;
+                }
+                let mut data = Vec::with_capacity(size as usize);
+                (&mut file).take(size as u64).read_to_end(&mut data).await?;
+                Ok::<_, IdeviceError>(data)
+            }
+            .await;
+            handle.position = match (&res, file.into_raw().await) {
+                (Ok(data), Ok(_)) => Some(offset + data.len() as u64),
+                _ => None,
+            };
+            res.map_err(errno)
+        }
+
+        async fn write_file(&mut self, fh: u64, offset: u64, data: &[u8]) -> Result<u32, i32> {
+            let handle = self.files.get_mut(&fh).ok_or(libc::EBADF)?;
+            let mut file = self.client.file_from_raw(handle.fd, handle.path.clone());
+
+            let res = async {
+                if handle.position != Some(offset) {
+                    file.seek(SeekFrom::Start(offset)).await?;
+                }
+                file.write(data).await
+            }
+            .await;
+            handle.position = match (&res, file.into_raw().await) {
+                (Ok(_), Ok(_)) => Some(offset + data.len() as u64),
+                _ => None,
+            };
+            let path = handle.path.clone();
+            self.invalidate(&path);
+            res.map_err(errno)?;
+            Ok(data.len() as u32)
+        }
+
+        async fn release_file(&mut self, fh: u64) -> Result<(), i32> {
+            let handle = self.files.remove(&fh).ok_or(libc::EBADF)?;
+            self.invalidate(&handle.path);
+            let file = self.client.file_from_raw(handle.fd, handle.path);
+            file.close().await.map_err(errno)
+        }
+
+        /// Lists a directory, with the type of everything in it since readdir has to give one
+        async fn open_dir(&mut self, inode: u64) -> Result<u64, i32> {
+            let path = self.path(inode)?;
+            let mut names = self.client.list_dir(&path).await.map_err(errno)?;
+            names.retain(|e| e != "." && e != "..");
+
+            let mut entries = vec![
+                DirEntry {
+                    name: ".".to_string(),
+                    inode,
+                    kind: FileType::Directory,
+                },
+                DirEntry {
+                    name: "..".to_string(),
+                    inode: self.inode(parent(&path)),
+                    kind: FileType::Directory,
+                },
+            ];
+            for name in names {
+                let child = self.child(inode, OsStr::new(&name))?;
+                let attr = match self.attr(&child).await {
+                    Ok((attr, _)) => attr,
+                    // Removed since it was listed
+                    Err(libc::ENOENT) => continue,
+                    Err(e) => return Err(e),
+                };
+                entries.push(DirEntry {
+                    name,
+                    inode: attr.ino,
+                    kind: attr.kind,
+                });
+            }
+            let fh = self.next_handle;
+            self.next_handle += 1;
+            self.dirs.insert(fh, entries);
+            Ok(fh)
+        }
+
+        async fn device_statfs(&mut self) -> Result<(u64, u64, u32), i32> {
+            let info = self.client.get_device_info().await.map_err(errno)?;
+            let block_size = info.block_size.max(1) as u64;
+            Ok((
+                info.total_bytes as u64 / block_size,
+                info.free_bytes as u64 / block_size,
+                block_size as u32,
+            ))
+        }
+    }
+
+    impl Filesystem for AfcFs {
+        fn destroy(&mut self) {
+            let rt = self.rt.clone();
+            rt.block_on(self.close_all());
+        }
+
+        fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.lookup_child(parent, name)) {
+                Ok(attr) => reply.entry(&self.ttl, &attr, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.getattr_inode(ino)) {
+                Ok(attr) => reply.attr(&self.ttl, &attr),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn setattr(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _mode: Option<u32>,
+            _uid: Option<u32>,
+            _gid: Option<u32>,
+            size: Option<u64>,
+            _atime: Option<TimeOrNow>,
+            mtime: Option<TimeOrNow>,
+            _ctime: Option<SystemTime>,
+            fh: Option<u64>,
+            _crtime: Option<SystemTime>,
+            _chgtime: Option<SystemTime>,
+            _bkuptime: Option<SystemTime>,
+            _flags: Option<u32>,
+            reply: ReplyAttr,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.set_attr(ino, size, mtime, fh)) {
+                Ok(attr) => reply.attr(&self.ttl, &attr),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.readlink_inode(ino)) {
+                Ok(target) => reply.data(target.as_bytes()),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn mkdir(
+            &mut self,
+            _req: &Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            _mode: u32,
+            _umask: u32,
+            reply: ReplyEntry,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.make_dir(parent, name)) {
+                Ok(attr) => reply.entry(&self.ttl, &attr, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.remove(parent, name)) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.remove(parent, name)) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn symlink(
+            &mut self,
+            _req: &Request<'_>,
+            parent: u64,
+            link_name: &OsStr,
+            target: &Path,
+            reply: ReplyEntry,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.make_symlink(parent, link_name, target)) {
+                Ok(attr) => reply.entry(&self.ttl, &attr, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn rename(
+            &mut self,
+            _req: &Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            newparent: u64,
+            newname: &OsStr,
+            flags: u32,
+            reply: ReplyEmpty,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.rename_child(parent, name, newparent, newname, flags)) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn link(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            newparent: u64,
+            newname: &OsStr,
+            reply: ReplyEntry,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.make_link(ino, newparent, newname)) {
+                Ok(attr) => reply.entry(&self.ttl, &attr, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.open_inode(ino, flags)) {
+                Ok(fh) => reply.opened(fh, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn read(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            offset: i64,
+            size: u32,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            reply: ReplyData,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.read_file(fh, offset as u64, size)) {
+                Ok(data) => reply.data(&data),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn write(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            offset: i64,
+            data: &[u8],
+            _write_flags: u32,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            reply: ReplyWrite,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.write_file(fh, offset as u64, data)) {
+                Ok(written) => reply.written(written),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn release(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            _flush: bool,
+            reply: ReplyEmpty,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.release_file(fh)) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.open_dir(ino)) {
+                Ok(fh) => reply.opened(fh, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn readdir(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            offset: i64,
+            mut reply: ReplyDirectory,
+        ) {
+            let Some(entries) = self.dirs.get(&fh) else {
+                return reply.error(libc::EBADF);
+            };
+            for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
+                // The offset given with an entry is where the next read starts
+                if reply.add(entry.inode, i as i64 + 1, entry.kind, &entry.name) {
+                    break;
+                }
+            }
+            reply.ok();
+        }
+
+        fn releasedir(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            _flags: i32,
+            reply: ReplyEmpty,
+        ) {
+            self.dirs.remove(&fh);
+            reply.ok();
+        }
+
+        fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.device_statfs()) {
+                Ok((blocks, free, block_size)) => {
+                    reply.statfs(blocks, free, free, 0, 0, block_size, 255, block_size)
+                }
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn create(
+            &mut self,
+            _req: &Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            _mode: u32,
+            _umask: u32,
+            _flags: i32,
+            reply: ReplyCreate,
+        ) {
+            let rt = self.rt.clone();
+            match rt.block_on(self.create_child(parent, name)) {
+                Ok((attr, fh)) => reply.created(&self.ttl, &attr, 0, fh, 0),
+                Err(e) => reply.error(e),
+            }
+        }
+    }
+
+    fn parent(path: &str) -> &str {
+        match path.rfind('/') {
+            Some(0) | None => "/",
+            Some(i) => &path[..i],
+        }
+    }
+
+    /// Converts an error from the device to the closest errno
+    fn errno(e: IdeviceError) -> i32 {
+        match e {
+            IdeviceError::Afc(e) => match e {
+                AfcError::ObjectNotFound => libc::ENOENT,
+                AfcError::ObjectExists => libc::EEXIST,
+                AfcError::ObjectIsDir => libc::EISDIR,
+                AfcError::PermDenied => libc::EACCES,
+                AfcError::DirNotEmpty => libc::ENOTEMPTY,
+                AfcError::InvalidArg => libc::EINVAL,
+                AfcError::NoSpaceLeft => libc::ENOSPC,
+                AfcError::ObjectBusy => libc::EBUSY,
+                AfcError::OpWouldBlock => libc::EAGAIN,
+                AfcError::OpTimeout => libc::ETIMEDOUT,
+                AfcError::OpNotSupported | AfcError::UnknownPacketType => libc::ENOTSUP,
+                _ => libc::EIO,
+            },
+            IdeviceError::InvalidArgument => libc::EINVAL,
+            _ => libc::EIO,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use idevice::mock::{AfcStub, MockDevice};
+
+        use super::*;
+
+        async fn mount(stub: &AfcStub) -> AfcFs {
+            let device = MockDevice::new().with_service("com.apple.afc", false, stub.clone());
+            let client = AfcClient::connect(&device).await.unwrap();
+            AfcFs::new(
+                client,
+                Duration::from_secs(1),
+                tokio::runtime::Handle::current(),
+            )
+        }
+
+        #[tokio::test]
+        async fn browse() {
+            let stub = AfcStub::new()
+                .with_dir("/DCIM")
+                .with_file("/Downloads/hello.txt", b"hello world");
+            let mut fs = mount(&stub).await;
+
+            let downloads = fs
+                .lookup_child(FUSE_ROOT_ID, OsStr::new("Downloads"))
+                .await
+                .unwrap();
+            assert_eq!(downloads.kind, FileType::Directory);
+            let hello = fs
+                .lookup_child(downloads.ino, OsStr::new("hello.txt"))
+                .await
+                .unwrap();
+            assert_eq!(hello.kind, FileType::RegularFile);
+            assert_eq!(hello.size, 11);
+            assert_eq!(fs.getattr_inode(hello.ino).await.unwrap().ino, hello.ino);
+            assert_eq!(
+                fs.lookup_child(FUSE_ROOT_ID, OsStr::new("missing")).await,
+                Err(libc::ENOENT)
+            );
+
+            let dir = fs.open_dir(FUSE_ROOT_ID).await.unwrap();
+            let names: Vec<&str> = fs.dirs[&dir].iter().map(|e| e.name.as_str()).collect();
+            assert_eq!(names[..2], [".", ".."]);
+            assert!(names.contains(&"DCIM") && names.contains(&"Downloads"));
+
+            // Reads past the first one continue from the device's position
+            let fh = fs.open_inode(hello.ino, libc::O_RDONLY).await.unwrap();
+            assert_eq!(fs.read_file(fh, 0, 5).await.unwrap(), b"hello");
+            assert_eq!(fs.read_file(fh, 5, 100).await.unwrap(), b" world");
+            assert_eq!(fs.read_file(fh, 6, 5).await.unwrap(), b"world");
+            fs.release_file(fh).await.unwrap();
+            assert_eq!(fs.read_file(fh, 0, 5).await, Err(libc::EBADF));
+        }
+
+        #[tokio::test]
+        async fn modify() {
+            let stub = AfcStub::new().with_dir("/Downloads");
+            let mut fs = mount(&stub).await;
+            let downloads = fs
+                .lookup_child(FUSE_ROOT_ID, OsStr::new("Downloads"))
+                .await
+                .unwrap()
+                .ino;
+
+            let (attr, fh) = fs
+                .create_child(downloads, OsStr::new("a.txt"))
+                .await
+                .unwrap();
+            assert_eq!(fs.write_file(fh, 0, b"hello").await.unwrap(), 5);
+            assert_eq!(fs.write_file(fh, 5, b"!").await.unwrap(), 1);
+            fs.release_file(fh).await.unwrap();
+            assert_eq!(stub.file("/Downloads/a.txt").unwrap(), b"hello!");
+            assert_eq!(fs.getattr_inode(attr.ino).await.unwrap().size, 6);
+
+            let attr = fs.set_attr(attr.ino, Some(4), None, None).await.unwrap();
+            assert_eq!(attr.size, 4);
+            assert_eq!(stub.file("/Downloads/a.txt").unwrap(), b"hell");
+
+            // A renamed file keeps its inode
+            fs.make_dir(FUSE_ROOT_ID, OsStr::new("Documents"))
+                .await
+                .unwrap();
+            let documents = fs
+                .lookup_child(FUSE_ROOT_ID, OsStr::new("Documents"))
+                .await
+                .unwrap()
+                .ino;
+            fs.rename_child(
+                downloads,
+                OsStr::new("a.txt"),
+                documents,
+                OsStr::new("b.txt"),
+                0,
+            )
+            .await
+            .unwrap();
+            assert!(!stub.contains("/Downloads/a.txt"));
+            let moved = fs
+                .lookup_child(documents, OsStr::new("b.txt"))
+                .await
+                .unwrap();
+            assert_eq!(moved.ino, attr.ino);
+
+            let link = fs
+                .make_symlink(FUSE_ROOT_ID, OsStr::new("b"), Path::new("Documents/b.txt"))
+                .await
+                .unwrap();
+            assert_eq!(link.kind, FileType::Symlink);
+            assert_eq!(
+                fs.readlink_inode(link.ino).await.unwrap(),
+                "Documents/b.txt"
+            );
+
+            fs.remove(documents, OsStr::new("b.txt")).await.unwrap();
+            assert!(!stub.contains("/Documents/b.txt"));
+            assert_eq!(fs.getattr_inode(moved.ino).await, Err(libc::ENOENT));
+        }
+    }
+}
@@ -65,12 +65,10 @@ async fn main() {
         }
     };
 
-    println!(
-        "{:?}",
-        lockdown_client
-            .get_value(Some("ProductVersion"), None)
-            .await
-    );
+    match lockdown_client.ios_version().await {
+        Ok(v) => println!("iOS {v}"),
+        Err(e) => eprintln!("Unable to get the iOS version: {e:?}"),
+    }
 
     println!(
         "{:?}",
@@ -84,5 +82,6 @@ async fn main() {
             .await
     );
     println!("{:?}", lockdown_client.idevice.get_type().await.unwrap());
-    println!("{:#?}", lockdown_client.get_value(None, None).await);
+    println!("{:#?}", lockdown_client.get_device_info().await);
+    println!("{:#?}", lockdown_client.get_all_values().await);
 }
@@ -49,6 +49,25 @@ async fn main() {
                 .arg(arg!(-v --value <STRING> "the value to set the key to").required(true))
                 .arg(arg!(-d --domain <STRING> "the domain to get in").required(false)),
         )
+        .subcommand(
+            Command::new("remove")
+                .about("Removes a lockdown value")
+                .arg(arg!(-k --key <STRING> "the key to remove").required(false))
+                .arg(arg!(-d --domain <STRING> "the domain to remove from").required(false)),
+        )
+        .subcommand(Command::new("battery").about("Shows the battery state"))
+        .subcommand(Command::new("disk").about("Shows the storage usage"))
+        .subcommand(Command::new("recovery").about("Reboots the device into recovery mode"))
+        .subcommand(
+            Command::new("wifi")
+                .about("Shows or changes whether the device accepts Wi-Fi connections")
+                .arg(
+                    Arg::new("state")
+                        .value_parser(["on", "off"])
+                        .help("Enable or disable Wi-Fi connections")
+                        .index(1),
+                ),
+        )
         .get_matches();
 
     if matches.get_flag("about") {
@@ -110,6 +129,42 @@ async fn main() {
             }
         }
 
+        Some(("remove", sub_m)) => {
+            let key = sub_m.get_one::<String>("key").map(|x| x.as_str());
+            let domain = sub_m.get_one::<String>("domain").map(|x| x.as_str());
+
+            match lockdown_client.remove_value(key, domain).await {
+                Ok(()) => println!("Successfully removed"),
+                Err(e) => eprintln!("Error removing value: {e}"),
+            }
+        }
+
+        Some(("battery", _)) => match lockdown_client.get_battery().await {
+            Ok(b) => println!("{b:#?}"),
+            Err(e) => eprintln!("Error getting battery state: {e}"),
+        },
+
+        Some(("disk", _)) => match lockdown_client.get_disk_usage().await {
+            Ok(d) => println!("{d:#?}"),
+            Err(e) => eprintln!("Error getting disk usage: {e}"),
+        },
+
+        Some(("recovery", _)) => match lockdown_client.enter_recovery().await {
+            Ok(()) => println!("Entering recovery mode"),
+            Err(e) => eprintln!("Error entering recovery: {e}"),
+        },
+
+        Some(("wifi", sub_m)) => match sub_m.get_one::<String>("state").map(|x| x.as_str()) {
+            Some(state) => match lockdown_client.set_wifi_connections(state == "on").await {
+                Ok(()) => println!("Successfully set"),
+                Err(e) => eprintln!("Error setting Wi-Fi connections: {e}"),
+            },
+            None => match lockdown_client.wifi_connections_enabled().await {
+                Ok(enabled) => println!("{enabled}"),
+                Err(e) => eprintln!("Error getting Wi-Fi connections: {e}"),
+            },
+        },
+
         _ => {
             eprintln!("No subcommand provided. Try `--help` for usage.");
         }
@@ -0,0 +1,67 @@
+// Jackson Coxson
+// Find paired devices on the local network
+
+use std::time::Duration;
+
+use clap::{Arg, Command};
+use idevice::{mdns, pairing_file::PairingFile};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let matches = Command::new("mdns_discover")
+        .about("Find paired devices on the local network")
+        .arg(
+            Arg::new("pairing_files")
+                .value_name("PATH")
+                .help("Paths to the pairing files of the devices to look for")
+                .required(true)
+                .num_args(1..),
+        )
+        .arg(
+            Arg::new("seconds")
+                .long("seconds")
+                .value_name("SECONDS")
+                .help("How long to browse for")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("about")
+                .long("about")
+                .help("Show about information")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    if matches.get_flag("about") {
+        println!("mdns_discover - find paired devices on the local network");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return;
+    }
+
+    let pairing_files = matches
+        .get_many::<String>("pairing_files")
+        .unwrap()
+        .map(|p| PairingFile::read_from_file(p).expect("Unable to read pairing file"))
+        .collect::<Vec<_>>();
+    let seconds = *matches.get_one::<u64>("seconds").unwrap();
+
+    let providers = mdns::discover(
+        pairing_files,
+        "mdns_discover-jkcoxson",
+        Duration::from_secs(seconds),
+    )
+    .await
+    .expect("Failed to browse for devices");
+
+    if providers.is_empty() {
+        eprintln!("No devices found");
+    }
+    for provider in providers {
+        println!(
+            "{} {}",
+            provider.pairing_file.wifi_mac_address, provider.addr
+        );
+    }
+}
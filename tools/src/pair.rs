@@ -2,7 +2,7 @@
 
 use clap::{Arg, Command};
 use idevice::{
-    lockdown::LockdownClient,
+    lockdown::{LockdownClient, PairingIdentity, PairingOptions},
     usbmuxd::{Connection, UsbmuxdAddr, UsbmuxdConnection},
     IdeviceService,
 };
@@ -19,6 +19,40 @@ async fn main() {
                 .help("UDID of the device (overrides host/pairing file)")
                 .index(1),
         )
+        .arg(
+            Arg::new("save")
+                .long("save")
+                .help("Save the pairing record to usbmuxd")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("root_cert")
+                .long("root-cert")
+                .value_name("PATH")
+                .requires("root_key")
+                .help("Root CA certificate to issue the pairing record from"),
+        )
+        .arg(
+            Arg::new("root_key")
+                .long("root-key")
+                .value_name("PATH")
+                .requires("root_cert")
+                .help("Private key of the root CA"),
+        )
+        .arg(
+            Arg::new("supervisor_cert")
+                .long("supervisor-cert")
+                .value_name("PATH")
+                .requires("supervisor_key")
+                .help("Supervisor certificate, for pairing supervised devices without a prompt"),
+        )
+        .arg(
+            Arg::new("supervisor_key")
+                .long("supervisor-key")
+                .value_name("PATH")
+                .requires("supervisor_cert")
+                .help("Private key of the supervisor certificate"),
+        )
         .arg(
             Arg::new("about")
                 .long("about")
@@ -62,8 +96,21 @@ async fn main() {
     };
     let id = uuid::Uuid::new_v4().to_string().to_uppercase();
 
+    let read_identity = |cert: &str, key: &str| {
+        matches.get_one::<String>(cert).map(|cert| PairingIdentity {
+            certificate: std::fs::read(cert).expect("Failed to read certificate"),
+            private_key: std::fs::read(matches.get_one::<String>(key).unwrap())
+                .expect("Failed to read private key"),
+        })
+    };
+    let options = PairingOptions {
+        root_ca: read_identity("root_cert", "root_key"),
+        supervisor: read_identity("supervisor_cert", "supervisor_key"),
+    };
+
+    let buid = u.get_buid().await.expect("Failed to get BUID");
     let mut pairing_file = lockdown_client
-        .pair(id, u.get_buid().await.unwrap())
+        .pair_with_options(id, buid, &options)
         .await
         .expect("Failed to pair");
 
@@ -74,7 +121,14 @@ async fn main() {
         .expect("Pairing file test failed");
 
     // Add the UDID (jitterbug spec)
-    pairing_file.udid = Some(dev.udid);
+    pairing_file.udid = Some(dev.udid.clone());
+
+    if matches.get_flag("save") {
+        u.save_pair_record(dev.device_id, &dev.udid, &pairing_file)
+            .await
+            .expect("Failed to save pairing record to usbmuxd");
+        eprintln!("Saved pairing record to usbmuxd");
+    }
 
     println!(
         "{}",
@@ -0,0 +1,93 @@
+// Jackson Coxson
+// Serve a network-paired device over a usbmuxd socket
+
+use std::{net::IpAddr, str::FromStr};
+
+use clap::{Arg, Command};
+use idevice::{
+    pairing_file::PairingFile,
+    provider::TcpProvider,
+    usbmuxd::{server::UsbmuxdServer, Connection, UsbmuxdAddr},
+};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let matches = Command::new("usbmuxd_server")
+        .about("Expose a network device to usbmuxd clients")
+        .arg(
+            Arg::new("host")
+                .long("host")
+                .value_name("HOST")
+                .help("IP address of the device")
+                .required(true),
+        )
+        .arg(
+            Arg::new("pairing_file")
+                .long("pairing-file")
+                .value_name("PATH")
+                .help("Path to the pairing file")
+                .required(true),
+        )
+        .arg(
+            Arg::new("udid")
+                .long("udid")
+                .value_name("UDID")
+                .help("UDID to report for the device (defaults to the pairing file's UDID)"),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .value_name("ADDR")
+                .help("Socket path or address to listen on (defaults to USBMUXD_SOCKET_ADDRESS)"),
+        )
+        .arg(
+            Arg::new("about")
+                .long("about")
+                .help("Show about information")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    if matches.get_flag("about") {
+        println!("usbmuxd_server - expose a network device to usbmuxd clients");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return;
+    }
+
+    let host = IpAddr::from_str(matches.get_one::<String>("host").unwrap()).expect("Invalid host");
+    let pairing_file =
+        PairingFile::read_from_file(matches.get_one::<String>("pairing_file").unwrap())
+            .expect("Unable to read pairing file");
+
+    let udid = match matches.get_one::<String>("udid") {
+        Some(u) => u.to_owned(),
+        None => pairing_file
+            .udid
+            .clone()
+            .expect("Pairing file has no UDID, pass one with --udid"),
+    };
+
+    let addr = match matches.get_one::<String>("socket") {
+        #[cfg(unix)]
+        Some(s) if !s.contains(':') => UsbmuxdAddr::UnixSocket(s.to_owned()),
+        Some(s) => UsbmuxdAddr::TcpSocket(s.parse().expect("Invalid socket address")),
+        None => UsbmuxdAddr::from_env_var().expect("Bad USBMUXD_SOCKET_ADDRESS"),
+    };
+
+    let server = UsbmuxdServer::new(pairing_file.system_buid.clone());
+    server.add_device(
+        udid,
+        Connection::Network(host),
+        Box::new(TcpProvider {
+            addr: host,
+            pairing_file,
+            label: "usbmuxd_server-jkcoxson".to_string(),
+        }),
+    );
+
+    println!("Serving on {addr:?}");
+    if let Err(e) = server.serve(&addr).await {
+        eprintln!("Server stopped: {e:?}");
+    }
+}